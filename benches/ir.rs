@@ -0,0 +1,46 @@
+#[macro_use]
+extern crate criterion;
+extern crate r9cc;
+
+use criterion::{black_box, Criterion};
+use r9cc::gen_ir::gen_ir;
+use r9cc::parse::parse;
+use r9cc::preprocess::Preprocessor;
+use r9cc::sema::sema;
+use r9cc::token::tokenize_str;
+
+// A program with many functions that each have locals, a loop, a
+// branch, a call and some arithmetic, so that every front-end pass has
+// real work to do.
+fn source(n: usize) -> String {
+    let mut s = String::from("int f0(int x) { return x; }\n");
+    for i in 1..n {
+        s.push_str(&format!(
+            "int f{i}(int x) {{
+               int a[4]; int s = 0; int j;
+               for (j = 0; j < 4; j++) a[j] = x * j + {i};
+               for (j = 0; j < 4; j++) {{
+                 if (a[j] % 2 == 0) s = s + a[j]; else s = s - (a[j] >> 1);
+               }}
+               return s != x ? f{prev}(s) : s;
+             }}\n",
+            i = i,
+            prev = i - 1
+        ));
+    }
+    s
+}
+
+fn tokenize_to_ir(c: &mut Criterion) {
+    let src = source(200);
+    c.bench_function("tokenize to ir, 200 functions", move |b| {
+        b.iter(|| {
+            let tokens = tokenize_str("bench.c".into(), src.clone(), &mut Preprocessor::new());
+            let (nodes, _) = sema(parse(&tokens));
+            black_box(gen_ir(nodes))
+        })
+    });
+}
+
+criterion_group!(benches, tokenize_to_ir);
+criterion_main!(benches);
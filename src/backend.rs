@@ -0,0 +1,18 @@
+use gen_ir::Function;
+use Var;
+
+use alloc::string::String;
+
+// A code generator. The driver hands the final IR to a backend and
+// writes out whatever text it returns, so a new target only needs to
+// implement this trait.
+pub trait Backend {
+    fn emit(&self, globals: &[Var], fns: &[Function]) -> String;
+
+    // Where emit() places each function and global, as a text table.
+    // Backends that place nothing, like the IR dumper, have nothing to
+    // report.
+    fn symbol_map(&self, _globals: &[Var], _fns: &[Function]) -> Option<String> {
+        None
+    }
+}
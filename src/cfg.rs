@@ -0,0 +1,183 @@
+use gen_ir::{Function, IROp};
+
+use std::collections::HashMap;
+
+// Control flow graph of a function.
+//
+// A basic block starts at the beginning of the function, at every label
+// and after every terminator. Blocks are numbered in the order they
+// appear in the code, so block 0 is the entry.
+
+pub use gen_ir::BlockId;
+
+#[derive(Debug)]
+pub struct BasicBlock {
+    pub start: usize, // Index of the first instruction
+    pub end: usize,   // One past the last instruction
+    pub succ: Vec<BlockId>,
+    pub pred: Vec<BlockId>,
+}
+
+pub fn basic_blocks(f: &Function) -> Vec<BasicBlock> {
+    let code = &f.ir;
+    if code.is_empty() {
+        return vec![];
+    }
+
+    let mut starts = vec![0];
+    for (i, ir) in code.iter().enumerate() {
+        if ir.op == IROp::Label && *starts.last().unwrap() != i {
+            starts.push(i);
+        } else if ir.op.is_terminator() && i + 1 < code.len() {
+            starts.push(i + 1);
+        }
+    }
+
+    let mut blocks: Vec<BasicBlock> = starts
+        .iter()
+        .enumerate()
+        .map(|(id, &start)| BasicBlock {
+            start,
+            end: starts.get(id + 1).cloned().unwrap_or(code.len()),
+            succ: vec![],
+            pred: vec![],
+        })
+        .collect();
+
+    let labels: HashMap<usize, BlockId> = blocks
+        .iter()
+        .enumerate()
+        .filter(|&(_, b)| code[b.start].op == IROp::Label)
+        .map(|(id, b)| (code[b.start].lhs.unwrap(), id))
+        .collect();
+
+    let n = blocks.len();
+    for id in 0..n {
+        let last = &code[blocks[id].end - 1];
+        let next = if id + 1 < n { Some(id + 1) } else { None };
+        let succ: Vec<BlockId> = match last.op {
+            IROp::Jmp => vec![labels[&last.lhs.unwrap()]],
+            IROp::If | IROp::Unless | IROp::IfEq(_) => next
+                .into_iter()
+                .chain(Some(labels[&last.rhs.unwrap()]))
+                .collect(),
+            IROp::Return | IROp::Trap => vec![],
+            _ => next.into_iter().collect(),
+        };
+        for &s in &succ {
+            if !blocks[s].pred.contains(&id) {
+                blocks[s].pred.push(id);
+            }
+        }
+        blocks[id].succ = succ;
+    }
+    blocks
+}
+
+fn reverse_postorder(blocks: &[BasicBlock]) -> Vec<BlockId> {
+    let mut visited = vec![false; blocks.len()];
+    let mut order = vec![];
+    let mut stack = vec![(0, 0)];
+    visited[0] = true;
+    while let Some((id, i)) = stack.pop() {
+        if i < blocks[id].succ.len() {
+            stack.push((id, i + 1));
+            let s = blocks[id].succ[i];
+            if !visited[s] {
+                visited[s] = true;
+                stack.push((s, 0));
+            }
+        } else {
+            order.push(id);
+        }
+    }
+    order.reverse();
+    order
+}
+
+// Computes the immediate dominator of each basic block, using the
+// iterative algorithm by Cooper, Harvey and Kennedy. The entry block and
+// blocks that cannot be reached from it have no immediate dominator, so
+// they are not in the map.
+pub fn dominators(f: &Function) -> HashMap<BlockId, BlockId> {
+    let blocks = basic_blocks(f);
+    let mut idom = HashMap::new();
+    if blocks.is_empty() {
+        return idom;
+    }
+
+    let rpo = reverse_postorder(&blocks);
+    let mut order = HashMap::new();
+    for (i, &id) in rpo.iter().enumerate() {
+        order.insert(id, i);
+    }
+
+    let intersect = |idom: &HashMap<BlockId, BlockId>, mut a: BlockId, mut b: BlockId| {
+        while a != b {
+            while order[&a] > order[&b] {
+                a = idom[&a];
+            }
+            while order[&b] > order[&a] {
+                b = idom[&b];
+            }
+        }
+        a
+    };
+
+    idom.insert(0, 0);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &id in &rpo[1..] {
+            let mut new_idom = None;
+            for &p in &blocks[id].pred {
+                if !idom.contains_key(&p) {
+                    continue;
+                }
+                new_idom = match new_idom {
+                    None => Some(p),
+                    Some(d) => Some(intersect(&idom, p, d)),
+                };
+            }
+            let new_idom = new_idom.unwrap();
+            if idom.get(&id) != Some(&new_idom) {
+                idom.insert(id, new_idom);
+                changed = true;
+            }
+        }
+    }
+
+    idom.remove(&0);
+    idom
+}
+
+// Computes the dominance frontier of each basic block: the blocks where
+// its dominance ends, that is, the blocks it does not strictly dominate
+// but which have a predecessor it dominates. This is where SSA
+// construction puts PHIs. Blocks that cannot be reached are left out.
+pub fn dominance_frontiers(f: &Function) -> HashMap<BlockId, Vec<BlockId>> {
+    let blocks = basic_blocks(f);
+    let idom = dominators(f);
+    let reachable = |id: &BlockId| *id == 0 || idom.contains_key(id);
+
+    let mut df: HashMap<BlockId, Vec<BlockId>> = HashMap::new();
+    for (id, b) in blocks.iter().enumerate() {
+        if b.pred.len() < 2 || !reachable(&id) {
+            continue;
+        }
+        for &p in b.pred.iter().filter(|p| reachable(p)) {
+            let mut runner = p;
+            while Some(&runner) != idom.get(&id) {
+                let frontier = df.entry(runner).or_default();
+                if !frontier.contains(&id) {
+                    frontier.push(id);
+                }
+                match idom.get(&runner) {
+                    Some(&d) => runner = d,
+                    None => break,
+                }
+            }
+        }
+    }
+    df
+}
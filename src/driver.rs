@@ -0,0 +1,270 @@
+use backend::Backend;
+use gen_ir::gen_ir;
+use gen_wasm::Wasm;
+use gen_x86::X86;
+use irdump::{dump_ir, format_ir};
+use llvmdump::dump_llvm;
+use opt::{optimize, schedule};
+use parse::parse;
+use preprocess::Preprocessor;
+use regalloc::alloc_regs;
+use sema::{self, sema};
+use target::{set_target, Target};
+use token::{
+    catch_error, promote_warnings, set_nested_comments, take_diagnostics, tokenize, tokenize_str,
+    Token,
+};
+use validate::validate;
+use {Diagnostic, Severity};
+
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// How far compile() runs, and what it returns.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    Tokens, // One token per line
+    Ast,    // One top-level node per line, as parsed
+    Ir,     // The optimized IR, as -dump-ir1 prints it
+    Asm,    // The backend's output, x86-64 assembly by default
+}
+
+// Settings that change how compilation behaves rather than what it
+// produces.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub warnings_as_errors: bool,
+    pub canonical_regs: bool, // See Function::canonicalize_regs()
+    pub target: Target,
+    pub max_name_length: Option<usize>, // sema::DEFAULT_MAX_NAME_LEN if None
+    pub nested_comments: bool,
+    pub max_errors: Option<usize>, // check() reports every error if None
+    pub cc: Option<String>,        // Assembler and linker; "cc" if None
+    pub wasm: bool,                // Emit WebAssembly text instead of x86-64
+    pub canary: bool,              // See X86::canary
+    pub verbose_asm: bool,         // See X86::verbose
+    // Print the IR to stderr as it is before scheduling, or after
+    // register allocation, or as LLVM IR.
+    pub dump_ir1: bool,
+    pub dump_ir2: bool,
+    pub dump_llvm: bool,
+    pub number_ir: bool, // Number the instructions in IR dumps
+}
+
+// The result of compiling up to the backend.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Output {
+    pub text: String,
+    pub map: Option<String>, // See Backend::symbol_map()
+}
+
+impl Output {
+    fn text(text: String) -> Self {
+        Output { text, map: None }
+    }
+}
+
+// The external assembler or linker could not be run, or failed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolchainError {
+    pub message: String,
+}
+
+impl fmt::Display for ToolchainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+fn run(tokens: Vec<Token>, stage: Stage, opts: &Options) -> Output {
+    if stage == Stage::Tokens {
+        return Output::text(tokens.iter().map(|t| format!("{:?}\n", t.ty)).collect());
+    }
+
+    set_target(opts.target);
+    let nodes = parse(&tokens);
+    if stage == Stage::Ast {
+        return Output::text(nodes.iter().map(|n| format!("{:?}\n", n)).collect());
+    }
+
+    sema::set_max_name_length(opts.max_name_length.unwrap_or(sema::DEFAULT_MAX_NAME_LEN));
+    let (nodes, globals) = sema(nodes);
+    if opts.warnings_as_errors {
+        promote_warnings();
+    }
+    let mut fns = gen_ir(nodes);
+    optimize(&mut fns);
+    if opts.canonical_regs {
+        for f in &mut fns {
+            f.canonicalize_regs();
+        }
+    }
+    if opts.dump_ir1 {
+        dump_ir(&fns, opts.number_ir);
+    }
+    if opts.dump_llvm {
+        dump_llvm(&fns);
+    }
+    if stage == Stage::Ir {
+        return Output::text(format_ir(&fns));
+    }
+
+    schedule(&mut fns);
+    for f in &fns {
+        if let Err(err) = validate(f) {
+            panic!("internal error: {}", err);
+        }
+    }
+    alloc_regs(&mut fns);
+    if opts.dump_ir2 {
+        dump_ir(&fns, opts.number_ir);
+    }
+
+    let backend: Box<dyn Backend> = if opts.wasm {
+        Box::new(Wasm)
+    } else {
+        Box::new(X86 {
+            canary: opts.canary,
+            verbose: opts.verbose_asm,
+            ..X86::new()
+        })
+    };
+    Output {
+        text: backend.emit(&globals, &fns),
+        map: backend.symbol_map(&globals, &fns),
+    }
+}
+
+// Compiles C source code up to `stage`. This is the entry point for
+// tools that want to look at an intermediate result.
+pub fn compile(src: &str, stage: Stage) -> String {
+    compile_with(src, stage, &Options::default())
+}
+
+// Same as compile(), with non-default options.
+pub fn compile_with(src: &str, stage: Stage, opts: &Options) -> String {
+    if opts.warnings_as_errors {
+        take_diagnostics();
+    }
+    set_nested_comments(opts.nested_comments);
+    let tokens = tokenize_str("<input>".into(), src.into(), &mut Preprocessor::new());
+    run(tokens, stage, opts).text
+}
+
+// Analyzes C source code without generating any code, and returns the
+// errors and warnings found, in source order. Tokenizer and parser
+// errors end the analysis, but sema reports an error in every function
+// that has one.
+pub fn check(src: &str) -> Vec<Diagnostic> {
+    check_with(src, &Options::default())
+}
+
+// Same as check(), with non-default options. With `max_errors`, the
+// diagnostics stop at that many errors and end with a note saying so.
+pub fn check_with(src: &str, opts: &Options) -> Vec<Diagnostic> {
+    take_diagnostics();
+    set_target(opts.target);
+    sema::set_max_name_length(opts.max_name_length.unwrap_or(sema::DEFAULT_MAX_NAME_LEN));
+    set_nested_comments(opts.nested_comments);
+    let mut diags = match catch_error(|| {
+        let tokens = tokenize_str("<input>".into(), src.into(), &mut Preprocessor::new());
+        parse(&tokens)
+    }) {
+        Ok(nodes) => sema::check(nodes),
+        Err(err) => vec![err],
+    };
+    diags.extend(take_diagnostics());
+    diags.sort_by_key(|d| d.span.as_ref().map(|s| s.start));
+    if let Some(max) = opts.max_errors {
+        let mut errors = 0;
+        let end = diags.iter().position(|d| {
+            if d.severity == Severity::Error {
+                errors += 1;
+            }
+            errors > max
+        });
+        if let Some(end) = end {
+            diags.truncate(end);
+            diags.push(Diagnostic {
+                severity: Severity::Note,
+                message: format!("too many errors; stopping after {}", max),
+                span: None,
+                suggestion: None,
+            });
+        }
+    }
+    diags
+}
+
+// Compiles a C source file to x86-64 assembly. The file goes through
+// the preprocessor, so `#include` and `#define` work as usual.
+pub fn compile_file(path: &Path) -> String {
+    compile_file_with(path, &Options::default()).text
+}
+
+// Same as compile_file(), with non-default options. This is what the
+// command line runs.
+pub fn compile_file_with(path: &Path, opts: &Options) -> Output {
+    if opts.warnings_as_errors {
+        take_diagnostics();
+    }
+    set_nested_comments(opts.nested_comments);
+    let tokens = tokenize(
+        path.to_string_lossy().into_owned(),
+        &mut Preprocessor::new(),
+    );
+    run(tokens, Stage::Asm, opts)
+}
+
+// Compiles C source code and hands the assembly to the C compiler
+// driver. If `out_path` ends in `.o`, the result is an object file;
+// otherwise it is a statically linked executable.
+pub fn compile_to_object(src: &str, out_path: &Path) -> Result<(), ToolchainError> {
+    compile_to_object_with(src, out_path, &Options::default())
+}
+
+// Same as compile_to_object(), with non-default options.
+pub fn compile_to_object_with(
+    src: &str,
+    out_path: &Path,
+    opts: &Options,
+) -> Result<(), ToolchainError> {
+    let asm = compile_with(src, Stage::Asm, opts);
+    let cc = opts.cc.as_ref().map_or("cc", |s| s.as_str());
+    let mut cmd = Command::new(cc);
+    if out_path.extension().is_some_and(|ext| ext == "o") {
+        cmd.arg("-c");
+    } else {
+        cmd.arg("-static");
+    }
+    // The assembly is read from stdin, so no temporary file is needed.
+    cmd.args(["-x", "assembler", "-", "-o"])
+        .arg(out_path)
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let error = |message: String| ToolchainError { message };
+    let mut child = cmd
+        .spawn()
+        .map_err(|e| error(format!("cannot run {}: {}", cc, e)))?;
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(asm.as_bytes())
+        .map_err(|e| error(format!("cannot write to {}: {}", cc, e)))?;
+    let output = child
+        .wait_with_output()
+        .map_err(|e| error(format!("cannot run {}: {}", cc, e)))?;
+    if !output.status.success() {
+        return Err(error(format!(
+            "{} failed ({}):\n{}",
+            cc,
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+    Ok(())
+}
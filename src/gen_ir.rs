@@ -10,26 +10,23 @@
 // > Such infinite number of registers are mapped to a finite registers
 // > in a later pass.
 
-use parse::{Node, NodeType};
+use irdump::IRInfo;
+use parse::{is_minmax, is_trap, Node, NodeType};
+use util::roundup;
 use {Ctype, Scope, TokenType, Type};
 
-use std::sync::Mutex;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::mem;
 
-lazy_static! {
-    static ref NUM_REGS: Mutex<usize> = Mutex::new(0);
-    static ref NLABEL: Mutex<usize> = Mutex::new(1);
-    static ref RETURN_LABEL: Mutex<usize> = Mutex::new(0);
-    static ref RETURN_REG: Mutex<usize> = Mutex::new(0);
-    static ref BREAK_LABEL: Mutex<usize> = Mutex::new(0);
-    static ref CODE: Mutex<Vec<IR>> = Mutex::new(vec![]);
-}
-
-fn add(op: IROp, lhs: Option<usize>, rhs: Option<usize>) {
-    let ir = IR::new(op, lhs, rhs);
-    CODE.lock().unwrap().push(ir.clone());
-}
+// Arguments past this many are passed on the stack. They are stored in
+// the caller's frame as they are evaluated, so that a call does not need
+// more registers than the allocator has.
+pub const REG_ARGS: usize = 6;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum IRType {
     Noarg,
     Reg,
@@ -43,6 +40,10 @@ pub enum IRType {
     StoreArg,
     RegLabel,
     Call,
+    CMov,
+    RegRegLabel,
+    Phi,
+    Asm,
 }
 
 #[derive(Clone, Debug)]
@@ -50,18 +51,138 @@ pub struct Function {
     pub name: String,
     pub ir: Vec<IR>,
     pub stacksize: usize,
+    // Local variables and parameters as (name, offset from rbp), in the
+    // order they are declared. For a debugger; the code refers to
+    // variables by offset only.
+    pub vars: Vec<(String, usize)>,
 }
 
 impl Function {
-    fn new(name: String, ir: Vec<IR>, stacksize: usize) -> Self {
+    fn new(name: String, ir: Vec<IR>, stacksize: usize, vars: Vec<(String, usize)>) -> Self {
         Function {
             name,
             ir,
             stacksize,
+            vars,
+        }
+    }
+
+    // One past the highest register number in use.
+    pub fn reg_count(&self) -> usize {
+        self.ir
+            .iter()
+            .flat_map(|ir| {
+                let mut v = ir.uses();
+                v.extend(ir.def());
+                if ir.op == IROp::Kill {
+                    v.extend(ir.lhs);
+                }
+                v
+            })
+            .max()
+            .map_or(0, |r| r + 1)
+    }
+
+    // One past the highest label number in use.
+    pub fn label_count(&self) -> usize {
+        self.ir
+            .iter()
+            .filter(|ir| ir.op == IROp::Label)
+            .filter_map(|ir| ir.lhs)
+            .max()
+            .map_or(0, |l| l + 1)
+    }
+
+    // Returns a copy in which every register number is offset by
+    // `reg_base` and every label by `label_base`, so that its code can be
+    // merged into another function without collisions.
+    pub fn clone_with_renumbered_regs(&self, reg_base: usize, label_base: usize) -> Function {
+        let mut f = self.clone();
+        f.map_regs(|r| r + reg_base, |l| l + label_base);
+        f
+    }
+
+    // Renumbers the registers densely from 1 in the order in which they
+    // are first defined, followed by any that are only read. Register
+    // numbers otherwise depend on the order in which gen_ir happened to
+    // allocate them, so this keeps IR dumps stable across unrelated
+    // changes.
+    pub fn canonicalize_regs(&mut self) {
+        let mut order: Vec<usize> = self.ir.iter().filter_map(|ir| ir.def()).collect();
+        order.extend(self.ir.iter().flat_map(|ir| ir.uses()));
+
+        let mut map = BTreeMap::new();
+        for r in order {
+            let next = map.len() + 1;
+            map.entry(r).or_insert(next);
+        }
+        self.map_regs(|r| map[&r], |l| l);
+    }
+
+    fn map_regs<R: Fn(usize) -> usize, L: Fn(usize) -> usize>(&mut self, reg: R, label: L) {
+        use self::IRType::*;
+
+        for ir in &mut self.ir {
+            match IRInfo::from(&ir.op).ty {
+                Reg | RegImm | LabelAddr => ir.lhs = ir.lhs.map(&reg),
+                Mem | RegReg => {
+                    ir.lhs = ir.lhs.map(&reg);
+                    ir.rhs = ir.rhs.map(&reg);
+                }
+                RegLabel => {
+                    ir.lhs = ir.lhs.map(&reg);
+                    ir.rhs = ir.rhs.map(&label);
+                }
+                Label | Jmp => ir.lhs = ir.lhs.map(&label),
+                Call => {
+                    ir.lhs = ir.lhs.map(&reg);
+                    match ir.op {
+                        IROp::Call(_, ref mut args, _) => {
+                            for arg in args.iter_mut() {
+                                *arg = reg(*arg);
+                            }
+                        }
+                        IROp::CallReg(ref mut r, ref mut args, _) => {
+                            *r = reg(*r);
+                            for arg in args.iter_mut() {
+                                *arg = reg(*arg);
+                            }
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+                CMov => {
+                    ir.lhs = ir.lhs.map(&reg);
+                    ir.rhs = ir.rhs.map(&reg);
+                    if let IROp::CMov(ref mut cond) = ir.op {
+                        *cond = reg(*cond);
+                    }
+                }
+                RegRegLabel => {
+                    ir.lhs = ir.lhs.map(&reg);
+                    ir.rhs = ir.rhs.map(&label);
+                    if let IROp::IfEq(ref mut r) = ir.op {
+                        *r = reg(*r);
+                    }
+                }
+                Phi => {
+                    ir.lhs = ir.lhs.map(&reg);
+                    if let IROp::Phi(ref mut args) = ir.op {
+                        for &mut (_, ref mut r) in args.iter_mut() {
+                            *r = reg(*r);
+                        }
+                    }
+                }
+                Noarg | Imm | StoreArg | Asm => (),
+            }
         }
     }
 }
 
+// Basic blocks are numbered in the order they appear in the code. See
+// cfg.rs.
+pub type BlockId = usize;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum IROp {
     Add,
@@ -71,34 +192,62 @@ pub enum IROp {
     Mul,
     MulImm,
     Div,
+    UDiv,
     Imm,
     Bprel,
     Mov,
     Return,
-    Call(String, usize, [usize; 6]),
+    // Arguments in registers, then the frame offsets of the arguments
+    // that did not fit in registers.
+    Call(String, Vec<usize>, Vec<usize>),
+    CallReg(usize, Vec<usize>, Vec<usize>), // Like Call, but to the address in the first register
     Label,
     LabelAddr(String),
     EQ,
     NE,
     LE,
     LT,
+    ULE, // LE on unsigned values
+    ULT, // LT on unsigned values
     AND,
     OR,
     XOR,
     SHL,
     SHR,
+    SAR,
     Mod,
+    UMod,
     Neg,
+    Trunc,
+    Zext,
+    CMov(usize), // lhs = rhs if the register in the op is not 0
     Jmp,
     If,
     Unless,
+    IfEq(usize),                // Jump to rhs if lhs equals the register in the op
+    Phi(Vec<(BlockId, usize)>), // lhs = the register for the block control came from
     Load(u8),
     Store(u8),
+    VolatileLoad(u8),  // Like Load and Store, but never removed, merged or
+    VolatileStore(u8), // reordered with other memory accesses
     StoreArg(u8),
+    Asm(String), // Inline assembly, passed through to the output as is
+    Trap,        // Abort the program
     Kill,
     Nop,
 }
 
+impl IROp {
+    // Whether this instruction ends a basic block. A conditional jump
+    // does too, even though control may fall through to the next one.
+    pub fn is_terminator(&self) -> bool {
+        matches!(
+            self,
+            IROp::Jmp | IROp::Return | IROp::Trap | IROp::If | IROp::Unless | IROp::IfEq(_)
+        )
+    }
+}
+
 impl From<NodeType> for IROp {
     fn from(node_type: NodeType) -> Self {
         match node_type {
@@ -132,125 +281,168 @@ impl IR {
     fn new(op: IROp, lhs: Option<usize>, rhs: Option<usize>) -> Self {
         Self { op, lhs, rhs }
     }
-}
 
-fn kill(r: Option<usize>) {
-    add(IROp::Kill, r, None);
-}
+    // Registers read by this instruction.
+    pub fn uses(&self) -> Vec<usize> {
+        use self::IROp::*;
+        match self.op {
+            Add | Sub | Mul | Div | UDiv | Mod | UMod | EQ | NE | LE | LT | ULE | ULT | AND
+            | OR | XOR | SHL | SHR | SAR | Store(_) | VolatileStore(_) => {
+                vec![self.lhs.unwrap(), self.rhs.unwrap()]
+            }
+            AddImm | SubImm | MulImm | Neg | Trunc | Zext | Return | If | Unless => {
+                vec![self.lhs.unwrap()]
+            }
+            Mov | Load(_) | VolatileLoad(_) => vec![self.rhs.unwrap()],
+            Call(_, ref args, _) => args.clone(),
+            CallReg(r, ref args, _) => {
+                let mut v = vec![r];
+                v.extend(args);
+                v
+            }
+            CMov(cond) => vec![self.lhs.unwrap(), self.rhs.unwrap(), cond],
+            IfEq(r) => vec![self.lhs.unwrap(), r],
+            Phi(ref args) => args.iter().map(|&(_, r)| r).collect(),
+            Imm | Bprel | Label | LabelAddr(_) | Jmp | StoreArg(_) | Asm(_) | Trap | Kill | Nop => {
+                vec![]
+            }
+        }
+    }
 
-fn label(x: Option<usize>) {
-    add(IROp::Label, x, None);
+    // Register written by this instruction.
+    pub fn def(&self) -> Option<usize> {
+        use self::IROp::*;
+        match self.op {
+            Add
+            | Sub
+            | Mul
+            | Div
+            | UDiv
+            | Mod
+            | UMod
+            | EQ
+            | NE
+            | LE
+            | LT
+            | ULE
+            | ULT
+            | AND
+            | OR
+            | XOR
+            | SHL
+            | SHR
+            | SAR
+            | AddImm
+            | SubImm
+            | MulImm
+            | Neg
+            | Trunc
+            | Zext
+            | Mov
+            | Load(_)
+            | VolatileLoad(_)
+            | Imm
+            | Bprel
+            | LabelAddr(_)
+            | Call(_, _, _)
+            | CallReg(_, _, _)
+            | CMov(_)
+            | Phi(_) => self.lhs,
+            Return | If | Unless | IfEq(_) | Store(_) | VolatileStore(_) | Label | Jmp
+            | StoreArg(_) | Asm(_) | Trap | Kill | Nop => None,
+        }
+    }
 }
 
-fn jmp(x: Option<usize>) {
-    add(IROp::Jmp, x, None);
+fn get_inc_scale(ty: &Type) -> i32 {
+    match ty.ty {
+        Ctype::Ptr(ref ptr_to) => ptr_to.size as i32,
+        _ => 1,
+    }
 }
 
-fn load(ty: &Type, dst: Option<usize>, src: Option<usize>) {
-    add(IROp::Load(ty.size as u8), dst, src);
+// Whether evaluating the expression can neither have a side effect nor
+// trap, so that it may be evaluated even if its value is not needed.
+// Reading a volatile object counts as a side effect.
+fn is_pure(node: &Node) -> bool {
+    use self::TokenType::*;
+    match node.op {
+        NodeType::Num(_) => true,
+        NodeType::Lvar(_) | NodeType::Gvar(_, _, _) => !node.ty.volatile,
+        NodeType::Addr(ref expr) => matches!(expr.op, NodeType::Lvar(_) | NodeType::Gvar(_, _, _)),
+        NodeType::Neg(ref expr) | NodeType::Exclamation(ref expr) | NodeType::Cast(ref expr) => {
+            is_pure(expr)
+        }
+        NodeType::BinOp(
+            Plus | Minus | Mul | And | VerticalBar | Hat | SHL | SHR | EQ | NE | LE
+            | LeftAngleBracket,
+            ref lhs,
+            ref rhs,
+        ) => is_pure(lhs) && is_pure(rhs),
+        _ => false,
+    }
 }
 
-fn store(ty: &Type, dst: Option<usize>, src: Option<usize>) {
-    add(IROp::Store(ty.size as u8), dst, src);
+fn is_zero(node: &Node) -> bool {
+    matches!(node.op, NodeType::Num(0))
 }
 
-fn store_arg(ty: &Type, bpoff: Option<usize>, argreg: Option<usize>) {
-    add(IROp::StoreArg(ty.size as u8), bpoff, argreg);
+fn is_unsigned(ty: &Type) -> bool {
+    matches!(ty.ty, Ctype::Uint | Ctype::Ulong)
 }
 
-// Quoted from 9cc
-// > In C, all expressions that can be written on the left-hand side of
-// > the '=' operator must have an address in memory. In other words, if
-// > you can apply the '&' operator to take an address of some
-// > expression E, you can assign E to a new value.
-//
-// > Other expressions, such as `1+2`, cannot be written on the lhs of
-// > '=', since they are just temporary values that don't have an address.
-//
-// > The stuff that can be written on the lhs of '=' is called lvalue.
-// > Other values are called rvalue. An lvalue is essentially an address.
-//
-// > When lvalues appear on the rvalue context, they are converted to
-// > rvalues by loading their values from their addresses. You can think
-// > '&' as an operator that suppresses such automatic lvalue-to-rvalue
-// > conversion.
-//
-// > This function evaluates a given node as an lvalue.
-
-fn gen_lval(node: Box<Node>) -> Option<usize> {
-    match node.op {
-        NodeType::Deref(expr) => gen_expr(expr),
-        NodeType::Dot(ref expr, _, ref offset) => {
-            let r = gen_lval(expr.clone());
-            add(IROp::AddImm, r, Some(*offset));
-            r
-        }
-        NodeType::Lvar(Scope::Local(offset)) => {
-            let r = Some(*NUM_REGS.lock().unwrap());
-            *NUM_REGS.lock().unwrap() += 1;
-            add(IROp::Bprel, r, Some(offset));
-            r
-        }
-        NodeType::Gvar(name, _, _) => {
-            let r = Some(*NUM_REGS.lock().unwrap());
-            *NUM_REGS.lock().unwrap() += 1;
-            add(IROp::LabelAddr(name), r, None);
-            r
-        }
-        _ => unreachable!(),
+// Division and right shift depend on the signedness of the operands.
+fn div_op(ty: &Type) -> IROp {
+    if is_unsigned(ty) {
+        IROp::UDiv
+    } else {
+        IROp::Div
     }
 }
 
-fn gen_binop(ty: IROp, lhs: Box<Node>, rhs: Box<Node>) -> Option<usize> {
-    let r1 = gen_expr(lhs);
-    let r2 = gen_expr(rhs);
-    add(ty, r1, r2);
-    kill(r2);
-    r1
+// So do comparisons. `ty` is the type both operands were converted to.
+fn lt_op(ty: &Type) -> IROp {
+    if is_unsigned(ty) {
+        IROp::ULT
+    } else {
+        IROp::LT
+    }
 }
 
-fn get_inc_scale(ty: &Type) -> usize {
-    match ty.ty {
-        Ctype::Ptr(ref ptr_to) => ptr_to.size,
-        _ => 1,
+fn le_op(ty: &Type) -> IROp {
+    if is_unsigned(ty) {
+        IROp::ULE
+    } else {
+        IROp::LE
     }
 }
 
-fn gen_pre_inc(ty: &Type, expr: Box<Node>, num: i32) -> i32 {
-    let addr = gen_lval(expr);
-    let val = *NUM_REGS.lock().unwrap();
-    *NUM_REGS.lock().unwrap() += 1;
-    load(ty, Some(val), addr);
-    add(
-        IROp::AddImm,
-        Some(val),
-        Some(num as usize * get_inc_scale(ty)),
-    );
-    store(ty, addr, Some(val));
-    kill(addr);
-    val as i32
+fn mod_op(ty: &Type) -> IROp {
+    if is_unsigned(ty) {
+        IROp::UMod
+    } else {
+        IROp::Mod
+    }
 }
 
-fn gen_post_inc(ty: &Type, expr: Box<Node>, num: i32) -> i32 {
-    let val = gen_pre_inc(ty, expr, num);
-    add(
-        IROp::SubImm,
-        Some(val as usize),
-        Some(num as usize * get_inc_scale(ty)),
-    );
-    val as i32
+fn shr_op(ty: &Type) -> IROp {
+    if is_unsigned(ty) {
+        IROp::SHR
+    } else {
+        IROp::SAR
+    }
 }
 
-fn to_assign_op(op: &TokenType) -> IROp {
+fn to_assign_op(op: &TokenType, ty: &Type) -> IROp {
     use self::TokenType::*;
     match op {
         MulEQ => IROp::Mul,
-        DivEQ => IROp::Div,
-        ModEQ => IROp::Mod,
+        DivEQ => div_op(ty),
+        ModEQ => mod_op(ty),
         AddEQ => IROp::Add,
         SubEQ => IROp::Sub,
         ShlEQ => IROp::SHL,
-        ShrEQ => IROp::SHR,
+        ShrEQ => shr_op(ty),
         BitandEQ => IROp::AND,
         XorEQ => IROp::XOR,
         BitorEQ => IROp::OR,
@@ -258,309 +450,800 @@ fn to_assign_op(op: &TokenType) -> IROp {
     }
 }
 
-fn gen_assign_op(op: &TokenType, ty: &Type, lhs: Box<Node>, rhs: Box<Node>) -> Option<usize> {
-    let src = gen_expr(rhs);
-    let dst = gen_lval(lhs);
-    let val = Some(*NUM_REGS.lock().unwrap());
-    *NUM_REGS.lock().unwrap() += 1;
-
-    load(ty, val, dst);
-    add(to_assign_op(op), val, src);
-    kill(src);
-    store(ty, dst, val);
-    kill(dst);
-    val
+// Where `break` and `continue` jump to. `continue` skips switches and
+// goes to the innermost loop. The continue label is only placed if a
+// `continue` jumps to it.
+enum JumpTarget {
+    Loop {
+        brk: usize,
+        cont: usize,
+        continued: bool,
+    },
+    Switch {
+        brk: usize,
+    },
 }
 
-fn gen_expr(node: Box<Node>) -> Option<usize> {
-    let node = *node;
+// The values of the `case` labels directly in a switch body, in order.
+// None stands for `default`.
+fn collect_cases(node: &Node, cases: &mut Vec<Option<i32>>) {
     match node.op {
-        NodeType::Num(val) => {
-            let r = Some(*NUM_REGS.lock().unwrap());
-            *NUM_REGS.lock().unwrap() += 1;
-            add(IROp::Imm, r, Some(val as usize));
-            r
+        NodeType::Case(val, ref body) => {
+            cases.push(Some(val));
+            collect_cases(body, cases);
         }
-        NodeType::Lvar(_) | NodeType::Dot(_, _, _) | NodeType::Gvar(_, _, _) => {
-            let r = gen_lval(Box::new(node.clone()));
-            load(&node.ty, r, r);
-            r
+        NodeType::Default(ref body) => {
+            cases.push(None);
+            collect_cases(body, cases);
         }
-        NodeType::Call(name, args) => {
-            let mut args_ir: [usize; 6] = [0; 6];
-            for i in 0..args.len() {
-                args_ir[i] = gen_expr(Box::new(args[i].clone())).unwrap();
+        NodeType::If(_, ref then, ref els) => {
+            collect_cases(then, cases);
+            if let Some(ref els) = els {
+                collect_cases(els, cases);
             }
+        }
+        NodeType::For(_, _, _, ref body) | NodeType::DoWhile(ref body, _) => {
+            collect_cases(body, cases)
+        }
+        NodeType::CompStmt(ref stmts) | NodeType::VecStmt(ref stmts) => {
+            for stmt in stmts {
+                collect_cases(stmt, cases);
+            }
+        }
+        _ => (),
+    }
+}
+
+struct Context {
+    num_regs: usize,
+    nlabel: usize,
+    return_label: Option<usize>, // Set inside a statement expression
+    return_reg: usize,
+    targets: Vec<JumpTarget>,
+    // Labels of the cases of the innermost switch not generated yet,
+    // the next one last.
+    case_labels: Vec<usize>,
+    code: Vec<IR>,
+    vars: Vec<(String, usize)>,
+    stacksize: usize,
+}
+
+impl Context {
+    fn new() -> Self {
+        Context {
+            num_regs: 0,
+            nlabel: 0,
+            return_label: None,
+            return_reg: 0,
+            targets: vec![],
+            case_labels: vec![],
+            code: vec![],
+            vars: vec![],
+            stacksize: 0,
+        }
+    }
+
+    fn new_reg(&mut self) -> usize {
+        let r = self.num_regs;
+        self.num_regs += 1;
+        r
+    }
 
-            let r = Some(*NUM_REGS.lock().unwrap());
-            *NUM_REGS.lock().unwrap() += 1;
+    fn new_label(&mut self) -> usize {
+        let x = self.nlabel;
+        self.nlabel += 1;
+        x
+    }
+
+    fn add(&mut self, op: IROp, lhs: Option<usize>, rhs: Option<usize>) {
+        let ir = IR::new(op, lhs, rhs);
+        self.code.push(ir);
+    }
 
-            add(IROp::Call(name, args.len(), args_ir), r, None);
+    // Ends the innermost loop's body. Pops the loop off the jump targets
+    // and places its continue label, if a `continue` used it.
+    fn continue_label(&mut self) {
+        if let Some(JumpTarget::Loop {
+            cont,
+            continued: true,
+            ..
+        }) = self.targets.pop()
+        {
+            self.label(Some(cont));
+        }
+    }
+
+    fn kill(&mut self, r: Option<usize>) {
+        self.add(IROp::Kill, r, None);
+    }
 
-            for arg in args_ir.iter().take(args.len()) {
-                kill(Some(*arg));
+    fn label(&mut self, x: Option<usize>) {
+        self.add(IROp::Label, x, None);
+    }
+
+    fn jmp(&mut self, x: Option<usize>) {
+        self.add(IROp::Jmp, x, None);
+    }
+
+    fn load(&mut self, ty: &Type, dst: Option<usize>, src: Option<usize>) {
+        if ty.in_memory() {
+            if dst != src {
+                self.add(IROp::Mov, dst, src);
             }
-            r
+            return;
         }
-        NodeType::Addr(expr) => gen_lval(expr),
-        NodeType::Deref(expr) => {
-            let r = gen_expr(expr);
-            load(&node.ty, r, r);
-            r
+        if ty.volatile {
+            self.add(IROp::VolatileLoad(ty.size as u8), dst, src);
+        } else {
+            self.add(IROp::Load(ty.size as u8), dst, src);
         }
-        NodeType::StmtExpr(body) => {
-            let orig_label = *RETURN_LABEL.lock().unwrap();
-            let orig_reg = *RETURN_REG.lock().unwrap();
-            *RETURN_LABEL.lock().unwrap() = *NLABEL.lock().unwrap();
-            *NLABEL.lock().unwrap() += 1;
-            let r = *NUM_REGS.lock().unwrap();
-            *NUM_REGS.lock().unwrap() += 1;
-            *RETURN_REG.lock().unwrap() = r;
-
-            gen_stmt(*body);
-            label(Some(*RETURN_LABEL.lock().unwrap()));
-
-            *RETURN_LABEL.lock().unwrap() = orig_label;
-            *RETURN_REG.lock().unwrap() = orig_reg;
-            Some(r)
+        self.wrap(ty, dst);
+    }
+
+    // An unsigned int is kept zero-extended in its register, so the
+    // result of anything that may leave the upper half set is cut back
+    // to 32 bits.
+    fn wrap(&mut self, ty: &Type, r: Option<usize>) {
+        if is_unsigned(ty) && ty.size == 4 {
+            self.add(IROp::Zext, r, Some(4));
         }
-        NodeType::BinOp(op, lhs, rhs) => {
-            use self::TokenType::*;
-            match op {
-                Equal => {
-                    let rhs = gen_expr(rhs);
-                    let lhs = gen_lval(lhs);
-                    store(&node.ty, lhs, rhs);
-                    kill(lhs);
-                    rhs
-                }
-                Plus => gen_binop(IROp::Add, lhs, rhs),
-                Minus => gen_binop(IROp::Sub, lhs, rhs),
-                Logand => {
-                    let x = Some(*NLABEL.lock().unwrap());
-                    *NLABEL.lock().unwrap() += 1;
-
-                    let r1 = gen_expr(lhs);
-                    add(IROp::Unless, r1, x);
-                    let r2 = gen_expr(rhs);
-                    add(IROp::Mov, r1, r2);
-                    kill(r2);
-                    add(IROp::Unless, r1, x);
-                    add(IROp::Imm, r1, Some(1));
-                    label(x);
-                    r1
-                }
-                Logor => {
-                    let x = Some(*NLABEL.lock().unwrap());
-                    *NLABEL.lock().unwrap() += 1;
-                    let y = Some(*NLABEL.lock().unwrap());
-                    *NLABEL.lock().unwrap() += 1;
-
-                    let r1 = gen_expr(lhs);
-                    add(IROp::Unless, r1, x);
-                    add(IROp::Imm, r1, Some(1));
-                    jmp(y);
-                    label(x);
-
-                    let r2 = gen_expr(rhs);
-                    add(IROp::Mov, r1, r2);
-                    kill(r2);
-                    add(IROp::Unless, r1, y);
-                    add(IROp::Imm, r1, Some(1));
-                    label(y);
-                    r1
-                }
-                MulEQ | DivEQ | ModEQ | AddEQ | SubEQ | ShlEQ | ShrEQ | BitandEQ | XorEQ
-                | BitorEQ => gen_assign_op(&op, &node.ty, lhs, rhs),
-                EQ => gen_binop(IROp::EQ, lhs, rhs),
-                NE => gen_binop(IROp::NE, lhs, rhs),
-                LE => gen_binop(IROp::LE, lhs, rhs),
-                And => gen_binop(IROp::AND, lhs, rhs),
-                VerticalBar => gen_binop(IROp::OR, lhs, rhs),
-                Hat => gen_binop(IROp::XOR, lhs, rhs),
-                SHL => gen_binop(IROp::SHL, lhs, rhs),
-                SHR => gen_binop(IROp::SHR, lhs, rhs),
-                Mod => gen_binop(IROp::Mod, lhs, rhs),
-                Comma => {
-                    kill(gen_expr(lhs));
-                    gen_expr(rhs)
-                }
-                _ => gen_binop(IROp::from(op), lhs, rhs),
-            }
+    }
+
+    fn store(&mut self, ty: &Type, dst: Option<usize>, src: Option<usize>) {
+        if ty.in_memory() {
+            self.copy(ty.size, dst, src);
+            return;
         }
-        NodeType::Neg(expr) => {
-            let r = gen_expr(expr);
-            add(IROp::Neg, r, None);
-            r
+        if ty.volatile {
+            self.add(IROp::VolatileStore(ty.size as u8), dst, src);
+        } else {
+            self.add(IROp::Store(ty.size as u8), dst, src);
         }
-        NodeType::PostInc(expr) => Some(gen_post_inc(&node.ty, expr, 1) as usize),
-        NodeType::PostDec(expr) => Some(gen_post_inc(&node.ty, expr, -1) as usize),
-        NodeType::Ternary(cond, then, els) => {
-            //      cond then els  then
-            // return 1 ? 3 : 5; => 3
-            let x = Some(*NLABEL.lock().unwrap());
-            *NLABEL.lock().unwrap() += 1;
-            let y = Some(*NLABEL.lock().unwrap());
-            *NLABEL.lock().unwrap() += 1;
-            let r = gen_expr(cond);
-
-            add(IROp::Unless, r, x);
-            let r2 = gen_expr(then);
-            add(IROp::Mov, r, r2);
-            kill(r2);
-            jmp(y);
-
-            label(x);
-            let r3 = gen_expr(els);
-            add(IROp::Mov, r, r3);
-            kill(r3);
-            label(y);
-            r
+    }
+
+    // Copies `size` bytes from the address in `src` to the one in `dst`.
+    fn copy(&mut self, size: usize, dst: Option<usize>, src: Option<usize>) {
+        let mut off = 0;
+        while off < size {
+            let n = if size - off >= 8 {
+                8
+            } else if size - off >= 4 {
+                4
+            } else {
+                1
+            };
+            let from = Some(self.new_reg());
+            self.add(IROp::Mov, from, src);
+            if off != 0 {
+                self.add(IROp::AddImm, from, Some(off));
+            }
+            let val = Some(self.new_reg());
+            self.add(IROp::Load(n as u8), val, from);
+            self.kill(from);
+            let to = Some(self.new_reg());
+            self.add(IROp::Mov, to, dst);
+            if off != 0 {
+                self.add(IROp::AddImm, to, Some(off));
+            }
+            self.add(IROp::Store(n as u8), to, val);
+            self.kill(to);
+            self.kill(val);
+            off += n;
         }
-        NodeType::Exclamation(expr) => {
-            let lhs = gen_expr(expr);
-            let rhs = Some(*NUM_REGS.lock().unwrap());
-            *NUM_REGS.lock().unwrap() += 1;
-            add(IROp::Imm, rhs, Some(0));
-            add(IROp::EQ, lhs, rhs);
-            kill(rhs);
-            lhs
+    }
+
+    fn store_arg(&mut self, ty: &Type, bpoff: Option<usize>, argreg: Option<usize>) {
+        self.add(IROp::StoreArg(ty.size as u8), bpoff, argreg);
+    }
+
+    // Evaluates call arguments. The first REG_ARGS end up in registers,
+    // and the rest in new 8-byte slots at the bottom of the frame. The
+    // latter are evaluated first, while no argument register is live.
+    fn gen_args(&mut self, mut args: Vec<Node>) -> (Vec<usize>, Vec<usize>) {
+        let rest = args.split_off(args.len().min(REG_ARGS));
+        let mut slots = vec![];
+        for arg in rest {
+            let r = self.gen_expr(Box::new(arg));
+            self.stacksize = roundup(self.stacksize, 8) + 8;
+            slots.push(self.stacksize);
+            let addr = Some(self.new_reg());
+            self.add(IROp::Bprel, addr, Some(self.stacksize));
+            self.add(IROp::Store(8), addr, r);
+            self.kill(addr);
+            self.kill(r);
         }
-        e => unreachable!("{:?}", e),
+        let regs = args
+            .into_iter()
+            .map(|arg| self.gen_expr(Box::new(arg)).unwrap())
+            .collect();
+        (regs, slots)
     }
-}
 
-fn gen_stmt(node: Node) {
-    match node.op {
-        NodeType::Null => return,
-        NodeType::Vardef(_, init_may, Scope::Local(offset)) => {
-            if let Some(init) = init_may {
-                let rhs = gen_expr(init);
-                let lhs = Some(*NUM_REGS.lock().unwrap());
-                *NUM_REGS.lock().unwrap() += 1;
-                add(IROp::Bprel, lhs, Some(offset));
-                store(&node.ty, lhs, rhs);
-                kill(lhs);
-                kill(rhs);
+    // Quoted from 9cc
+    // > In C, all expressions that can be written on the left-hand side of
+    // > the '=' operator must have an address in memory. In other words, if
+    // > you can apply the '&' operator to take an address of some
+    // > expression E, you can assign E to a new value.
+    //
+    // > Other expressions, such as `1+2`, cannot be written on the lhs of
+    // > '=', since they are just temporary values that don't have an address.
+    //
+    // > The stuff that can be written on the lhs of '=' is called lvalue.
+    // > Other values are called rvalue. An lvalue is essentially an address.
+    //
+    // > When lvalues appear on the rvalue context, they are converted to
+    // > rvalues by loading their values from their addresses. You can think
+    // > '&' as an operator that suppresses such automatic lvalue-to-rvalue
+    // > conversion.
+    //
+    // > This function evaluates a given node as an lvalue.
+
+    fn gen_lval(&mut self, node: Box<Node>) -> Option<usize> {
+        match node.op {
+            NodeType::Deref(expr) => self.gen_expr(expr),
+            NodeType::Dot(ref expr, _, ref offset) => {
+                let r = self.gen_lval(expr.clone());
+                self.add(IROp::AddImm, r, Some(*offset));
+                r
             }
-            return;
+            NodeType::Lvar(Scope::Local(offset)) => {
+                let r = Some(self.new_reg());
+                self.add(IROp::Bprel, r, Some(offset));
+                r
+            }
+            NodeType::Gvar(name, _, _) => {
+                let r = Some(self.new_reg());
+                self.add(IROp::LabelAddr(name), r, None);
+                r
+            }
+            // A struct returned in memory is already at an address.
+            NodeType::Call(_, _) | NodeType::CallPtr(_, _) if node.ty.in_memory() => {
+                self.gen_expr(node)
+            }
+            // Sema rejects these with check_lval(), so reaching here is a bug.
+            op => panic!("not an lvalue: {:?}", op),
         }
-        NodeType::If(cond, then, els_may) => {
-            if let Some(els) = els_may {
-                let x = Some(*NLABEL.lock().unwrap());
-                *NLABEL.lock().unwrap() += 1;
-                let y = Some(*NLABEL.lock().unwrap());
-                *NLABEL.lock().unwrap() += 1;
-                let r = gen_expr(cond.clone());
-                add(IROp::Unless, r, x);
-                kill(r);
-                gen_stmt(*then.clone());
-                jmp(y);
-                label(x);
-                gen_stmt(*els);
-                label(y);
-                return;
+    }
+
+    fn gen_binop(&mut self, ty: IROp, lhs: Box<Node>, rhs: Box<Node>) -> Option<usize> {
+        let r1 = self.gen_expr(lhs);
+        let r2 = self.gen_expr(rhs);
+        self.add(ty, r1, r2);
+        self.kill(r2);
+        r1
+    }
+
+    fn gen_pre_inc(&mut self, ty: &Type, expr: Box<Node>, num: i32) -> i32 {
+        let addr = self.gen_lval(expr);
+        let val = self.new_reg();
+        self.load(ty, Some(val), addr);
+        self.add(
+            IROp::AddImm,
+            Some(val),
+            Some((num * get_inc_scale(ty)) as usize),
+        );
+        self.store(ty, addr, Some(val));
+        self.kill(addr);
+        val as i32
+    }
+
+    fn gen_post_inc(&mut self, ty: &Type, expr: Box<Node>, num: i32) -> i32 {
+        let val = self.gen_pre_inc(ty, expr, num);
+        self.add(
+            IROp::SubImm,
+            Some(val as usize),
+            Some((num * get_inc_scale(ty)) as usize),
+        );
+        val as i32
+    }
+
+    fn gen_assign_op(
+        &mut self,
+        op: &TokenType,
+        ty: &Type,
+        lhs: Box<Node>,
+        rhs: Box<Node>,
+    ) -> Option<usize> {
+        let src = self.gen_expr(rhs);
+        let dst = self.gen_lval(lhs);
+        let val = Some(self.new_reg());
+
+        self.load(ty, val, dst);
+        self.add(to_assign_op(op, ty), val, src);
+        self.wrap(ty, val);
+        self.kill(src);
+        self.store(ty, dst, val);
+        self.kill(dst);
+        val
+    }
+
+    fn gen_expr(&mut self, node: Box<Node>) -> Option<usize> {
+        let node = *node;
+        match node.op {
+            // An unsigned constant is zero-extended, like any other
+            // unsigned value in a register.
+            NodeType::Num(val) => {
+                let r = Some(self.new_reg());
+                if is_unsigned(&node.ty) {
+                    self.add(IROp::Imm, r, Some(val as u32 as usize));
+                } else {
+                    self.add(IROp::Imm, r, Some(val as usize));
+                }
+                r
             }
+            NodeType::Lvar(_) | NodeType::Dot(_, _, _) | NodeType::Gvar(_, _, _) => {
+                let r = self.gen_lval(Box::new(node.clone()));
+                self.load(&node.ty, r, r);
+                r
+            }
+            NodeType::Call(ref name, ref args) if is_minmax(name) => {
+                let r1 = self.gen_expr(Box::new(args[0].clone()));
+                let r2 = self.gen_expr(Box::new(args[1].clone()));
+                // r1 = r2 if max ? r1 < r2 : r2 < r1
+                let cond = self.new_reg();
+                if name == "__builtin_max" {
+                    self.add(IROp::Mov, Some(cond), r1);
+                    self.add(lt_op(&node.ty), Some(cond), r2);
+                } else {
+                    self.add(IROp::Mov, Some(cond), r2);
+                    self.add(lt_op(&node.ty), Some(cond), r1);
+                }
+                self.add(IROp::CMov(cond), r1, r2);
+                self.kill(Some(cond));
+                self.kill(r2);
+                r1
+            }
+            // The value is never used, but an expression must have one.
+            NodeType::Call(ref name, _) if is_trap(name) => {
+                let r = Some(self.new_reg());
+                self.add(IROp::Imm, r, Some(0));
+                self.add(IROp::Trap, None, None);
+                r
+            }
+            NodeType::Call(name, args) => {
+                let (args_ir, slots) = self.gen_args(args);
 
-            let x = Some(*NLABEL.lock().unwrap());
-            *NLABEL.lock().unwrap() += 1;
-            let r = gen_expr(cond);
-            add(IROp::Unless, r, x);
-            kill(r);
-            gen_stmt(*then);
-            label(x);
-        }
-        NodeType::For(init, cond, inc, body) => {
-            let x = Some(*NLABEL.lock().unwrap());
-            *NLABEL.lock().unwrap() += 1;
-            let y = Some(*NLABEL.lock().unwrap());
-            *NLABEL.lock().unwrap() += 1;
-            let orig = *BREAK_LABEL.lock().unwrap();
-            *BREAK_LABEL.lock().unwrap() = *NLABEL.lock().unwrap();
-            *NLABEL.lock().unwrap() += 1;
-
-            gen_stmt(*init);
-            label(x);
-            if !cond.is_null() {
-                let r2 = gen_expr(cond);
-                add(IROp::Unless, r2, y);
-                kill(r2);
-            }
-            gen_stmt(*body);
-            if !inc.is_null() {
-                gen_stmt(*inc);
-            }
-            jmp(x);
-            label(y);
-            label(Some(*BREAK_LABEL.lock().unwrap()));
-            *BREAK_LABEL.lock().unwrap() = orig;
-        }
-        NodeType::DoWhile(body, cond) => {
-            let x = Some(*NLABEL.lock().unwrap());
-            *NLABEL.lock().unwrap() += 1;
-            let orig = *BREAK_LABEL.lock().unwrap();
-            *BREAK_LABEL.lock().unwrap() = *NLABEL.lock().unwrap();
-            *NLABEL.lock().unwrap() += 1;
-            label(x);
-            gen_stmt(*body);
-            let r = gen_expr(cond);
-            add(IROp::If, r, x);
-            kill(r);
-            label(Some(*BREAK_LABEL.lock().unwrap()));
-            *BREAK_LABEL.lock().unwrap() = orig;
-        }
-        NodeType::Break => {
-            let break_label = *BREAK_LABEL.lock().unwrap();
-            if break_label == 0 {
-                panic!("stray 'break' statement");
+                let r = Some(self.new_reg());
+
+                self.add(IROp::Call(name, args_ir.clone(), slots), r, None);
+
+                for arg in args_ir {
+                    self.kill(Some(arg));
+                }
+                r
+            }
+            NodeType::CallPtr(callee, args) => {
+                let f = self.gen_expr(callee).unwrap();
+                let (args_ir, slots) = self.gen_args(args);
+
+                let r = Some(self.new_reg());
+                self.add(IROp::CallReg(f, args_ir.clone(), slots), r, None);
+
+                self.kill(Some(f));
+                for arg in args_ir {
+                    self.kill(Some(arg));
+                }
+                r
+            }
+            NodeType::Addr(expr) => self.gen_lval(expr),
+            NodeType::Deref(expr) => {
+                let r = self.gen_expr(expr);
+                self.load(&node.ty, r, r);
+                r
+            }
+            NodeType::StmtExpr(body) => {
+                let orig_label = self.return_label;
+                let orig_reg = self.return_reg;
+                self.return_label = Some(self.new_label());
+                let r = self.new_reg();
+                self.return_reg = r;
+
+                self.gen_stmt(*body);
+                self.label(self.return_label);
+
+                self.return_label = orig_label;
+                self.return_reg = orig_reg;
+                Some(r)
+            }
+            NodeType::BinOp(op, lhs, rhs) => {
+                use self::TokenType::*;
+                match op {
+                    Equal => {
+                        let rhs = self.gen_expr(rhs);
+                        let lhs = self.gen_lval(lhs);
+                        self.store(&node.ty, lhs, rhs);
+                        self.kill(lhs);
+                        rhs
+                    }
+                    Plus => {
+                        let r = self.gen_binop(IROp::Add, lhs, rhs);
+                        self.wrap(&node.ty, r);
+                        r
+                    }
+                    Minus => {
+                        let r = self.gen_binop(IROp::Sub, lhs, rhs);
+                        self.wrap(&node.ty, r);
+                        r
+                    }
+                    Mul => {
+                        let r = self.gen_binop(IROp::Mul, lhs, rhs);
+                        self.wrap(&node.ty, r);
+                        r
+                    }
+                    Div => self.gen_binop(div_op(&node.ty), lhs, rhs),
+                    Logand => {
+                        let x = Some(self.new_label());
+
+                        let r1 = self.gen_expr(lhs);
+                        self.add(IROp::Unless, r1, x);
+                        let r2 = self.gen_expr(rhs);
+                        self.add(IROp::Mov, r1, r2);
+                        self.kill(r2);
+                        self.add(IROp::Unless, r1, x);
+                        self.add(IROp::Imm, r1, Some(1));
+                        self.label(x);
+                        r1
+                    }
+                    Logor => {
+                        let x = Some(self.new_label());
+                        let y = Some(self.new_label());
+
+                        let r1 = self.gen_expr(lhs);
+                        self.add(IROp::Unless, r1, x);
+                        self.add(IROp::Imm, r1, Some(1));
+                        self.jmp(y);
+                        self.label(x);
+
+                        let r2 = self.gen_expr(rhs);
+                        self.add(IROp::Mov, r1, r2);
+                        self.kill(r2);
+                        self.add(IROp::Unless, r1, y);
+                        self.add(IROp::Imm, r1, Some(1));
+                        self.label(y);
+                        r1
+                    }
+                    MulEQ | DivEQ | ModEQ | AddEQ | SubEQ | ShlEQ | ShrEQ | BitandEQ | XorEQ
+                    | BitorEQ => self.gen_assign_op(&op, &node.ty, lhs, rhs),
+                    EQ => self.gen_binop(IROp::EQ, lhs, rhs),
+                    NE => self.gen_binop(IROp::NE, lhs, rhs),
+                    LE => {
+                        let op = le_op(&lhs.ty);
+                        self.gen_binop(op, lhs, rhs)
+                    }
+                    LeftAngleBracket => {
+                        let op = lt_op(&lhs.ty);
+                        self.gen_binop(op, lhs, rhs)
+                    }
+                    And => self.gen_binop(IROp::AND, lhs, rhs),
+                    VerticalBar => self.gen_binop(IROp::OR, lhs, rhs),
+                    Hat => self.gen_binop(IROp::XOR, lhs, rhs),
+                    SHL => {
+                        let r = self.gen_binop(IROp::SHL, lhs, rhs);
+                        self.wrap(&node.ty, r);
+                        r
+                    }
+                    SHR => self.gen_binop(shr_op(&node.ty), lhs, rhs),
+                    Mod => self.gen_binop(mod_op(&node.ty), lhs, rhs),
+                    Comma => {
+                        let r = self.gen_expr(lhs);
+                        self.kill(r);
+                        self.gen_expr(rhs)
+                    }
+                    _ => self.gen_binop(IROp::from(op), lhs, rhs),
+                }
+            }
+            NodeType::Neg(expr) => {
+                let r = self.gen_expr(expr);
+                self.add(IROp::Neg, r, None);
+                self.wrap(&node.ty, r);
+                r
+            }
+            NodeType::Cast(expr) => {
+                let from = *expr.ty.clone();
+                let r = self.gen_expr(expr);
+                match node.ty.ty {
+                    Ctype::Int if is_unsigned(&from) => self.add(IROp::Trunc, r, Some(4)),
+                    Ctype::Int | Ctype::Char if node.ty.size < from.size => {
+                        self.add(IROp::Trunc, r, Some(node.ty.size))
+                    }
+                    Ctype::Uint if !is_unsigned(&from) || from.size > 4 => {
+                        self.add(IROp::Zext, r, Some(4))
+                    }
+                    // An int register may hold garbage above bit 31.
+                    Ctype::Long | Ctype::Ulong
+                        if from.size < node.ty.size && !is_unsigned(&from) =>
+                    {
+                        self.add(IROp::Trunc, r, Some(4))
+                    }
+                    _ => (),
+                }
+                r
             }
-            jmp(Some(break_label));
+            NodeType::PostInc(expr) => Some(self.gen_post_inc(&node.ty, expr, 1) as usize),
+            NodeType::PostDec(expr) => Some(self.gen_post_inc(&node.ty, expr, -1) as usize),
+            NodeType::Ternary(cond, then, els) => {
+                //      cond then els  then
+                // return 1 ? 3 : 5; => 3
+
+                // If both arms are cheap to evaluate, evaluate both and
+                // pick one without a branch.
+                if is_pure(&then) && is_pure(&els) {
+                    let r = self.gen_expr(cond);
+                    let r2 = self.gen_expr(els);
+                    let r3 = self.gen_expr(then);
+                    self.add(IROp::CMov(r.unwrap()), r2, r3);
+                    self.kill(r3);
+                    self.kill(r);
+                    return r2;
+                }
+
+                let x = Some(self.new_label());
+                let y = Some(self.new_label());
+                let r = self.gen_expr(cond);
+
+                self.add(IROp::Unless, r, x);
+                let r2 = self.gen_expr(then);
+                self.add(IROp::Mov, r, r2);
+                self.kill(r2);
+                self.jmp(y);
+
+                self.label(x);
+                let r3 = self.gen_expr(els);
+                self.add(IROp::Mov, r, r3);
+                self.kill(r3);
+                self.label(y);
+                r
+            }
+            // The value of cond is the result unless it is 0.
+            NodeType::Elvis(cond, els) => {
+                let x = Some(self.new_label());
+                let r = self.gen_expr(cond);
+                self.add(IROp::If, r, x);
+                let r2 = self.gen_expr(els);
+                self.add(IROp::Mov, r, r2);
+                self.kill(r2);
+                self.label(x);
+                r
+            }
+            NodeType::Exclamation(expr) => {
+                let lhs = self.gen_expr(expr);
+                let rhs = Some(self.new_reg());
+                self.add(IROp::Imm, rhs, Some(0));
+                self.add(IROp::EQ, lhs, rhs);
+                self.kill(rhs);
+                lhs
+            }
+            e => unreachable!("{:?}", e),
         }
-        NodeType::Return(expr) => {
-            let r = gen_expr(expr);
-
-            // Statement expression (GNU extension)
-            if *RETURN_LABEL.lock().unwrap() != 0 {
-                add(IROp::Mov, Some(*RETURN_REG.lock().unwrap()), r);
-                kill(r);
-                jmp(Some(*RETURN_LABEL.lock().unwrap()));
+    }
+
+    fn gen_stmt(&mut self, node: Node) {
+        match node.op {
+            NodeType::Null => return,
+            NodeType::Vardef(name, init_may, Scope::Local(offset)) => {
+                self.vars.push((name, offset));
+                if let Some(init) = init_may {
+                    let rhs = self.gen_expr(init);
+                    let lhs = Some(self.new_reg());
+                    self.add(IROp::Bprel, lhs, Some(offset));
+                    self.store(&node.ty, lhs, rhs);
+                    self.kill(lhs);
+                    self.kill(rhs);
+                }
                 return;
             }
+            NodeType::If(cond, then, els_may) => {
+                if let Some(els) = els_may {
+                    let x = Some(self.new_label());
+                    let y = Some(self.new_label());
+                    let r = self.gen_expr(cond.clone());
+                    self.add(IROp::Unless, r, x);
+                    self.kill(r);
+                    self.gen_stmt(*then.clone());
+                    self.jmp(y);
+                    self.label(x);
+                    self.gen_stmt(*els);
+                    self.label(y);
+                    return;
+                }
+
+                let x = Some(self.new_label());
+                let r = self.gen_expr(cond);
+                self.add(IROp::Unless, r, x);
+                self.kill(r);
+                self.gen_stmt(*then);
+                self.label(x);
+            }
+            NodeType::For(init, cond, inc, body) => {
+                let x = self.new_label();
+                let y = Some(self.new_label());
+                let brk = self.new_label();
+                // Without an increment, as in a while loop, `continue`
+                // goes straight back to the condition.
+                let cont = if inc.is_null() {
+                    x
+                } else {
+                    self.new_label()
+                };
+                self.targets.push(JumpTarget::Loop {
+                    brk,
+                    cont,
+                    continued: false,
+                });
 
-            add(IROp::Return, r, None);
-            kill(r);
-        }
-        NodeType::ExprStmt(expr) => {
-            let r = gen_expr(expr);
-            kill(r);
-        }
-        NodeType::VecStmt(stmts) | NodeType::CompStmt(stmts) => {
-            for n in stmts {
-                gen_stmt(n);
+                self.gen_stmt(*init);
+                self.label(Some(x));
+                if !cond.is_null() {
+                    let r2 = self.gen_expr(cond);
+                    self.add(IROp::Unless, r2, y);
+                    self.kill(r2);
+                }
+                self.gen_stmt(*body);
+                if inc.is_null() {
+                    self.targets.pop();
+                } else {
+                    self.continue_label();
+                    self.gen_stmt(*inc);
+                }
+                self.jmp(Some(x));
+                self.label(y);
+                self.label(Some(brk));
+            }
+            // `do { ... } while (0)`, as macros often expand to, runs
+            // the body once. Both `break` and `continue` leave it.
+            NodeType::DoWhile(body, ref cond) if is_zero(cond) => {
+                let brk = self.new_label();
+                self.targets.push(JumpTarget::Loop {
+                    brk,
+                    cont: brk,
+                    continued: false,
+                });
+                self.gen_stmt(*body);
+                self.targets.pop();
+                self.label(Some(brk));
+            }
+            NodeType::DoWhile(body, cond) => {
+                let x = Some(self.new_label());
+                let brk = self.new_label();
+                let cont = self.new_label();
+                self.targets.push(JumpTarget::Loop {
+                    brk,
+                    cont,
+                    continued: false,
+                });
+                self.label(x);
+                self.gen_stmt(*body);
+                self.continue_label();
+                let r = self.gen_expr(cond);
+                self.add(IROp::If, r, x);
+                self.kill(r);
+                self.label(Some(brk));
+            }
+            NodeType::Switch(cond, body) => {
+                let mut cases = vec![];
+                collect_cases(&body, &mut cases);
+                let labels: Vec<usize> = cases.iter().map(|_| self.new_label()).collect();
+                let brk = self.new_label();
+
+                // Compare the value with each case in turn.
+                let r = self.gen_expr(cond);
+                for (case, &label) in cases.iter().zip(&labels) {
+                    if let Some(val) = *case {
+                        let r2 = Some(self.new_reg());
+                        let r3 = Some(self.new_reg());
+                        self.add(IROp::Mov, r2, r);
+                        self.add(IROp::Imm, r3, Some(val as usize));
+                        self.add(IROp::EQ, r2, r3);
+                        self.kill(r3);
+                        self.add(IROp::If, r2, Some(label));
+                        self.kill(r2);
+                    }
+                }
+                self.kill(r);
+                let default = cases.iter().zip(&labels).find(|&(c, _)| c.is_none());
+                self.jmp(Some(default.map_or(brk, |(_, &label)| label)));
+
+                let orig = mem::replace(&mut self.case_labels, labels);
+                self.case_labels.reverse();
+                self.targets.push(JumpTarget::Switch { brk });
+                self.gen_stmt(*body);
+                self.targets.pop();
+                self.case_labels = orig;
+                self.label(Some(brk));
+            }
+            NodeType::Case(_, body) | NodeType::Default(body) => {
+                match self.case_labels.pop() {
+                    Some(label) => self.label(Some(label)),
+                    None => panic!("stray case label"),
+                }
+                self.gen_stmt(*body);
+            }
+            NodeType::Break => {
+                let brk = match self.targets.last() {
+                    Some(&JumpTarget::Loop { brk, .. }) | Some(&JumpTarget::Switch { brk }) => brk,
+                    None => panic!("stray 'break' statement"),
+                };
+                self.jmp(Some(brk));
+            }
+            NodeType::Continue => {
+                let cont = self.targets.iter_mut().rev().find_map(|t| match *t {
+                    JumpTarget::Loop {
+                        cont,
+                        ref mut continued,
+                        ..
+                    } => {
+                        *continued = true;
+                        Some(cont)
+                    }
+                    JumpTarget::Switch { .. } => None,
+                });
+                match cont {
+                    Some(cont) => self.jmp(Some(cont)),
+                    None => panic!("stray 'continue' statement"),
+                }
+            }
+            NodeType::InlineAsm(text) => self.add(IROp::Asm(text), None, None),
+            NodeType::Return(expr) => {
+                let r = if expr.is_null() {
+                    let r = Some(self.new_reg());
+                    self.add(IROp::Imm, r, Some(0));
+                    r
+                } else {
+                    self.gen_expr(expr)
+                };
+
+                // Statement expression (GNU extension)
+                if self.return_label.is_some() {
+                    self.add(IROp::Mov, Some(self.return_reg), r);
+                    self.kill(r);
+                    self.jmp(self.return_label);
+                    return;
+                }
+
+                self.add(IROp::Return, r, None);
+                self.kill(r);
+            }
+            NodeType::ExprStmt(expr) => {
+                let r = self.gen_expr(expr);
+                self.kill(r);
+            }
+            NodeType::VecStmt(stmts) | NodeType::CompStmt(stmts) => {
+                for n in stmts {
+                    self.gen_stmt(n);
+                }
             }
+            e => panic!("unknown node: {:?}", e),
         }
-        e => panic!("unknown node: {:?}", e),
     }
 }
 
 pub fn gen_ir(nodes: Vec<Node>) -> Vec<Function> {
+    let mut ctx = Context::new();
     let mut v = vec![];
     for node in nodes {
         match node.op {
             NodeType::Func(name, args, body, stacksize) => {
-                *CODE.lock().unwrap() = vec![];
-                // *NUM_REGS.lock().unwrap() = 0;
+                // Registers and labels are numbered from 0 in each
+                // function, so a long file does not run the register
+                // allocator out of register numbers.
+                ctx.code = vec![];
+                ctx.vars = vec![];
+                ctx.stacksize = stacksize;
+                ctx.num_regs = 0;
+                ctx.nlabel = 0;
 
                 for (i, arg) in args.iter().enumerate() {
-                    if let NodeType::Vardef(_, _, Scope::Local(offset)) = arg.op {
-                        store_arg(&arg.ty, Some(offset), Some(i));
+                    if let NodeType::Vardef(ref name, _, Scope::Local(offset)) = arg.op {
+                        ctx.vars.push((name.clone(), offset));
+                        ctx.store_arg(&arg.ty, Some(offset), Some(i));
                     } else {
                         unreachable!();
                     }
                 }
-                gen_stmt(*body);
+                ctx.gen_stmt(*body);
 
-                v.push(Function::new(name, CODE.lock().unwrap().clone(), stacksize));
+                let vars = mem::take(&mut ctx.vars);
+                v.push(Function::new(name, ctx.code.clone(), ctx.stacksize, vars));
             }
             NodeType::Vardef(_, _, _) => (),
             _ => panic!("parse error."),
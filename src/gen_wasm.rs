@@ -0,0 +1,449 @@
+use backend::Backend;
+use gen_ir::{Function, IROp, IR};
+use util::roundup;
+use {Scope, Var};
+
+use std::collections::HashMap;
+
+// This pass generates WebAssembly text format from IR.
+//
+// Registers become i64 locals, and stack frames live in linear memory
+// below the global $sp, which starts at the top of memory. Global
+// variables are placed at the bottom of memory.
+//
+// Wasm has no goto. Each function body is therefore a loop around a
+// br_table which jumps to the basic block selected by $pc, and a jump
+// is "set $pc, restart the loop".
+//
+// Functions whose address is taken are put in a table, and a function
+// pointer is an index into it. Index 0 is left empty so that a null
+// pointer traps when called.
+
+const MEMORY_PAGES: usize = 2;
+const PAGE_SIZE: usize = 65536;
+const DATA_START: usize = 8;
+
+macro_rules! emit{
+    ($out:expr, $fmt:expr) => ($out.push_str(concat!("\t", $fmt, "\n")));
+    ($out:expr, $fmt:expr, $($arg:tt)*) => ($out.push_str(&format!(concat!("\t", $fmt, "\n"), $($arg)*)));
+}
+
+fn escape(data: &str, len: usize) -> String {
+    let bytes: Vec<char> = data.chars().collect();
+    let mut sb = String::new();
+    for i in 0..len {
+        let c = bytes.get(i).cloned().unwrap_or('\0');
+        sb.push_str(&format!("\\{:02x}", c as u32 as u8));
+    }
+    sb
+}
+
+// Arguments passed on the stack by the IR are loaded back from the
+// frame, since wasm passes all of them as parameters.
+fn push_args(out: &mut String, args: &[usize], slots: &[usize]) {
+    for arg in args {
+        emit!(out, "local.get $r{}", arg);
+    }
+    for off in slots {
+        emit!(out, "local.get $bp");
+        emit!(out, "i32.const {}", off);
+        emit!(out, "i32.sub");
+        emit!(out, "i64.load");
+    }
+}
+
+fn binop(op: &IROp) -> Option<&'static str> {
+    use self::IROp::*;
+    match op {
+        Add | AddImm => Some("i64.add"),
+        Sub | SubImm => Some("i64.sub"),
+        Mul | MulImm => Some("i64.mul"),
+        Div => Some("i64.div_s"),
+        UDiv => Some("i64.div_u"),
+        Mod => Some("i64.rem_s"),
+        UMod => Some("i64.rem_u"),
+        AND => Some("i64.and"),
+        OR => Some("i64.or"),
+        XOR => Some("i64.xor"),
+        SHL => Some("i64.shl"),
+        SHR => Some("i64.shr_u"),
+        SAR => Some("i64.shr_s"),
+        EQ => Some("i64.eq"),
+        NE => Some("i64.ne"),
+        LT => Some("i64.lt_s"),
+        LE => Some("i64.le_s"),
+        ULT => Some("i64.lt_u"),
+        ULE => Some("i64.le_u"),
+        _ => None,
+    }
+}
+
+fn mem_suffix(size: u8) -> &'static str {
+    match size {
+        1 => "8",
+        4 => "32",
+        8 => "",
+        _ => unreachable!(),
+    }
+}
+
+fn jump(out: &mut String, block: usize) {
+    emit!(out, "i32.const {}", block);
+    emit!(out, "local.set $pc");
+    emit!(out, "br $top");
+}
+
+fn gen_ir(
+    out: &mut String,
+    ir: &IR,
+    blocks: &HashMap<usize, usize>,
+    data: &HashMap<String, usize>,
+) {
+    use self::IROp::*;
+    let lhs = ir.lhs.unwrap_or(0);
+    let rhs = ir.rhs.unwrap_or(0);
+
+    if let Some(insn) = binop(&ir.op) {
+        emit!(out, "local.get $r{}", lhs);
+        match ir.op {
+            AddImm | SubImm | MulImm => emit!(out, "i64.const {}", rhs as i32),
+            _ => emit!(out, "local.get $r{}", rhs),
+        }
+        emit!(out, "{}", insn);
+        match ir.op {
+            EQ | NE | LT | LE | ULT | ULE => emit!(out, "i64.extend_i32_u"),
+            _ => (),
+        }
+        emit!(out, "local.set $r{}", lhs);
+        return;
+    }
+
+    match ir.op {
+        Imm => {
+            emit!(out, "i64.const {}", rhs as i64);
+            emit!(out, "local.set $r{}", lhs);
+        }
+        Mov => {
+            emit!(out, "local.get $r{}", rhs);
+            emit!(out, "local.set $r{}", lhs);
+        }
+        Return => {
+            emit!(out, "local.get $bp");
+            emit!(out, "global.set $sp");
+            emit!(out, "local.get $r{}", lhs);
+            emit!(out, "return");
+        }
+        Call(ref name, ref args, ref slots) => {
+            push_args(out, args, slots);
+            emit!(out, "call ${}", name);
+            emit!(out, "local.set $r{}", lhs);
+        }
+        CallReg(r, ref args, ref slots) => {
+            push_args(out, args, slots);
+            emit!(out, "local.get $r{}", r);
+            emit!(out, "i32.wrap_i64");
+            let mut ty = String::new();
+            for _ in 0..args.len() + slots.len() {
+                ty.push_str(" (param i64)");
+            }
+            emit!(out, "call_indirect{} (result i64)", ty);
+            emit!(out, "local.set $r{}", lhs);
+        }
+        LabelAddr(ref name) => {
+            match data.get(name) {
+                Some(addr) => emit!(out, "i64.const {}", addr),
+                None => panic!("{}: extern variables are not supported", name),
+            }
+            emit!(out, "local.set $r{}", lhs);
+        }
+        Neg => {
+            emit!(out, "i64.const 0");
+            emit!(out, "local.get $r{}", lhs);
+            emit!(out, "i64.sub");
+            emit!(out, "local.set $r{}", lhs);
+        }
+        Trunc => {
+            emit!(out, "local.get $r{}", lhs);
+            match rhs {
+                1 => {
+                    emit!(out, "i64.const 255");
+                    emit!(out, "i64.and");
+                }
+                4 => {
+                    emit!(out, "i32.wrap_i64");
+                    emit!(out, "i64.extend_i32_s");
+                }
+                _ => unreachable!(),
+            }
+            emit!(out, "local.set $r{}", lhs);
+        }
+        CMov(cond) => {
+            emit!(out, "local.get $r{}", rhs);
+            emit!(out, "local.get $r{}", lhs);
+            emit!(out, "local.get $r{}", cond);
+            emit!(out, "i64.eqz");
+            emit!(out, "i32.eqz");
+            emit!(out, "select");
+            emit!(out, "local.set $r{}", lhs);
+        }
+        Zext => {
+            emit!(out, "local.get $r{}", lhs);
+            emit!(out, "i64.const 4294967295");
+            emit!(out, "i64.and");
+            emit!(out, "local.set $r{}", lhs);
+        }
+        Jmp => jump(out, blocks[&lhs]),
+        If | Unless => {
+            emit!(out, "local.get $r{}", lhs);
+            emit!(out, "i64.eqz");
+            if ir.op == If {
+                emit!(out, "i32.eqz");
+            }
+            emit!(out, "if");
+            jump(out, blocks[&rhs]);
+            emit!(out, "end");
+        }
+        IfEq(r) => {
+            emit!(out, "local.get $r{}", lhs);
+            emit!(out, "local.get $r{}", r);
+            emit!(out, "i64.eq");
+            emit!(out, "if");
+            jump(out, blocks[&rhs]);
+            emit!(out, "end");
+        }
+        Load(size) | VolatileLoad(size) => {
+            emit!(out, "local.get $r{}", rhs);
+            emit!(out, "i32.wrap_i64");
+            match size {
+                1 => emit!(out, "i64.load8_u"),
+                4 => emit!(out, "i64.load32_s"),
+                _ => emit!(out, "i64.load"),
+            }
+            emit!(out, "local.set $r{}", lhs);
+        }
+        Store(size) | VolatileStore(size) => {
+            emit!(out, "local.get $r{}", lhs);
+            emit!(out, "i32.wrap_i64");
+            emit!(out, "local.get $r{}", rhs);
+            emit!(out, "i64.store{}", mem_suffix(size));
+        }
+        StoreArg(size) => {
+            emit!(out, "local.get $bp");
+            emit!(out, "i32.const {}", lhs);
+            emit!(out, "i32.sub");
+            emit!(out, "local.get $a{}", rhs);
+            emit!(out, "i64.store{}", mem_suffix(size));
+        }
+        Bprel => {
+            emit!(out, "local.get $bp");
+            emit!(out, "i32.const {}", rhs);
+            emit!(out, "i32.sub");
+            emit!(out, "i64.extend_i32_u");
+            emit!(out, "local.set $r{}", lhs);
+        }
+        Asm(ref text) => emit!(out, "{}", text),
+        Trap => emit!(out, "unreachable"),
+        Label | Kill | Nop => (),
+        _ => unreachable!(),
+    }
+}
+
+fn gen(out: &mut String, f: &Function, data: &HashMap<String, usize>) {
+    let nparams =
+        f.ir.iter()
+            .filter_map(|ir| match ir.op {
+                IROp::StoreArg(_) => Some(ir.rhs.unwrap() + 1),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+    let mut regs: Vec<usize> =
+        f.ir.iter()
+            .flat_map(|ir| {
+                let mut v = ir.uses();
+                v.extend(ir.def());
+                v
+            })
+            .collect();
+    regs.sort();
+    regs.dedup();
+
+    // A new basic block starts at every label.
+    let mut blocks = HashMap::new();
+    for ir in &f.ir {
+        if ir.op == IROp::Label {
+            let n = blocks.len() + 1;
+            blocks.insert(ir.lhs.unwrap(), n);
+        }
+    }
+    let nblocks = blocks.len() + 1;
+
+    out.push_str(&format!("(func ${} (export \"{}\")", f.name, f.name));
+    for i in 0..nparams {
+        out.push_str(&format!(" (param $a{} i64)", i));
+    }
+    out.push_str(" (result i64)\n");
+    emit!(out, "(local $bp i32)");
+    emit!(out, "(local $pc i32)");
+    for r in regs {
+        emit!(out, "(local $r{} i64)", r);
+    }
+
+    emit!(out, "global.get $sp");
+    emit!(out, "local.set $bp");
+    emit!(out, "local.get $bp");
+    emit!(out, "i32.const {}", roundup(f.stacksize, 16));
+    emit!(out, "i32.sub");
+    emit!(out, "global.set $sp");
+
+    emit!(out, "loop $top");
+    for i in (0..nblocks).rev() {
+        emit!(out, "block $B{}", i);
+    }
+    emit!(out, "local.get $pc");
+    let targets: Vec<String> = (0..nblocks).map(|i| format!("$B{}", i)).collect();
+    emit!(out, "br_table {} $B0", targets.join(" "));
+    emit!(out, "end");
+    for ir in &f.ir {
+        if ir.op == IROp::Label {
+            emit!(out, "end");
+        }
+        gen_ir(out, ir, &blocks, data);
+    }
+    emit!(out, "end");
+
+    // Falling off the end of a function returns 0.
+    emit!(out, "local.get $bp");
+    emit!(out, "global.set $sp");
+    emit!(out, "i64.const 0");
+    out.push_str(")\n");
+}
+
+// An entry of the symbol map. Globals are placed at a memory address,
+// and functions at an index in the module's function space.
+struct Symbol<'a> {
+    name: &'a str,
+    kind: &'static str,
+    addr: usize,
+    size: usize, // In bytes for data, in instructions for code
+}
+
+fn gen_module<'a>(globals: &'a [Var], fns: &'a [Function]) -> (String, Vec<Symbol<'a>>) {
+    let mut out = String::new();
+    let mut syms = vec![];
+    out.push_str("(module\n");
+
+    // Functions called but not defined here come from the host.
+    let mut imports: Vec<(&str, usize)> = vec![];
+    for f in fns {
+        for ir in &f.ir {
+            if let IROp::Call(ref name, ref args, ref slots) = ir.op {
+                if fns.iter().all(|f| &f.name != name) && imports.iter().all(|i| i.0 != name) {
+                    imports.push((name, args.len() + slots.len()));
+                }
+            }
+        }
+    }
+    let nimports = imports.len();
+    for (name, nargs) in imports {
+        out.push_str(&format!("(import \"env\" \"{}\" (func ${}", name, name));
+        for _ in 0..nargs {
+            out.push_str(" (param i64)");
+        }
+        out.push_str(" (result i64)))\n");
+    }
+
+    out.push_str("(memory (export \"memory\") ");
+    out.push_str(&format!("{})\n", MEMORY_PAGES));
+    out.push_str(&format!(
+        "(global $sp (mut i32) (i32.const {}))\n",
+        MEMORY_PAGES * PAGE_SIZE
+    ));
+
+    let mut data = HashMap::new();
+    let mut addr = DATA_START;
+    for var in globals {
+        if let Scope::Global(ref s, len, is_extern) = var.scope {
+            if is_extern {
+                continue;
+            }
+            addr = roundup(addr, 8);
+            out.push_str(&format!(
+                "(data (i32.const {}) \"{}\")\n",
+                addr,
+                escape(s, len)
+            ));
+            data.insert(var.name.clone(), addr);
+            syms.push(Symbol {
+                name: &var.name,
+                kind: "data",
+                addr,
+                size: len,
+            });
+            addr += len;
+            continue;
+        }
+        unreachable!();
+    }
+
+    let taken = fns.iter().any(|f| {
+        f.ir.iter().any(|ir| match ir.op {
+            IROp::LabelAddr(ref name) => fns.iter().any(|f| &f.name == name),
+            _ => false,
+        })
+    });
+    if taken {
+        out.push_str(&format!("(table {} funcref)\n", fns.len() + 1));
+        out.push_str("(elem (i32.const 1)");
+        for (i, f) in fns.iter().enumerate() {
+            out.push_str(&format!(" ${}", f.name));
+            data.insert(f.name.clone(), i + 1);
+        }
+        out.push_str(")\n");
+    }
+
+    for (i, f) in fns.iter().enumerate() {
+        let start = out.len();
+        gen(&mut out, f, &data);
+        syms.push(Symbol {
+            name: &f.name,
+            kind: "func",
+            addr: nimports + i,
+            size: out[start..]
+                .lines()
+                .filter(|l| l.starts_with('\t') && !l.starts_with("\t(local"))
+                .count(),
+        });
+    }
+    out.push_str(")\n");
+    (out, syms)
+}
+
+pub fn gen_wasm(globals: &[Var], fns: &[Function]) -> String {
+    gen_module(globals, fns).0
+}
+
+// Lists the functions and globals of the module with where they are
+// placed, one per line.
+pub fn symbol_map(globals: &[Var], fns: &[Function]) -> String {
+    let mut out = format!("{:<16} {:<4} {:>8} {:>8}\n", "name", "kind", "addr", "size");
+    for sym in gen_module(globals, fns).1 {
+        out.push_str(&format!(
+            "{:<16} {:<4} {:>8} {:>8}\n",
+            sym.name, sym.kind, sym.addr, sym.size
+        ));
+    }
+    out
+}
+
+pub struct Wasm;
+
+impl Backend for Wasm {
+    fn emit(&self, globals: &[Var], fns: &[Function]) -> String {
+        gen_wasm(globals, fns)
+    }
+
+    fn symbol_map(&self, globals: &[Var], fns: &[Function]) -> Option<String> {
+        Some(symbol_map(globals, fns))
+    }
+}
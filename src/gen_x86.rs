@@ -1,4 +1,6 @@
+use backend::Backend;
 use gen_ir::{Function, IROp, IR};
+use target::{target, Target};
 use util::roundup;
 use {Scope, Var, REGS_N};
 
@@ -6,17 +8,57 @@ const REGS: [&str; REGS_N] = ["r10", "r11", "rbx", "r12", "r13", "r14", "r15"];
 const REGS8: [&str; REGS_N] = ["r10b", "r11b", "bl", "r12b", "r13b", "r14b", "r15b"];
 const REGS32: [&str; REGS_N] = ["r10d", "r11d", "ebx", "r12d", "r13d", "r14d", "r15d"];
 
-use std::sync::Mutex;
-
 // Quoted from 9cc
 // > This pass generates x86-64 assembly from IR.
 
-const ARGREGS: [&str; 6] = ["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
-const ARGREGS8: [&str; 6] = ["dil", "sil", "dl", "cl", "r8b", "r9b"];
-const ARGREGS32: [&str; 6] = ["edi", "esi", "edx", "ecx", "r8d", "r9d"];
+// How integer arguments are passed to functions.
+pub struct CallConv {
+    pub argregs: &'static [&'static str],
+    pub argregs8: &'static [&'static str],
+    pub argregs32: &'static [&'static str],
+    // Stack space the caller reserves for the callee to spill its
+    // register arguments.
+    pub shadow_space: usize,
+    // Alignment of rsp at a call instruction. libc uses SSE instructions
+    // that fault on a misaligned stack.
+    pub stack_align: usize,
+}
+
+impl CallConv {
+    // System V AMD64 ABI used on Linux and macOS.
+    pub fn sysv() -> Self {
+        CallConv {
+            argregs: &["rdi", "rsi", "rdx", "rcx", "r8", "r9"],
+            argregs8: &["dil", "sil", "dl", "cl", "r8b", "r9b"],
+            argregs32: &["edi", "esi", "edx", "ecx", "r8d", "r9d"],
+            shadow_space: 0,
+            stack_align: 16,
+        }
+    }
 
-lazy_static! {
-    static ref LABEL: Mutex<usize> = Mutex::new(0);
+    // Microsoft x64 calling convention.
+    pub fn win64() -> Self {
+        CallConv {
+            argregs: &["rcx", "rdx", "r8", "r9"],
+            argregs8: &["cl", "dl", "r8b", "r9b"],
+            argregs32: &["ecx", "edx", "r8d", "r9d"],
+            shadow_space: 32,
+            stack_align: 16,
+        }
+    }
+
+    pub fn argreg(&self, r: usize, size: u8) -> &'static str {
+        let regs = match size {
+            1 => self.argregs8,
+            4 => self.argregs32,
+            8 => self.argregs,
+            _ => unreachable!(),
+        };
+        match regs.get(r) {
+            Some(reg) => reg,
+            None => panic!("too many arguments: at most {} are supported", regs.len()),
+        }
+    }
 }
 
 fn backslash_escape(s: String, len: usize) -> String {
@@ -41,10 +83,7 @@ fn backslash_escape(s: String, len: usize) -> String {
             } else if c.is_ascii_graphic() || c == &' ' {
                 sb.push(c.clone());
             } else {
-                sb.push_str(&format!("\\{:o}", *c as i8));
-            }
-            if i == len - 1 {
-                sb.push_str("\\000");
+                sb.push_str(&format!("\\{:03o}", *c as u32 as u8));
             }
         } else {
             sb.push_str("\\000");
@@ -54,16 +93,86 @@ fn backslash_escape(s: String, len: usize) -> String {
 }
 
 macro_rules! emit{
-    ($fmt:expr) => (print!(concat!("\t", $fmt, "\n")));
-    ($fmt:expr, $($arg:tt)*) => (print!(concat!("\t", $fmt, "\n"), $($arg)*));
+    ($out:expr, $fmt:expr) => ($out.push_str(concat!("\t", $fmt, "\n")));
+    ($out:expr, $fmt:expr, $($arg:tt)*) => ($out.push_str(&format!(concat!("\t", $fmt, "\n"), $($arg)*)));
 }
 
-fn emit_cmp(ir: IR, insn: &'static str) {
+fn emit_cmp(out: &mut String, regs: &[&str], ir: &IR, insn: &'static str) {
     let lhs = ir.lhs.unwrap();
     let rhs = ir.rhs.unwrap();
-    emit!("cmp {}, {}", REGS[lhs], REGS[rhs]);
-    emit!("{} {}", insn, REGS8[lhs]);
-    emit!("movzb {}, {}", REGS[lhs], REGS8[lhs]);
+    emit!(out, "cmp {}, {}", regs[lhs], regs[rhs]);
+    emit!(out, "{} {}", insn, REGS8[lhs]);
+    emit!(out, "movzb {}, {}", regs[lhs], REGS8[lhs]);
+}
+
+// `target` is either a symbol or a register holding the callee's address.
+// The scratch registers are saved around the call, which leaves a callee
+// address in r10 or r11 intact for the call instruction itself.
+//
+// Arguments that do not fit in the convention's registers, followed by
+// the ones the IR already stored in `slots`, are pushed right to left.
+fn emit_call(
+    out: &mut String,
+    x86: &X86,
+    target: &str,
+    lhs: usize,
+    args: &[usize],
+    slots: &[usize],
+) {
+    let conv = &x86.conv;
+    let regs = x86.regs();
+    let nregs = conv.argregs.len();
+    for (i, &arg) in args.iter().enumerate().take(nregs) {
+        emit!(
+            out,
+            "mov {}, {}",
+            conv.argreg(i, x86.target.word_size as u8),
+            regs[arg]
+        );
+    }
+    emit!(out, "push r10");
+    emit!(out, "push r11");
+    let stack: Vec<String> = args
+        .iter()
+        .skip(nregs)
+        .map(|&arg| REGS[arg].to_string())
+        .chain(slots.iter().map(|off| format!("qword ptr [rbp-{}]", off)))
+        .collect();
+    let pad = roundup(stack.len() * 8, conv.stack_align) - stack.len() * 8;
+    if pad != 0 {
+        emit!(out, "sub rsp, {}", pad);
+    }
+    for arg in stack.iter().rev() {
+        emit!(out, "push {}", arg);
+    }
+    emit!(out, "mov rax, 0");
+    if conv.shadow_space != 0 {
+        emit!(out, "sub rsp, {}", conv.shadow_space);
+    }
+    emit!(out, "call {}", target);
+    let cleanup = conv.shadow_space + stack.len() * 8 + pad;
+    if cleanup != 0 {
+        emit!(out, "add rsp, {}", cleanup);
+    }
+    emit!(out, "pop r11");
+    emit!(out, "pop r10");
+
+    emit!(
+        out,
+        "mov {}, {}",
+        regs[lhs],
+        if x86.target.word_size == 8 {
+            "rax"
+        } else {
+            "eax"
+        }
+    );
+}
+
+// Label numbers start from 0 in each function, so the function name
+// makes them unique in the output.
+fn label(f: &Function, l: usize) -> String {
+    format!(".L{}_{}", f.name, l)
 }
 
 fn reg(r: usize, size: u8) -> &'static str {
@@ -75,154 +184,298 @@ fn reg(r: usize, size: u8) -> &'static str {
     }
 }
 
-fn argreg(r: usize, size: u8) -> &'static str {
-    match size {
-        1 => ARGREGS8[r],
-        4 => ARGREGS32[r],
-        8 => ARGREGS[r],
-        _ => unreachable!(),
-    }
-}
+// Written just below the local variables when stack canaries are
+// enabled, and checked on return.
+const CANARY: u64 = 0xdead_beef_cafe_babe;
 
-fn gen(f: Function) {
+fn gen(out: &mut String, f: &Function, x86: &X86) {
     use self::IROp::*;
-    let ret = format!(".Lend{}", *LABEL.lock().unwrap());
-    *LABEL.lock().unwrap() += 1;
-
-    println!(".text");
-    println!(".global {}", f.name);
-    println!("{}:", f.name);
-    emit!("push rbp");
-    emit!("mov rbp, rsp");
-    emit!("sub rsp, {}", roundup(f.stacksize, 16));
-    emit!("push r12");
-    emit!("push r13");
-    emit!("push r14");
-    emit!("push r15");
-
-    for ir in f.ir {
-        let lhs = ir.lhs.unwrap();
+    let conv = &x86.conv;
+    let regs = x86.regs();
+    let wide = x86.target.word_size == 8;
+    let (ax, dx) = if wide { ("rax", "rdx") } else { ("eax", "edx") };
+    let sign_extend = if wide { "cqo" } else { "cdq" };
+    let ret = format!(".L{}_end", f.name);
+
+    // The return address and rbp take 16 bytes, and the five
+    // callee-saved registers below are padded to 48, so rsp stays
+    // aligned as long as the frame size is a multiple of the alignment.
+    let mut frame = roundup(f.stacksize, conv.stack_align);
+    if x86.canary {
+        frame += 16;
+    }
+
+    out.push_str(".text\n");
+    out.push_str(&format!(".global {}\n", f.name));
+    out.push_str(&format!("{}:\n", f.name));
+    emit!(out, "push rbp");
+    emit!(out, "mov rbp, rsp");
+    emit!(out, "sub rsp, {}", frame);
+    if x86.canary {
+        emit!(out, "mov r11, 0x{:x}", CANARY);
+        emit!(out, "mov [rbp-{}], r11", frame - 8);
+    }
+    emit!(out, "push rbx");
+    emit!(out, "push r12");
+    emit!(out, "push r13");
+    emit!(out, "push r14");
+    emit!(out, "push r15");
+    emit!(out, "sub rsp, 8");
+
+    for ir in &f.ir {
+        let lhs = ir.lhs.unwrap_or(0);
         let rhs = ir.rhs.unwrap_or(0);
+        let start = out.len();
         match ir.op {
-            Imm => emit!("mov {}, {}", REGS[lhs], rhs as i32),
-            Mov => emit!("mov {}, {}", REGS[lhs], REGS[rhs]),
+            Imm => emit!(out, "mov {}, {}", regs[lhs], rhs as i64),
+            Mov => emit!(out, "mov {}, {}", regs[lhs], regs[rhs]),
             Return => {
-                emit!("mov rax, {}", REGS[lhs]);
-                emit!("jmp {}", ret);
+                emit!(out, "mov {}, {}", ax, regs[lhs]);
+                emit!(out, "jmp {}", ret);
             }
-            Call(name, nargs, args) => {
-                for i in 0..nargs {
-                    emit!("mov {}, {}", ARGREGS[i], REGS[args[i]]);
-                }
-                emit!("push r10");
-                emit!("push r11");
-                emit!("mov rax, 0");
-                emit!("call {}", name);
-                emit!("pop r11");
-                emit!("pop r10");
-
-                emit!("mov {}, rax", REGS[lhs]);
-            }
-            Label => println!(".L{}:", lhs),
-            LabelAddr(name) => emit!("lea {}, {}", REGS[lhs], name),
-            Neg => emit!("neg {}", REGS[lhs]),
-            EQ => emit_cmp(ir, "sete"),
-            NE => emit_cmp(ir, "setne"),
-            LT => emit_cmp(ir, "setl"),
-            LE => emit_cmp(ir, "setle"),
-            AND => emit!("and {}, {}", REGS[lhs], REGS[rhs]),
-            OR => emit!("or {}, {}", REGS[lhs], REGS[rhs]),
-            XOR => emit!("xor {}, {}", REGS[lhs], REGS[rhs]),
+            Call(ref name, ref args, ref slots) => emit_call(out, x86, name, lhs, args, slots),
+            CallReg(r, ref args, ref slots) => emit_call(out, x86, REGS[r], lhs, args, slots),
+            Label => out.push_str(&format!("{}:\n", label(f, lhs))),
+            LabelAddr(ref name) => emit!(out, "lea {}, {}", regs[lhs], name),
+            Neg => emit!(out, "neg {}", regs[lhs]),
+            Trunc => match rhs {
+                1 => emit!(out, "movzb {}, {}", regs[lhs], REGS8[lhs]),
+                4 if wide => emit!(out, "movsxd {}, {}", regs[lhs], REGS32[lhs]),
+                4 => (),
+                _ => unreachable!(),
+            },
+            // Writing a 32-bit register clears the upper half.
+            Zext => emit!(out, "mov {}, {}", REGS32[lhs], REGS32[lhs]),
+            CMov(cond) => {
+                emit!(out, "cmp {}, 0", regs[cond]);
+                emit!(out, "cmovne {}, {}", regs[lhs], regs[rhs]);
+            }
+            EQ => emit_cmp(out, regs, ir, "sete"),
+            NE => emit_cmp(out, regs, ir, "setne"),
+            LT => emit_cmp(out, regs, ir, "setl"),
+            LE => emit_cmp(out, regs, ir, "setle"),
+            ULT => emit_cmp(out, regs, ir, "setb"),
+            ULE => emit_cmp(out, regs, ir, "setbe"),
+            AND => emit!(out, "and {}, {}", regs[lhs], regs[rhs]),
+            OR => emit!(out, "or {}, {}", regs[lhs], regs[rhs]),
+            XOR => emit!(out, "xor {}, {}", regs[lhs], regs[rhs]),
             SHL => {
-                emit!("mov cl, {}", REGS8[rhs]);
-                emit!("shl {}, cl", REGS[lhs]);
+                emit!(out, "mov cl, {}", REGS8[rhs]);
+                emit!(out, "shl {}, cl", regs[lhs]);
             }
             SHR => {
-                emit!("mov cl, {}", REGS8[rhs]);
-                emit!("shr {}, cl", REGS[lhs]);
+                emit!(out, "mov cl, {}", REGS8[rhs]);
+                emit!(out, "shr {}, cl", regs[lhs]);
+            }
+            SAR => {
+                emit!(out, "mov cl, {}", REGS8[rhs]);
+                emit!(out, "sar {}, cl", regs[lhs]);
             }
             Mod => {
-                /* Same meaning(?).
-                 * emit!("mov rdx, 0");
-                 * emit!("mov rax, {}", REGS[lhs]);
-                 */
-                emit!("mov rax, {}", REGS[lhs]);
-                emit!("cqo"); // rax -> rdx:rax
-                emit!("div {}", REGS[rhs]);
-                emit!("mov {}, rdx", REGS[lhs]);
-            }
-            Jmp => emit!("jmp .L{}", lhs),
+                emit!(out, "mov {}, {}", ax, regs[lhs]);
+                emit!(out, "{}", sign_extend); // rax -> rdx:rax
+                emit!(out, "idiv {}", regs[rhs]);
+                emit!(out, "mov {}, {}", regs[lhs], dx);
+            }
+            UMod => {
+                emit!(out, "mov {}, {}", ax, regs[lhs]);
+                emit!(out, "xor edx, edx");
+                emit!(out, "div {}", regs[rhs]);
+                emit!(out, "mov {}, {}", regs[lhs], dx);
+            }
+            Jmp => emit!(out, "jmp {}", label(f, lhs)),
             If => {
-                emit!("cmp {}, 0", REGS[lhs]);
-                emit!("jne .L{}", rhs);
+                emit!(out, "cmp {}, 0", regs[lhs]);
+                emit!(out, "jne {}", label(f, rhs));
             }
             Unless => {
-                emit!("cmp {}, 0", REGS[lhs]);
-                emit!("je .L{}", rhs);
+                emit!(out, "cmp {}, 0", regs[lhs]);
+                emit!(out, "je {}", label(f, rhs));
             }
-            Load(size) => {
-                emit!("mov {}, [{}]", reg(lhs, size), REGS[rhs]);
-                if size == 1 {
-                    emit!("movzb {}, {}", REGS[lhs], REGS8[lhs]);
+            IfEq(r) => {
+                emit!(out, "cmp {}, {}", regs[lhs], regs[r]);
+                emit!(out, "je {}", label(f, rhs));
+            }
+            // char is unsigned and int is signed.
+            Load(size) | VolatileLoad(size) => match size {
+                1 => {
+                    emit!(out, "mov {}, [{}]", REGS8[lhs], REGS[rhs]);
+                    emit!(out, "movzb {}, {}", regs[lhs], REGS8[lhs]);
                 }
+                4 if wide => emit!(out, "movsxd {}, dword ptr [{}]", regs[lhs], REGS[rhs]),
+                4 => emit!(out, "mov {}, dword ptr [{}]", regs[lhs], REGS[rhs]),
+                _ => emit!(out, "mov {}, [{}]", REGS[lhs], REGS[rhs]),
+            },
+            Store(size) | VolatileStore(size) => {
+                emit!(out, "mov [{}], {}", REGS[lhs], reg(rhs, size))
+            }
+            StoreArg(size) if rhs < conv.argregs.len() => {
+                emit!(out, "mov [rbp-{}], {}", lhs, conv.argreg(rhs, size))
             }
-            Store(size) => emit!("mov [{}], {}", REGS[lhs], reg(rhs, size)),
-            StoreArg(size) => emit!("mov [rbp-{}], {}", lhs, argreg(rhs, size)),
-            Add => emit!("add {}, {}", REGS[lhs], REGS[rhs]),
-            AddImm => emit!("add {}, {}", REGS[lhs], rhs as i32),
-            Sub => emit!("sub {}, {}", REGS[lhs], REGS[rhs]),
-            SubImm => emit!("sub {}, {}", REGS[lhs], rhs as i32),
-            Bprel => emit!("lea {}, [rbp-{}]", REGS[lhs], rhs),
+            // The caller pushed the rest above the return address and
+            // the shadow space.
+            StoreArg(size) => {
+                let off = 16 + conv.shadow_space + (rhs - conv.argregs.len()) * 8;
+                emit!(out, "mov rax, [rbp+{}]", off);
+                let val = match size {
+                    1 => "al",
+                    4 => "eax",
+                    _ => "rax",
+                };
+                emit!(out, "mov [rbp-{}], {}", lhs, val);
+            }
+            Add => emit!(out, "add {}, {}", regs[lhs], regs[rhs]),
+            AddImm => emit!(out, "add {}, {}", regs[lhs], rhs as i32),
+            Sub => emit!(out, "sub {}, {}", regs[lhs], regs[rhs]),
+            SubImm => emit!(out, "sub {}, {}", regs[lhs], rhs as i32),
+            Bprel => emit!(out, "lea {}, [rbp-{}]", regs[lhs], rhs),
             Mul => {
-                emit!("mov rax, {}", REGS[rhs]);
-                emit!("mul {}", REGS[lhs]);
-                emit!("mov {}, rax", REGS[lhs]);
+                emit!(out, "mov {}, {}", ax, regs[rhs]);
+                emit!(out, "mul {}", regs[lhs]);
+                emit!(out, "mov {}, {}", regs[lhs], ax);
             }
             MulImm => {
                 if rhs < 256 && rhs.count_ones() == 1 {
-                    emit!("shl {}, {}", REGS[lhs], rhs.trailing_zeros());
+                    emit!(out, "shl {}, {}", regs[lhs], rhs.trailing_zeros());
                 } else {
-                    emit!("mov rax, {}", rhs as i32);
-                    emit!("mul {}", REGS[lhs]);
-                    emit!("mov {}, rax", REGS[lhs]);
+                    emit!(out, "mov {}, {}", ax, rhs as i32);
+                    emit!(out, "mul {}", regs[lhs]);
+                    emit!(out, "mov {}, {}", regs[lhs], ax);
                 }
             }
             Div => {
-                emit!("mov rax, {}", REGS[lhs]);
-                emit!("cqo");
-                emit!("div {}", REGS[rhs]);
-                emit!("mov {}, rax", REGS[lhs]);
+                emit!(out, "mov {}, {}", ax, regs[lhs]);
+                emit!(out, "{}", sign_extend);
+                emit!(out, "idiv {}", regs[rhs]);
+                emit!(out, "mov {}, {}", regs[lhs], ax);
+            }
+            UDiv => {
+                emit!(out, "mov {}, {}", ax, regs[lhs]);
+                emit!(out, "xor edx, edx");
+                emit!(out, "div {}", regs[rhs]);
+                emit!(out, "mov {}, {}", regs[lhs], ax);
             }
+            Asm(ref text) => emit!(out, "{}", text),
+            Trap => emit!(out, "ud2"),
             Nop | Kill => (),
+            Phi(_) => unreachable!("PHI must be removed before code generation"),
+        }
+
+        // Note the IR instruction after the last line it generated.
+        if x86.verbose && ir.op != Label && out.len() > start {
+            out.pop();
+            out.push_str(&format!("\t# {}\n", ir.to_string().trim()));
         }
     }
 
-    println!("{}:", ret);
-    emit!("pop r15");
-    emit!("pop r14");
-    emit!("pop r13");
-    emit!("pop r12");
-    emit!("mov rsp, rbp");
-    emit!("pop rbp");
-    emit!("ret");
+    out.push_str(&format!("{}:\n", ret));
+    if x86.canary {
+        emit!(out, "mov r11, 0x{:x}", CANARY);
+        emit!(out, "cmp [rbp-{}], r11", frame - 8);
+        emit!(out, "je {}.ok", ret);
+        emit!(out, "ud2");
+        out.push_str(&format!("{}.ok:\n", ret));
+    }
+    emit!(out, "add rsp, 8");
+    emit!(out, "pop r15");
+    emit!(out, "pop r14");
+    emit!(out, "pop r13");
+    emit!(out, "pop r12");
+    emit!(out, "pop rbx");
+    emit!(out, "mov rsp, rbp");
+    emit!(out, "pop rbp");
+    emit!(out, "ret");
 }
 
-pub fn gen_x86(globals: Vec<Var>, fns: Vec<Function>) {
-    println!(".intel_syntax noprefix");
-    println!(".data");
+pub fn gen_x86(globals: &[Var], fns: &[Function], x86: &X86) -> String {
+    let mut out = String::new();
+    out.push_str(".intel_syntax noprefix\n");
+    out.push_str(".data\n");
     for var in globals {
-        if let Scope::Global(data, len, is_extern) = var.scope {
+        if let Scope::Global(ref data, len, is_extern) = var.scope {
             if is_extern {
                 continue;
             }
-            println!("{}:", var.name);
-            emit!(".ascii \"{}\"", backslash_escape(data, len));
+            out.push_str(&format!("{}:\n", var.name));
+            emit!(out, ".ascii \"{}\"", backslash_escape(data.clone(), len));
             continue;
         }
         unreachable!();
     }
 
     for f in fns {
-        gen(f);
+        gen(&mut out, f, x86);
+    }
+    out
+}
+
+// Lists the globals and functions in the order gen_x86() emits them,
+// one per line. The assembler decides where the code goes, so only a
+// global has an address: its offset in .data, where the globals are
+// packed without padding. A function's address and size are `-`.
+pub fn symbol_map(globals: &[Var], fns: &[Function]) -> String {
+    let mut out = format!("{:<16} {:<4} {:>8} {:>8}\n", "name", "kind", "addr", "size");
+    let mut addr = 0;
+    for var in globals {
+        if let Scope::Global(_, len, false) = var.scope {
+            out.push_str(&format!(
+                "{:<16} {:<4} {:>8} {:>8}\n",
+                var.name, "data", addr, len
+            ));
+            addr += len;
+        }
+    }
+    for f in fns {
+        out.push_str(&format!(
+            "{:<16} {:<4} {:>8} {:>8}\n",
+            f.name, "func", "-", "-"
+        ));
+    }
+    out
+}
+
+pub struct X86 {
+    pub conv: CallConv,
+    pub target: Target,
+    // Check for stack corruption in every function. For debugging the
+    // compiler itself.
+    pub canary: bool,
+    // Annotate the assembly with the IR that each part of it came from.
+    pub verbose: bool,
+}
+
+impl Default for X86 {
+    fn default() -> Self {
+        X86::new()
+    }
+}
+
+impl X86 {
+    pub fn new() -> Self {
+        X86 {
+            conv: CallConv::sysv(),
+            target: target(),
+            canary: false,
+            verbose: false,
+        }
+    }
+
+    // Registers of the word size, for values. Addresses always use the
+    // 64-bit registers.
+    fn regs(&self) -> &'static [&'static str] {
+        match self.target.word_size {
+            4 => &REGS32,
+            _ => &REGS,
+        }
+    }
+}
+
+impl Backend for X86 {
+    fn emit(&self, globals: &[Var], fns: &[Function]) -> String {
+        gen_x86(globals, fns, self)
+    }
+
+    fn symbol_map(&self, globals: &[Var], fns: &[Function]) -> Option<String> {
+        Some(symbol_map(globals, fns))
     }
 }
@@ -0,0 +1,259 @@
+use cfg::{basic_blocks, BlockId};
+use gen_ir::{Function, IROp};
+use util::roundup;
+
+use std::collections::HashMap;
+
+// An interpreter for the IR, so that the output of the front end can be
+// checked without assembling and running it.
+//
+// Registers and immediates hold 64-bit values, like on x86-64, and
+// every call gets a fresh set of registers. Nothing is cut to 32 bits
+// unless the IR says so, so long arithmetic keeps all its bits. Stack
+// frames live in a byte array and addresses are offsets into it. Global
+// variables are not supported yet.
+//
+// The address of a function is its index in the program counted from
+// the end of the stack, so that it never points to data.
+
+const STACK_SIZE: usize = 1 << 20;
+const FUNC_BASE: usize = STACK_SIZE;
+
+// Label ids mapped to instruction indices, so that a jump is a lookup,
+// and the basic block of each instruction, so that a PHI can tell which
+// block control came from.
+struct Layout {
+    labels: HashMap<usize, usize>,
+    blocks: Vec<BlockId>,
+}
+
+fn layout(f: &Function) -> Layout {
+    let labels =
+        f.ir.iter()
+            .enumerate()
+            .filter(|&(_, ir)| ir.op == IROp::Label)
+            .map(|(i, ir)| (ir.lhs.unwrap(), i))
+            .collect();
+    let mut blocks = vec![0; f.ir.len()];
+    for (id, b) in basic_blocks(f).iter().enumerate() {
+        for block in &mut blocks[b.start..b.end] {
+            *block = id;
+        }
+    }
+    Layout { labels, blocks }
+}
+
+struct Interp<'a> {
+    fns: HashMap<&'a str, (&'a Function, &'a Layout)>,
+    names: Vec<&'a str>, // Function names by address
+    mem: Vec<u8>,
+    sp: usize,
+}
+
+impl<'a> Interp<'a> {
+    fn load(&self, addr: i64, size: u8) -> i64 {
+        let addr = addr as usize;
+        let mut val = 0u64;
+        for i in (0..size as usize).rev() {
+            val = val << 8 | u64::from(self.mem[addr + i]);
+        }
+        match size {
+            1 => val as i64,
+            4 => val as u32 as i32 as i64,
+            8 => val as i64,
+            _ => unreachable!(),
+        }
+    }
+
+    fn store(&mut self, addr: i64, size: u8, val: i64) {
+        let addr = addr as usize;
+        for i in 0..size as usize {
+            self.mem[addr + i] = (val >> (i * 8)) as u8;
+        }
+    }
+
+    // Argument values of a call, including the ones stored in the frame.
+    fn args(
+        &self,
+        regs: &HashMap<usize, i64>,
+        bp: usize,
+        args: &[usize],
+        slots: &[usize],
+    ) -> Vec<i64> {
+        let mut vals: Vec<i64> = args
+            .iter()
+            .map(|r| regs.get(r).cloned().unwrap_or(0))
+            .collect();
+        for off in slots {
+            vals.push(self.load(bp as i64 - *off as i64, 8));
+        }
+        vals
+    }
+
+    fn call(&mut self, name: &str, args: &[i64]) -> i64 {
+        let (f, layout) = match self.fns.get(name) {
+            Some(&entry) => entry,
+            None => panic!("{}: undefined function", name),
+        };
+
+        let bp = self.sp;
+        let frame = roundup(f.stacksize, 16);
+        if frame > self.sp {
+            panic!("{}: stack overflow", name);
+        }
+        self.sp -= frame;
+
+        let mut regs: HashMap<usize, i64> = HashMap::new();
+        let labels = &layout.labels;
+        let mut pc = 0;
+        let mut block = 0;
+        let mut from = 0;
+        let ret = loop {
+            if pc == f.ir.len() {
+                break 0;
+            }
+            if pc > 0 && layout.blocks[pc - 1] != layout.blocks[pc] {
+                from = block;
+            }
+            block = layout.blocks[pc];
+            let ir = &f.ir[pc];
+            pc += 1;
+
+            use self::IROp::*;
+            let lhs = ir.lhs.unwrap_or(0);
+            let rhs = ir.rhs.unwrap_or(0);
+            let imm = rhs as i64;
+            let a = regs.get(&lhs).cloned().unwrap_or(0);
+            let b = regs.get(&rhs).cloned().unwrap_or(0);
+
+            let val = match ir.op {
+                Imm => imm,
+                Mov => b,
+                Add => a.wrapping_add(b),
+                AddImm => a.wrapping_add(imm),
+                Sub => a.wrapping_sub(b),
+                SubImm => a.wrapping_sub(imm),
+                Mul => a.wrapping_mul(b),
+                MulImm => a.wrapping_mul(imm),
+                Div | Mod | UDiv | UMod if b == 0 => panic!("{}: division by zero", name),
+                Div => a.wrapping_div(b),
+                Mod => a.wrapping_rem(b),
+                UDiv => (a as u64 / b as u64) as i64,
+                UMod => (a as u64 % b as u64) as i64,
+                EQ => (a == b) as i64,
+                NE => (a != b) as i64,
+                LT => (a < b) as i64,
+                LE => (a <= b) as i64,
+                ULT => ((a as u64) < b as u64) as i64,
+                ULE => (a as u64 <= b as u64) as i64,
+                AND => a & b,
+                OR => a | b,
+                XOR => a ^ b,
+                SHL => a.wrapping_shl(b as u32),
+                SHR => (a as u64).wrapping_shr(b as u32) as i64,
+                SAR => a.wrapping_shr(b as u32),
+                Neg => a.wrapping_neg(),
+                Trunc => match rhs {
+                    1 => a & 0xff,
+                    4 => a as i32 as i64,
+                    _ => unreachable!(),
+                },
+                Zext => a & 0xffff_ffff,
+                CMov(cond) => {
+                    if regs.get(&cond).cloned().unwrap_or(0) != 0 {
+                        b
+                    } else {
+                        a
+                    }
+                }
+                Bprel => bp as i64 - rhs as i64,
+                Load(size) | VolatileLoad(size) => self.load(b, size),
+                Store(size) | VolatileStore(size) => {
+                    self.store(a, size, b);
+                    continue;
+                }
+                StoreArg(size) => {
+                    let arg = args.get(rhs).cloned().unwrap_or(0);
+                    self.store(bp as i64 - lhs as i64, size, arg);
+                    continue;
+                }
+                Call(ref callee, ref args, ref slots) => {
+                    let vals = self.args(&regs, bp, args, slots);
+                    self.call(callee, &vals)
+                }
+                CallReg(r, ref args, ref slots) => {
+                    let addr = regs.get(&r).cloned().unwrap_or(0) as usize;
+                    let callee: &str = match self.names.get(addr.wrapping_sub(FUNC_BASE)) {
+                        Some(&callee) => callee,
+                        None => panic!("{}: call through a bad pointer", name),
+                    };
+                    let vals = self.args(&regs, bp, args, slots);
+                    self.call(callee, &vals)
+                }
+                LabelAddr(ref name) => match self.names.iter().position(|f| f == name) {
+                    Some(i) => (FUNC_BASE + i) as i64,
+                    None => panic!("{}: global variables are not supported", name),
+                },
+                Jmp => {
+                    pc = labels[&lhs];
+                    continue;
+                }
+                // Any nonzero value is true, as in C.
+                If | Unless => {
+                    if (a != 0) == (ir.op == If) {
+                        pc = labels[&rhs];
+                    }
+                    continue;
+                }
+                IfEq(r) => {
+                    if a == regs.get(&r).cloned().unwrap_or(0) {
+                        pc = labels[&rhs];
+                    }
+                    continue;
+                }
+                // The PHIs at the start of a block all read their operands
+                // before any of them is written.
+                Phi(_) => {
+                    let mut vals = vec![];
+                    pc -= 1;
+                    while let Some(Phi(args)) = f.ir.get(pc).map(|ir| &ir.op) {
+                        let r = match args.iter().find(|&&(b, _)| b == from) {
+                            Some(&(_, r)) => r,
+                            None => panic!("{}: PHI has no value for B{}", name, from),
+                        };
+                        vals.push((f.ir[pc].lhs.unwrap(), regs.get(&r).cloned().unwrap_or(0)));
+                        pc += 1;
+                    }
+                    regs.extend(vals);
+                    continue;
+                }
+                Return => break a,
+                Asm(_) => panic!("{}: inline assembly cannot be interpreted", name),
+                Trap => panic!("{}: trap", name),
+                Label | Kill | Nop => continue,
+            };
+            regs.insert(lhs, val);
+        };
+
+        self.sp = bp;
+        ret
+    }
+}
+
+// Calls the function `name` with `args` and returns its return value.
+// Parameters for which no argument is given are 0, so `main` can be run
+// with or without arguments.
+pub fn run(fns: &[Function], name: &str, args: &[i64]) -> i64 {
+    let layouts: Vec<Layout> = fns.iter().map(layout).collect();
+    let mut interp = Interp {
+        fns: fns
+            .iter()
+            .zip(&layouts)
+            .map(|(f, l)| (f.name.as_str(), (f, l)))
+            .collect(),
+        names: fns.iter().map(|f| f.name.as_str()).collect(),
+        mem: vec![0; STACK_SIZE],
+        sp: STACK_SIZE,
+    };
+    interp.call(name, args)
+}
@@ -1,10 +1,13 @@
+use backend::Backend;
 use gen_ir::{Function, IROp, IRType, IR};
+use Var;
 
-use std::fmt;
+use alloc::string::String;
+use core::fmt;
 
 #[derive(Clone, Debug)]
 pub struct IRInfo {
-    name: &'static str,
+    pub name: &'static str,
     pub ty: IRType,
 }
 
@@ -21,7 +24,9 @@ impl<'a> From<&'a IROp> for IRInfo {
             Add => IRInfo::new("ADD", IRType::RegReg),
             AddImm => IRInfo::new("ADD", IRType::RegImm),
             Call(_, _, _) => IRInfo::new("CALL", IRType::Call),
+            CallReg(_, _, _) => IRInfo::new("CALL", IRType::Call),
             Div => IRInfo::new("DIV", IRType::RegReg),
+            UDiv => IRInfo::new("UDIV", IRType::RegReg),
             Imm => IRInfo::new("MOV", IRType::RegImm),
             Jmp => IRInfo::new("JMP", IRType::Jmp),
             Kill => IRInfo::new("KILL", IRType::Reg),
@@ -31,13 +36,20 @@ impl<'a> From<&'a IROp> for IRInfo {
             NE => IRInfo::new("NE", IRType::RegReg),
             LE => IRInfo::new("LE", IRType::RegReg),
             LT => IRInfo::new("LT", IRType::RegReg),
+            ULE => IRInfo::new("ULE", IRType::RegReg),
+            ULT => IRInfo::new("ULT", IRType::RegReg),
             AND => IRInfo::new("AND", IRType::RegReg),
             OR => IRInfo::new("OR", IRType::RegReg),
             XOR => IRInfo::new("XOR", IRType::RegReg),
             SHL => IRInfo::new("SHL", IRType::RegReg),
             SHR => IRInfo::new("SHR", IRType::RegReg),
+            SAR => IRInfo::new("SAR", IRType::RegReg),
             Mod => IRInfo::new("MOD", IRType::RegReg),
+            UMod => IRInfo::new("UMOD", IRType::RegReg),
             Neg => IRInfo::new("NEG", IRType::Reg),
+            Trunc => IRInfo::new("TRUNC", IRType::RegImm),
+            Zext => IRInfo::new("ZEXT", IRType::RegImm),
+            CMov(_) => IRInfo::new("CMOV", IRType::CMov),
             Load(_) => IRInfo::new("LOAD", IRType::Mem),
             Mov => IRInfo::new("MOV", IRType::RegReg),
             Mul => IRInfo::new("MUL", IRType::RegReg),
@@ -45,68 +57,177 @@ impl<'a> From<&'a IROp> for IRInfo {
             Nop => IRInfo::new("NOP", IRType::Noarg),
             Return => IRInfo::new("RET", IRType::Reg),
             Store(_) => IRInfo::new("STORE", IRType::Mem),
+            VolatileLoad(_) => IRInfo::new("VLOAD", IRType::Mem),
+            VolatileStore(_) => IRInfo::new("VSTORE", IRType::Mem),
             StoreArg(_) => IRInfo::new("STORE_ARG", IRType::StoreArg),
             Sub => IRInfo::new("SUB", IRType::RegReg),
             SubImm => IRInfo::new("SUB", IRType::RegImm),
             Bprel => IRInfo::new("BPREL", IRType::RegImm),
             If => IRInfo::new("IF", IRType::RegLabel),
             Unless => IRInfo::new("UNLESS", IRType::RegLabel),
+            IfEq(_) => IRInfo::new("IF_EQ", IRType::RegRegLabel),
+            Phi(_) => IRInfo::new("PHI", IRType::Phi),
+            Asm(_) => IRInfo::new("ASM", IRType::Asm),
+            Trap => IRInfo::new("TRAP", IRType::Noarg),
         }
     }
 }
 
+// Instructions are indented, except with `{:#}`. Printing allocates
+// nothing, so large functions can be dumped cheaply.
 impl fmt::Display for IR {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use self::IRType::*;
 
         let info = &IRInfo::from(&self.op);
 
-        let lhs = self.lhs.unwrap();
+        let lhs = self.lhs.unwrap_or(0);
+        if info.ty != Label && !f.alternate() {
+            f.write_str("  ")?;
+        }
         match info.ty {
             Label => write!(f, ".L{}:", lhs),
             LabelAddr => match self.op {
-                IROp::LabelAddr(ref name) => write!(f, "  {} r{}, {}", info.name, lhs, name),
+                IROp::LabelAddr(ref name) => write!(f, "{} r{}, {}", info.name, lhs, name),
                 _ => unreachable!(),
             },
-            Imm => write!(f, "  {} {}", info.name, lhs),
-            Reg => write!(f, "  {} r{}", info.name, lhs),
-            Jmp => write!(f, "  {} .L{}", info.name, lhs),
-            RegReg => write!(f, "  {} r{}, r{}", info.name, lhs, self.rhs.unwrap()),
+            Imm => write!(f, "{} {}", info.name, lhs),
+            Reg => write!(f, "{} r{}", info.name, lhs),
+            Jmp => write!(f, "{} .L{}", info.name, lhs),
+            RegReg => write!(f, "{} r{}, r{}", info.name, lhs, self.rhs.unwrap()),
             Mem | StoreArg => match self.op {
-                IROp::Load(ref size) | IROp::Store(ref size) => {
-                    write!(f, "  {}{} r{}, {}", info.name, size, lhs, self.rhs.unwrap())
+                IROp::Load(ref size)
+                | IROp::Store(ref size)
+                | IROp::VolatileLoad(ref size)
+                | IROp::VolatileStore(ref size) => {
+                    write!(f, "{}{} r{}, r{}", info.name, size, lhs, self.rhs.unwrap())
                 }
                 IROp::StoreArg(ref size) => {
-                    write!(f, "  {}{} {}, {}", info.name, size, lhs, self.rhs.unwrap())
+                    write!(f, "{}{} {}, {}", info.name, size, lhs, self.rhs.unwrap())
+                }
+                _ => unreachable!(),
+            },
+            RegImm => write!(f, "{} r{}, {}", info.name, lhs, self.rhs.unwrap() as i64),
+            RegLabel => write!(f, "{} r{}, .L{}", info.name, lhs, self.rhs.unwrap()),
+            Call => {
+                let (args, slots) = match self.op {
+                    IROp::Call(ref name, ref args, ref slots) => {
+                        write!(f, "r{} = {}(", lhs, name)?;
+                        (args, slots)
+                    }
+                    IROp::CallReg(r, ref args, ref slots) => {
+                        write!(f, "r{} = *r{}(", lhs, r)?;
+                        (args, slots)
+                    }
+                    _ => unreachable!(),
+                };
+                // Arguments passed on the stack are shown as the frame
+                // slots they were stored in.
+                for (i, arg) in args.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "r{}", arg)?;
                 }
+                for (i, off) in slots.iter().enumerate() {
+                    if i != 0 || !args.is_empty() {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "[bp-{}]", off)?;
+                }
+                f.write_str(")")
+            }
+            CMov => match self.op {
+                IROp::CMov(cond) => write!(
+                    f,
+                    "{} r{}, r{}, r{}",
+                    info.name,
+                    lhs,
+                    self.rhs.unwrap(),
+                    cond
+                ),
                 _ => unreachable!(),
             },
-            RegImm => write!(f, "  {} r{}, {}", info.name, lhs, self.rhs.unwrap() as i32),
-            RegLabel => write!(f, "  {} r{}, .L{}", info.name, lhs, self.rhs.unwrap()),
-            Call => match self.op {
-                IROp::Call(ref name, nargs, args) => {
-                    let mut sb: String = format!("  r{} = {}(", lhs, name);
-                    for (i, arg) in args.iter().enumerate().take(nargs) {
-                        if i != 0 {
-                            sb.push_str(&", ".to_string());
-                        }
-                        sb.push_str(&format!("r{}", *arg));
+            RegRegLabel => match self.op {
+                IROp::IfEq(r) => {
+                    write!(f, "{} r{}, r{}, .L{}", info.name, lhs, r, self.rhs.unwrap())
+                }
+                _ => unreachable!(),
+            },
+            // Like "PHI r3, [r1, B1], [r2, B2]".
+            Phi => match self.op {
+                IROp::Phi(ref args) => {
+                    write!(f, "{} r{}", info.name, lhs)?;
+                    for &(block, r) in args {
+                        write!(f, ", [r{}, B{}]", r, block)?;
                     }
-                    sb.push_str(")");
-                    write!(f, "{}", sb)
+                    Ok(())
                 }
                 _ => unreachable!(),
             },
-            Noarg => write!(f, "  {}", info.name),
+            Noarg => write!(f, "{}", info.name),
+            Asm => match self.op {
+                IROp::Asm(ref text) => write!(f, "{} {:?}", info.name, text),
+                _ => unreachable!(),
+            },
         }
     }
 }
 
-pub fn dump_ir(fns: &[Function]) {
+// Writes the IR of `fns` to `out` one instruction at a time. With
+// `numbered`, each instruction is prefixed with its index within the
+// function, which makes it easier to compare dumps taken before and
+// after a pass.
+pub fn write_ir<W: fmt::Write>(out: &mut W, fns: &[Function], numbered: bool) -> fmt::Result {
     for f in fns {
-        eprintln!("{}(): ", f.name);
-        for ir in &f.ir {
-            eprintln!("{}", ir);
+        writeln!(out, "{}() [stack={}]:", f.name, f.stacksize)?;
+        for (i, ir) in f.ir.iter().enumerate() {
+            if numbered {
+                writeln!(out, "{:04}: {:#}", i, ir)?;
+            } else {
+                writeln!(out, "{}", ir)?;
+            }
         }
     }
+    Ok(())
+}
+
+pub fn format_ir(fns: &[Function]) -> String {
+    let mut sb = String::new();
+    write_ir(&mut sb, fns, false).unwrap();
+    sb
+}
+
+pub fn format_ir_numbered(fns: &[Function]) -> String {
+    let mut sb = String::new();
+    write_ir(&mut sb, fns, true).unwrap();
+    sb
+}
+
+// Emits the IR as text instead of assembly.
+pub struct IRDumper;
+
+impl Backend for IRDumper {
+    fn emit(&self, _globals: &[Var], fns: &[Function]) -> String {
+        format_ir(fns)
+    }
+}
+
+// Adapts stderr to fmt::Write. Writes are buffered, since an
+// instruction is printed in several pieces.
+#[cfg(feature = "std")]
+struct Stderr(::std::io::BufWriter<::std::io::Stderr>);
+
+#[cfg(feature = "std")]
+impl fmt::Write for Stderr {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        use std::io::Write;
+        self.0.write_all(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+#[cfg(feature = "std")]
+pub fn dump_ir(fns: &[Function], numbered: bool) {
+    let mut out = Stderr(::std::io::BufWriter::new(::std::io::stderr()));
+    write_ir(&mut out, fns, numbered).unwrap();
 }
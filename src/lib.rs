@@ -1,18 +1,60 @@
-#![feature(core_intrinsics, dbg_macro, drain_filter, exclusive_range_pattern)]
+#![feature(core_intrinsics, drain_filter, exclusive_range_pattern)]
+#![cfg_attr(feature = "std", feature(dbg_macro))]
+#![cfg_attr(not(feature = "std"), no_std)]
 
+// Without the "std" feature only the IR, the AST it is generated from
+// and the IR printer are built, on top of `alloc`.
+#[cfg(not(feature = "std"))]
+#[macro_use]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate core;
+#[cfg(feature = "std")]
+extern crate std as alloc;
+
+pub mod backend;
+#[cfg(feature = "std")]
+pub mod cfg;
+#[cfg(feature = "std")]
+pub mod driver;
 pub mod gen_ir;
+#[cfg(feature = "std")]
+pub mod gen_wasm;
+#[cfg(feature = "std")]
 pub mod gen_x86;
+#[cfg(feature = "std")]
+pub mod interp;
 pub mod irdump;
+#[cfg(feature = "std")]
+pub mod llvmdump;
+#[cfg(feature = "std")]
+pub mod opt;
 pub mod parse;
+#[cfg(feature = "std")]
 pub mod preprocess;
+#[cfg(feature = "std")]
 pub mod regalloc;
+#[cfg(feature = "std")]
 pub mod sema;
+#[cfg(feature = "std")]
+pub mod ssa;
+pub mod target;
+#[cfg(feature = "std")]
 pub mod token;
 mod util;
+#[cfg(feature = "std")]
+pub mod validate;
 
+#[cfg(feature = "std")]
 #[macro_use]
 extern crate lazy_static;
 
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+#[cfg(feature = "std")]
 const REGS_N: usize = 7;
 
 // Token type
@@ -29,7 +71,11 @@ pub enum TokenType {
     Int,                 // "int"
     Char,                // "char"
     Void,                // "void"
+    Unsigned,            // "unsigned"
+    Volatile,            // "volatile"
+    Restrict,            // "restrict"
     Struct,              // "struct"
+    Enum,                // "enum"
     Plus,                // +
     Minus,               // -
     Mul,                 // *
@@ -49,6 +95,11 @@ pub enum TokenType {
     Do,                  // "do"
     While,               // "while"
     Break,               // "break"
+    Continue,            // "continue"
+    Asm,                 // "asm"
+    Switch,              // "switch"
+    Case,                // "case"
+    Default,             // "default"
     EQ,                  // ==
     NE,                  // !=
     LE,                  // <=
@@ -82,6 +133,7 @@ pub enum TokenType {
     Mod,                 // %
     Return,              // "return"
     Sizeof,              // "sizeof"
+    Typeof,              // "typeof" or "__typeof__"
     Alignof,             // "_Alignof"
     NewLine,             // preprocessor-only token
 }
@@ -97,8 +149,9 @@ pub enum CharacterType {
     Unknown(char),
 }
 
+#[cfg(feature = "std")]
 impl TokenType {
-    fn new_single_letter(c: char) -> Option<Self> {
+    pub(crate) fn new_single_letter(c: char) -> Option<Self> {
         use self::TokenType::*;
         match c {
             '+' => Some(Plus),
@@ -133,12 +186,16 @@ impl TokenType {
 #[derive(Debug, Clone)]
 pub enum Ctype {
     Int,
+    Uint,
+    Long,
+    Ulong,
     Char,
     Void,
     Ptr(Box<Type>),           // ptr of
     Ary(Box<Type>, usize),    // ary of, len
     Struct(Vec<parse::Node>), // members
     Func(Box<Type>),
+    Typeof(Box<parse::Node>), // Type of the expression, filled in by sema
 }
 
 impl Default for Ctype {
@@ -152,6 +209,10 @@ pub struct Type {
     pub ty: Ctype,
     pub size: usize,  // sizeof
     pub align: usize, // alignof
+    pub volatile: bool,
+    // A restrict pointer is the only way its target is accessed, so
+    // accesses through it do not alias any other.
+    pub restrict: bool,
 }
 
 impl Default for Type {
@@ -160,6 +221,8 @@ impl Default for Type {
             ty: Ctype::default(),
             size: 4,
             align: 4,
+            volatile: false,
+            restrict: false,
         }
     }
 }
@@ -170,19 +233,96 @@ pub enum Scope {
     Global(String, usize, bool), // data, len, is_extern
 }
 
+// Only sema creates variables, so without std nothing reads them.
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
 #[derive(Debug, Clone)]
 pub struct Var {
     ty: Box<Type>,
     pub name: String,
     pub scope: Scope,
+    // A struct parameter passed as a pointer to the caller's copy.
+    by_ref: bool,
 }
 
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
 impl Var {
     fn new(ty: Box<Type>, name: String, scope: Scope) -> Self {
-        Var { ty, name, scope }
+        Var {
+            ty,
+            name,
+            scope,
+            by_ref: false,
+        }
     }
 
     fn new_global(ty: Box<Type>, name: String, data: String, len: usize, is_extern: bool) -> Self {
         Var::new(ty, name.clone(), Scope::Global(data, len, is_extern))
     }
 }
+
+// A range of source characters that a diagnostic refers to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub filename: String,
+    pub line: usize,  // 1-origin
+    pub start: usize, // Offset of the first character
+    pub end: usize,   // Offset one past the last character
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note, // Information about the other diagnostics
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>,
+    pub suggestion: Option<String>, // Source text that was probably meant
+}
+
+impl fmt::Display for Diagnostic {
+    // Formatted like "foo.c:3: warning: message".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref span) = self.span {
+            write!(f, "{}:{}: ", span.filename, span.line)?;
+        }
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        };
+        write!(f, "{}: {}", severity, self.message)?;
+        if let Some(ref suggestion) = self.suggestion {
+            write!(f, "; did you mean {}?", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileError {
+    // An inconsistency in the IR of a function. Unlike a Diagnostic,
+    // this is a bug in the compiler rather than in the program being
+    // compiled.
+    InvalidIR { function: String, message: String },
+    // An expression without an address, such as `(a ? b : c)`, used
+    // where an lvalue is required. Holds what kind of expression it is.
+    NotAnLvalue(String),
+}
+
+impl fmt::Display for CompileError {
+    // Formatted like "main(): message" or "not an lvalue: call to f()".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CompileError::InvalidIR {
+                ref function,
+                ref message,
+            } => write!(f, "{}(): {}", function, message),
+            CompileError::NotAnLvalue(ref what) => write!(f, "not an lvalue: {}", what),
+        }
+    }
+}
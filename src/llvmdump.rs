@@ -0,0 +1,337 @@
+use cfg::{basic_blocks, BlockId};
+use gen_ir::{Function, IROp, IR};
+use ssa::to_ssa;
+
+use std::collections::HashMap;
+
+// Prints the IR in a form that resembles LLVM IR, for readers who know
+// that better than the register machine -dump-ir1 shows.
+//
+// The function is first put in SSA form. Since the IR is two-address, a
+// register may still be assigned several times within a block, so each
+// assignment gets a value of its own: %r3 for the first one, %r3.1 for
+// the second and so on. A register read in a block that has not assigned
+// it yet stands for its last value, which is the one that flows out of
+// the block that assigns it.
+//
+// Every value is an i64, including addresses and the results of
+// comparisons, and %bp is the frame pointer. The output is meant to be
+// read, not to be fed to LLVM.
+
+struct Printer {
+    out: String,
+    labels: HashMap<usize, BlockId>, // Label to the block it starts
+    defs: HashMap<usize, usize>,     // Assignments of each register in all
+    seen: HashMap<usize, usize>,     // Assignments printed so far
+    block: HashMap<usize, usize>,    // Latest value assigned in this block
+    tmps: usize,
+}
+
+fn size_ty(size: u8) -> String {
+    format!("i{}", size as usize * 8)
+}
+
+fn name(r: usize, version: usize) -> String {
+    match version {
+        0 => format!("%r{}", r),
+        v => format!("%r{}.{}", r, v),
+    }
+}
+
+impl Printer {
+    // The value of `r` that leaves the block assigning it.
+    fn out_val(&self, r: usize) -> String {
+        name(r, self.defs.get(&r).map_or(0, |&n| n - 1))
+    }
+
+    // The value a read of `r` sees.
+    fn val(&self, r: usize) -> String {
+        match self.block.get(&r) {
+            Some(&v) => name(r, v),
+            None => self.out_val(r),
+        }
+    }
+
+    // Names the next value of `r`.
+    fn def(&mut self, r: usize) -> String {
+        let v = self.seen.entry(r).or_insert(0);
+        self.block.insert(r, *v);
+        *v += 1;
+        name(r, *v - 1)
+    }
+
+    fn tmp(&mut self) -> String {
+        self.tmps += 1;
+        format!("%t{}", self.tmps)
+    }
+
+    fn line(&mut self, s: String) {
+        self.out.push_str("  ");
+        self.out.push_str(&s);
+        self.out.push('\n');
+    }
+
+    fn binop(&mut self, op: &str, r: usize, rhs: String) {
+        let lhs = self.val(r);
+        let d = self.def(r);
+        self.line(format!("{} = {} i64 {}, {}", d, op, lhs, rhs));
+    }
+
+    fn br(&mut self, cond: String, then: BlockId, els: BlockId) {
+        self.line(format!(
+            "br i64 {}, label %b{}, label %b{}",
+            cond, then, els
+        ));
+    }
+
+    fn call(&mut self, r: usize, callee: String, args: &[usize], slots: &[usize]) {
+        let mut args: Vec<String> = args
+            .iter()
+            .map(|&a| format!("i64 {}", self.val(a)))
+            .collect();
+        for off in slots {
+            let addr = self.tmp();
+            self.line(format!("{} = sub i64 %bp, {}", addr, off));
+            let t = self.tmp();
+            self.line(format!("{} = load i64, ptr {}", t, addr));
+            args.push(format!("i64 {}", t));
+        }
+        let d = self.def(r);
+        self.line(format!("{} = call i64 {}({})", d, callee, args.join(", ")));
+    }
+
+    fn ir(&mut self, ir: &IR, next: BlockId) {
+        use self::IROp::*;
+
+        let lhs = ir.lhs.unwrap_or(0);
+        let rhs = ir.rhs.unwrap_or(0);
+        match ir.op {
+            Imm => {
+                let d = self.def(lhs);
+                self.line(format!("{} = add i64 0, {}", d, rhs as i64));
+            }
+            Mov => {
+                let s = self.val(rhs);
+                let d = self.def(lhs);
+                self.line(format!("{} = add i64 {}, 0", d, s));
+            }
+            Bprel => {
+                let d = self.def(lhs);
+                self.line(format!("{} = sub i64 %bp, {}", d, rhs));
+            }
+            Add | Sub | Mul | Div | UDiv | Mod | UMod | AND | OR | XOR | SHL | SHR | SAR => {
+                let op = match ir.op {
+                    Add => "add",
+                    Sub => "sub",
+                    Mul => "mul",
+                    Div => "sdiv",
+                    UDiv => "udiv",
+                    Mod => "srem",
+                    UMod => "urem",
+                    AND => "and",
+                    OR => "or",
+                    XOR => "xor",
+                    SHL => "shl",
+                    SHR => "lshr",
+                    _ => "ashr",
+                };
+                let r = self.val(rhs);
+                self.binop(op, lhs, r);
+            }
+            AddImm => self.binop("add", lhs, (rhs as i32).to_string()),
+            SubImm => self.binop("sub", lhs, (rhs as i32).to_string()),
+            MulImm => self.binop("mul", lhs, (rhs as i32).to_string()),
+            EQ => self.binop("icmp eq", lhs, self.val(rhs)),
+            NE => self.binop("icmp ne", lhs, self.val(rhs)),
+            LT => self.binop("icmp slt", lhs, self.val(rhs)),
+            LE => self.binop("icmp sle", lhs, self.val(rhs)),
+            ULT => self.binop("icmp ult", lhs, self.val(rhs)),
+            ULE => self.binop("icmp ule", lhs, self.val(rhs)),
+            Neg => {
+                let s = self.val(lhs);
+                let d = self.def(lhs);
+                self.line(format!("{} = sub i64 0, {}", d, s));
+            }
+            Trunc | Zext => {
+                let (ty, ext) = match (&ir.op, rhs) {
+                    (&Trunc, 1) => ("i8", "zext"),
+                    (&Trunc, _) => ("i32", "sext"),
+                    _ => ("i32", "zext"),
+                };
+                let s = self.val(lhs);
+                let t = self.tmp();
+                self.line(format!("{} = trunc i64 {} to {}", t, s, ty));
+                let d = self.def(lhs);
+                self.line(format!("{} = {} {} {} to i64", d, ext, ty, t));
+            }
+            CMov(cond) => {
+                let (c, s, old) = (self.val(cond), self.val(rhs), self.val(lhs));
+                let d = self.def(lhs);
+                self.line(format!("{} = select i64 {}, i64 {}, i64 {}", d, c, s, old));
+            }
+            Load(size) | VolatileLoad(size) => {
+                let vol = if let VolatileLoad(_) = ir.op {
+                    "volatile "
+                } else {
+                    ""
+                };
+                let addr = self.val(rhs);
+                let d = self.def(lhs);
+                self.line(format!(
+                    "{} = load {}{}, ptr {}",
+                    d,
+                    vol,
+                    size_ty(size),
+                    addr
+                ));
+            }
+            Store(size) | VolatileStore(size) => {
+                let vol = if let VolatileStore(_) = ir.op {
+                    "volatile "
+                } else {
+                    ""
+                };
+                let (addr, val) = (self.val(lhs), self.val(rhs));
+                self.line(format!(
+                    "store {}{} {}, ptr {}",
+                    vol,
+                    size_ty(size),
+                    val,
+                    addr
+                ));
+            }
+            StoreArg(size) => {
+                let t = self.tmp();
+                self.line(format!("{} = sub i64 %bp, {}", t, lhs));
+                self.line(format!("store {} %a{}, ptr {}", size_ty(size), rhs, t));
+            }
+            LabelAddr(ref label) => {
+                let d = self.def(lhs);
+                self.line(format!("{} = ptrtoint ptr @{} to i64", d, label));
+            }
+            Call(ref callee, ref args, ref slots) => {
+                self.call(lhs, format!("@{}", callee), args, slots)
+            }
+            CallReg(r, ref args, ref slots) => {
+                let callee = self.val(r);
+                self.call(lhs, callee, args, slots)
+            }
+            Return => {
+                let s = self.val(lhs);
+                self.line(format!("ret i64 {}", s));
+            }
+            Jmp => {
+                let target = self.labels[&lhs];
+                self.line(format!("br label %b{}", target));
+            }
+            If => {
+                let c = self.val(lhs);
+                let target = self.labels[&rhs];
+                self.br(c, target, next);
+            }
+            Unless => {
+                let c = self.val(lhs);
+                let target = self.labels[&rhs];
+                self.br(c, next, target);
+            }
+            IfEq(r) => {
+                let (a, b) = (self.val(lhs), self.val(r));
+                let t = self.tmp();
+                self.line(format!("{} = icmp eq i64 {}, {}", t, a, b));
+                let target = self.labels[&rhs];
+                self.br(t, target, next);
+            }
+            Phi(ref args) => {
+                let args: Vec<String> = args
+                    .iter()
+                    .map(|&(block, r)| format!("[ {}, %b{} ]", self.out_val(r), block))
+                    .collect();
+                let d = self.def(lhs);
+                self.line(format!("{} = phi i64 {}", d, args.join(", ")));
+            }
+            Trap => self.line("unreachable".to_string()),
+            Asm(ref text) => self.line(format!("call void asm sideeffect {:?}, \"\"()", text)),
+            Label | Kill | Nop => (),
+        }
+    }
+}
+
+fn write_function(out: &mut String, f: &Function) {
+    let mut f = f.clone();
+    to_ssa(&mut f);
+
+    let blocks = basic_blocks(&f);
+    let nparams =
+        f.ir.iter()
+            .filter_map(|ir| match ir.op {
+                IROp::StoreArg(_) => Some(ir.rhs.unwrap() + 1),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+
+    let mut p = Printer {
+        out: String::new(),
+        labels: HashMap::new(),
+        defs: HashMap::new(),
+        seen: HashMap::new(),
+        block: HashMap::new(),
+        tmps: 0,
+    };
+    for (id, b) in blocks.iter().enumerate() {
+        if f.ir[b.start].op == IROp::Label {
+            p.labels.insert(f.ir[b.start].lhs.unwrap(), id);
+        }
+    }
+    for r in f.ir.iter().filter_map(|ir| ir.def()) {
+        *p.defs.entry(r).or_insert(0) += 1;
+    }
+
+    let params: Vec<String> = (0..nparams).map(|i| format!("i64 %a{}", i)).collect();
+    p.out.push_str(&format!(
+        "define i64 @{}({}) {{\n",
+        f.name,
+        params.join(", ")
+    ));
+
+    // LLVM wants every block to end with a terminator, so falling
+    // through becomes an explicit branch. Falling off the end of the
+    // function returns 0, and a conditional jump at the very end needs
+    // a block that does so.
+    let n = blocks.len();
+    let mut ret_block = false;
+    for (id, b) in blocks.iter().enumerate() {
+        p.out.push_str(&format!("b{}:\n", id));
+        p.block.clear();
+        for ir in &f.ir[b.start..b.end] {
+            p.ir(ir, id + 1);
+        }
+        match f.ir[b.end - 1].op {
+            IROp::Jmp | IROp::Return | IROp::Trap => (),
+            IROp::If | IROp::Unless | IROp::IfEq(_) => ret_block |= id + 1 == n,
+            _ if id + 1 < n => p.line(format!("br label %b{}", id + 1)),
+            _ => p.line("ret i64 0".to_string()),
+        }
+    }
+    if ret_block {
+        p.out.push_str(&format!("b{}:\n", n));
+        p.line("ret i64 0".to_string());
+    }
+    p.out.push_str("}\n");
+    out.push_str(&p.out);
+}
+
+pub fn format_llvm(fns: &[Function]) -> String {
+    let mut out = String::new();
+    for (i, f) in fns.iter().enumerate() {
+        if i != 0 {
+            out.push('\n');
+        }
+        write_function(&mut out, f);
+    }
+    out
+}
+
+pub fn dump_llvm(fns: &[Function]) {
+    eprint!("{}", format_llvm(fns));
+}
@@ -1,61 +1,62 @@
 extern crate r9cc;
 
-use r9cc::gen_ir::gen_ir;
-use r9cc::gen_x86::gen_x86;
-use r9cc::irdump::dump_ir;
-use r9cc::parse::parse;
-use r9cc::preprocess::Preprocessor;
-use r9cc::regalloc::alloc_regs;
-use r9cc::sema::sema;
-use r9cc::token::tokenize;
+use r9cc::driver::{compile_file_with, Options};
+use r9cc::target::Target;
 
 use std::env;
+use std::fs;
+use std::path::Path;
 use std::process;
 
 fn usage() -> ! {
-    eprintln!("Usage: 9cc [-dump-ir1] [-dump-ir2] <file>");
+    eprintln!("Usage: 9cc [-dump-ir1] [-dump-ir2] [-dump-llvm] [-number-ir] [-canonical-regs] [-wasm] [-canary] [-verbose-asm] [-Werror] [-m32] [-max-name-length <n>] [-nested-comments] [-map <file>] <file>");
     process::exit(1)
 }
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() == 1 {
-        usage();
-    }
-
-    let mut dump_ir1 = false;
-    let mut dump_ir2 = false;
-    let path;
-
-    if args.len() == 3 && args[1] == "-dump-ir1" {
-        dump_ir1 = true;
-        path = args[2].clone();
-    } else if args.len() == 3 && args[1] == "-dump-ir2" {
-        dump_ir2 = true;
-        path = args[2].clone();
-    } else {
-        if args.len() != 2 {
-            usage();
+    let mut opts = Options::default();
+    let mut map = None;
+    let mut path = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-dump-ir1" => opts.dump_ir1 = true,
+            "-dump-ir2" => opts.dump_ir2 = true,
+            "-dump-llvm" => opts.dump_llvm = true,
+            "-number-ir" => opts.number_ir = true,
+            "-canonical-regs" => opts.canonical_regs = true,
+            "-wasm" => opts.wasm = true,
+            "-canary" => opts.canary = true,
+            "-verbose-asm" => opts.verbose_asm = true,
+            "-Werror" => opts.warnings_as_errors = true,
+            "-m32" => opts.target = Target::ilp32(),
+            "-max-name-length" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(n) => opts.max_name_length = Some(n),
+                None => usage(),
+            },
+            "-nested-comments" => opts.nested_comments = true,
+            "-map" => map = Some(args.next().unwrap_or_else(|| usage())),
+            _ if arg.starts_with('-') || path.is_some() => usage(),
+            _ => path = Some(arg),
         }
-        path = args[1].clone();
     }
-
-    // Tokenize and parse.
-    let tokens = tokenize(path, &mut Preprocessor::new());
-
-    let nodes = parse(&tokens);
-    let (nodes, globals) = sema(nodes);
-    let mut fns = gen_ir(nodes);
-
-    if dump_ir1 {
-        dump_ir(&fns);
-    }
-
-    alloc_regs(&mut fns);
-
-    if dump_ir2 {
-        dump_ir(&fns);
+    let path = path.unwrap_or_else(|| usage());
+
+    let out = compile_file_with(Path::new(&path), &opts);
+    print!("{}", out.text);
+
+    if let Some(map) = map {
+        let text = match out.map {
+            Some(text) => text,
+            None => {
+                eprintln!("-map is not supported by this backend");
+                process::exit(1);
+            }
+        };
+        if let Err(err) = fs::write(&map, text) {
+            eprintln!("{}: {}", map, err);
+            process::exit(1);
+        }
     }
-
-    gen_x86(globals, fns);
 }
@@ -0,0 +1,966 @@
+// Optimization passes on the IR.
+//
+// These run after gen_ir and before the register allocator, so they
+// still see an infinite number of registers. They must keep the
+// invariant the allocator relies on: a register is killed only after
+// its last use.
+
+use gen_ir::{Function, IROp, IR};
+use util::roundup;
+use REGS_N;
+
+use std::collections::{HashMap, HashSet};
+
+// What a register is known to hold within a basic block.
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Const(i64),
+    Bprel(usize),
+    Label(String),
+    Mem(Box<Value>, u8, usize), // address, size, memory generation
+    Unknown(usize),
+}
+
+struct Values {
+    regs: HashMap<usize, Value>,
+    next_unknown: usize,
+    // Bumped on every store so that loads on either side of it differ.
+    mem_gen: usize,
+}
+
+impl Values {
+    fn new() -> Self {
+        Values {
+            regs: HashMap::new(),
+            next_unknown: 0,
+            mem_gen: 0,
+        }
+    }
+
+    fn unknown(&mut self) -> Value {
+        self.next_unknown += 1;
+        Value::Unknown(self.next_unknown)
+    }
+
+    fn get(&mut self, r: usize) -> Value {
+        if let Some(v) = self.regs.get(&r) {
+            return v.clone();
+        }
+        let v = self.unknown();
+        self.regs.insert(r, v.clone());
+        v
+    }
+
+    // Forget everything at a label, since control may come from elsewhere.
+    fn reset(&mut self) {
+        self.regs.clear();
+        self.mem_gen += 1;
+    }
+
+    fn update(&mut self, ir: &IR) {
+        use self::IROp::*;
+        let v = match ir.op {
+            Label => return self.reset(),
+            Store(_) | VolatileStore(_) | StoreArg(_) => {
+                self.mem_gen += 1;
+                return;
+            }
+            Call(_, _, _) | CallReg(_, _, _) => {
+                self.mem_gen += 1;
+                self.unknown()
+            }
+            // The assembly may write any memory.
+            Asm(_) => {
+                self.mem_gen += 1;
+                return;
+            }
+            Imm => Value::Const(ir.rhs.unwrap() as i64),
+            Bprel => Value::Bprel(ir.rhs.unwrap()),
+            LabelAddr(ref name) => Value::Label(name.clone()),
+            Mov => self.get(ir.rhs.unwrap()),
+            Load(size) => Value::Mem(Box::new(self.get(ir.rhs.unwrap())), size, self.mem_gen),
+            _ => match ir.def() {
+                Some(_) => self.unknown(),
+                None => return,
+            },
+        };
+        self.regs.insert(ir.lhs.unwrap(), v);
+    }
+}
+
+fn imm(r: Option<usize>, val: i64) -> Option<IR> {
+    Some(IR {
+        op: IROp::Imm,
+        lhs: r,
+        rhs: Some(val as usize),
+    })
+}
+
+fn mov(dst: Option<usize>, src: Option<usize>) -> Option<IR> {
+    Some(IR {
+        op: IROp::Mov,
+        lhs: dst,
+        rhs: src,
+    })
+}
+
+// Rewrites x+0, x-0, x*1, x/1, x*0 and x-x. Returns None if the
+// instruction can be dropped.
+fn simplify(ir: IR, vals: &mut Values) -> Option<IR> {
+    use self::IROp::*;
+    use self::Value::Const;
+
+    match ir.op {
+        Add | Sub | Mul | Div | UDiv => {
+            let a = vals.get(ir.lhs.unwrap());
+            let b = vals.get(ir.rhs.unwrap());
+            match ir.op {
+                Add if b == Const(0) => None,
+                Add if a == Const(0) => mov(ir.lhs, ir.rhs),
+                Sub if b == Const(0) => None,
+                Sub if a == b => imm(ir.lhs, 0),
+                Mul if b == Const(1) => None,
+                Mul if a == Const(1) => mov(ir.lhs, ir.rhs),
+                Mul if a == Const(0) || b == Const(0) => imm(ir.lhs, 0),
+                Div | UDiv if b == Const(1) => None,
+                _ => Some(ir),
+            }
+        }
+        AddImm | SubImm if ir.rhs == Some(0) => None,
+        MulImm if ir.rhs == Some(1) => None,
+        MulImm if ir.rhs == Some(0) => imm(ir.lhs, 0),
+        _ => Some(ir),
+    }
+}
+
+fn algebraic_identities(f: &mut Function) {
+    let mut vals = Values::new();
+    let mut v = vec![];
+    for ir in f.ir.drain(..) {
+        if let Some(ir) = simplify(ir, &mut vals) {
+            vals.update(&ir);
+            v.push(ir);
+        }
+    }
+    f.ir = v;
+}
+
+// Whether a register holding `v` already has the value a load of `size`
+// bytes would give.
+fn extended(v: &Value, size: u8) -> bool {
+    match *v {
+        Value::Const(c) => size == 4 || size == 8 || (size == 1 && (0..256).contains(&c)),
+        Value::Mem(_, s, _) => s == size,
+        _ => size == 8,
+    }
+}
+
+// Removes the computation of the address of a load that is about to be
+// replaced, if it is the instruction just before and nothing else reads
+// the address, as in `BPREL r3, 4; LOAD4 r3, r3`.
+fn drop_address(code: &mut Vec<IR>, load: &IR) {
+    let removable = match code.last() {
+        Some(prev) => {
+            load.lhs == load.rhs
+                && prev.def() == load.rhs
+                && matches!(prev.op, IROp::Bprel | IROp::LabelAddr(_))
+        }
+        None => false,
+    };
+    if removable {
+        code.pop();
+    }
+}
+
+// Replaces a load by a copy of a register that already holds the same
+// memory, loaded within the block with no store or call since. A
+// volatile load is never replaced.
+//
+// A load from where the last store went is replaced the same way by the
+// register that was stored, as in `x = a + b; return x + 1;`. The store
+// wrote only `size` bytes of it, so the copy is truncated the way the
+// load would have extended them, and the register's Kill moves after it.
+fn remove_redundant_loads(f: &mut Function) {
+    let mut vals = Values::new();
+    let mut killed = HashSet::new();
+    let mut stored: Vec<(Value, usize)> = vec![];
+    let mut code = vec![];
+    for ir in &f.ir {
+        let mut ir = ir.clone();
+        let mut after = vec![];
+        if let IROp::Load(size) = ir.op {
+            let v = Value::Mem(Box::new(vals.get(ir.rhs.unwrap())), size, vals.mem_gen);
+            let held = vals
+                .regs
+                .iter()
+                .filter(|&(r, val)| *val == v && !killed.contains(r))
+                .map(|(&r, _)| r)
+                .min();
+            let src = stored.iter().find(|&(val, _)| *val == v).map(|&(_, r)| r);
+            if held.is_some() || src.is_some() {
+                drop_address(&mut code, &ir);
+            }
+            if held.is_some() {
+                ir = mov(ir.lhs, held).unwrap();
+            } else if let Some(src) = src {
+                ir = mov(ir.lhs, Some(src)).unwrap();
+                if !extended(&vals.get(src), size) {
+                    after.push(IR {
+                        op: IROp::Trunc,
+                        lhs: ir.lhs,
+                        rhs: Some(size as usize),
+                    });
+                }
+                if killed.remove(&src) {
+                    let kill = code
+                        .iter()
+                        .rposition(|k: &IR| k.op == IROp::Kill && k.lhs == Some(src))
+                        .unwrap();
+                    after.push(code.remove(kill));
+                }
+            }
+        }
+        if let Some(r) = ir.def() {
+            stored.retain(|&(_, s)| s != r);
+        }
+        if ir.op == IROp::Kill {
+            killed.insert(ir.lhs.unwrap());
+        }
+        vals.update(&ir);
+        if let IROp::Store(size) = ir.op {
+            let addr = Box::new(vals.get(ir.lhs.unwrap()));
+            stored.push((Value::Mem(addr, size, vals.mem_gen), ir.rhs.unwrap()));
+        }
+        code.push(ir);
+        // The moved Kill ends the register's life again, so a later load
+        // of the same slot must not be forwarded from it.
+        for ir in after {
+            if let Some(r) = ir.def() {
+                stored.retain(|&(_, s)| s != r);
+            }
+            if ir.op == IROp::Kill {
+                killed.insert(ir.lhs.unwrap());
+            }
+            vals.update(&ir);
+            code.push(ir);
+        }
+    }
+
+    // The register is now kept alive until the copy.
+    if max_pressure(&code) <= REGS_N.max(max_pressure(&f.ir)) {
+        f.ir = code;
+    }
+}
+
+// Turns a conditional jump on a known constant into either an
+// unconditional jump or nothing.
+fn fold_branches(f: &mut Function) {
+    let mut vals = Values::new();
+    let mut v = vec![];
+    for mut ir in f.ir.drain(..) {
+        if ir.op == IROp::If || ir.op == IROp::Unless {
+            if let Value::Const(c) = vals.get(ir.lhs.unwrap()) {
+                if (c != 0) != (ir.op == IROp::If) {
+                    continue;
+                }
+                ir = IR {
+                    op: IROp::Jmp,
+                    lhs: ir.rhs,
+                    rhs: None,
+                };
+            }
+        }
+        vals.update(&ir);
+        v.push(ir);
+    }
+    f.ir = v;
+}
+
+// Drops `MOV r, r`, which does nothing.
+fn remove_self_moves(f: &mut Function) {
+    f.ir.retain(|ir| ir.op != IROp::Mov || ir.lhs != ir.rhs);
+}
+
+// Fuses `NE r, s` and a following `UNLESS r, L` into `IF_EQ r, s, L`,
+// if the result of the comparison is read by nothing else. This saves
+// materializing the 0 or 1 only to test it.
+fn fuse_compare_branch(f: &mut Function) {
+    let mut i = 0;
+    while i < f.ir.len() {
+        let (r, s) = match f.ir[i].op {
+            IROp::NE => (f.ir[i].lhs.unwrap(), f.ir[i].rhs.unwrap()),
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+
+        let mut j = i + 1;
+        while j < f.ir.len() && f.ir[j].op == IROp::Kill && f.ir[j].lhs != Some(r) {
+            j += 1;
+        }
+        if j == f.ir.len() || f.ir[j].op != IROp::Unless || f.ir[j].lhs != Some(r) {
+            i += 1;
+            continue;
+        }
+        let reads = f.ir[j + 1..]
+            .iter()
+            .filter(|ir| ir.op != IROp::Kill && ir.uses().contains(&r))
+            .count();
+        if reads > 0 {
+            i += 1;
+            continue;
+        }
+
+        // s is now read by the jump, so any kill of it moves past it.
+        let kills: Vec<IR> = f.ir[i + 1..j].to_vec();
+        let jump = IR {
+            op: IROp::IfEq(s),
+            lhs: Some(r),
+            rhs: f.ir[j].rhs,
+        };
+        f.ir.splice(i..=j, Some(jump).into_iter().chain(kills));
+        i += 1;
+    }
+}
+
+// Replaces labels that mark the same point as the label before them,
+// e.g. the ends of an `if` and of the `if` enclosing it, with that
+// label. Kills emit no code, so they may sit in between.
+fn merge_labels(f: &mut Function) {
+    let mut alias = HashMap::new();
+    let mut first = None;
+    for ir in &f.ir {
+        match ir.op {
+            IROp::Label => match first {
+                Some(l) => {
+                    alias.insert(ir.lhs.unwrap(), l);
+                }
+                None => first = ir.lhs,
+            },
+            IROp::Kill => (),
+            _ => first = None,
+        }
+    }
+    if alias.is_empty() {
+        return;
+    }
+
+    f.ir.retain(|ir| ir.op != IROp::Label || !alias.contains_key(&ir.lhs.unwrap()));
+    let rename = |l: Option<usize>| l.map(|l| *alias.get(&l).unwrap_or(&l));
+    for ir in &mut f.ir {
+        match ir.op {
+            IROp::Jmp => ir.lhs = rename(ir.lhs),
+            IROp::If | IROp::Unless | IROp::IfEq(_) => ir.rhs = rename(ir.rhs),
+            _ => (),
+        }
+    }
+}
+
+// Drops a jump to a label that control reaches anyway by falling
+// through, such as the `UNLESS r, .L1` of `if (x) ;`. Only Kills and
+// other labels may sit between the jump and its target.
+fn remove_jumps_to_next(f: &mut Function) {
+    let mut i = 0;
+    while i < f.ir.len() {
+        let target = match jump_target(&f.ir[i]) {
+            Some(target) => target,
+            None => {
+                i += 1;
+                continue;
+            }
+        };
+        let next = f.ir[i + 1..]
+            .iter()
+            .take_while(|ir| ir.op == IROp::Kill || ir.op == IROp::Label)
+            .any(|ir| ir.op == IROp::Label && ir.lhs == Some(target));
+        if next {
+            f.ir.remove(i);
+        } else {
+            i += 1;
+        }
+    }
+}
+
+// Removes instructions that control never reaches. Kills are kept,
+// since they mark where registers die rather than doing any work.
+fn remove_unreachable(f: &mut Function) {
+    use self::IROp::*;
+
+    let labels: HashMap<usize, usize> =
+        f.ir.iter()
+            .enumerate()
+            .filter(|&(_, ir)| ir.op == Label)
+            .map(|(i, ir)| (ir.lhs.unwrap(), i))
+            .collect();
+
+    let mut reachable = vec![false; f.ir.len()];
+    let mut worklist = vec![0];
+    while let Some(i) = worklist.pop() {
+        if i >= f.ir.len() || reachable[i] {
+            continue;
+        }
+        reachable[i] = true;
+        let ir = &f.ir[i];
+        match ir.op {
+            Jmp => worklist.push(labels[&ir.lhs.unwrap()]),
+            Return | Trap => (),
+            If | Unless | IfEq(_) => {
+                worklist.push(labels[&ir.rhs.unwrap()]);
+                worklist.push(i + 1);
+            }
+            _ => worklist.push(i + 1),
+        }
+    }
+
+    let mut i = 0;
+    f.ir.retain(|ir| {
+        i += 1;
+        reachable[i - 1] || ir.op == Kill
+    });
+}
+
+// Whether an instruction that defines a register must stay even if the
+// register is never read.
+fn has_side_effects(ir: &IR) -> bool {
+    matches!(
+        ir.op,
+        IROp::Call(_, _, _) | IROp::CallReg(_, _, _) | IROp::VolatileLoad(_)
+    )
+}
+
+// Removes computations whose results are never used, along with the
+// Kills of their registers.
+fn remove_dead_regs(f: &mut Function) {
+    loop {
+        let mut used = HashSet::new();
+        for ir in &f.ir {
+            if ir.op == IROp::Kill {
+                continue;
+            }
+            for r in ir.uses() {
+                // An instruction such as `LOAD r1, r1` does not keep r1
+                // alive, unless the instruction itself always stays.
+                if ir.def() != Some(r) || has_side_effects(ir) {
+                    used.insert(r);
+                }
+            }
+        }
+
+        let len = f.ir.len();
+        f.ir.retain(|ir| match ir.def() {
+            Some(r) => used.contains(&r) || has_side_effects(ir),
+            None => true,
+        });
+        if f.ir.len() == len {
+            break;
+        }
+    }
+
+    let mut live = HashSet::new();
+    for ir in &f.ir {
+        if ir.op != IROp::Kill {
+            live.extend(ir.uses());
+            live.extend(ir.def());
+        }
+    }
+    f.ir.retain(|ir| ir.op != IROp::Kill || live.contains(&ir.lhs.unwrap()));
+}
+
+fn jump_target(ir: &IR) -> Option<usize> {
+    match ir.op {
+        IROp::Jmp => ir.lhs,
+        IROp::If | IROp::Unless | IROp::IfEq(_) => ir.rhs,
+        _ => None,
+    }
+}
+
+// The highest number of registers alive at once, counting the way the
+// register allocator does: from the first appearance to the Kill.
+fn max_pressure(code: &[IR]) -> usize {
+    let mut live = HashSet::new();
+    let mut max = 0;
+    for ir in code {
+        if ir.op == IROp::Kill {
+            live.remove(&ir.lhs.unwrap());
+            continue;
+        }
+        live.extend(ir.uses());
+        live.extend(ir.def());
+        max = max.max(live.len());
+    }
+    max
+}
+
+// Returns the positions of the header label and of the last jump back
+// to it.
+fn loop_bounds(code: &[IR], label: usize) -> Option<(usize, usize)> {
+    let h = code
+        .iter()
+        .position(|ir| ir.op == IROp::Label && ir.lhs == Some(label))?;
+    let e = code.iter().rposition(|ir| jump_target(ir) == Some(label))?;
+    if e > h {
+        Some((h, e))
+    } else {
+        None
+    }
+}
+
+// Loop header labels, innermost loops first.
+fn find_loops(code: &[IR]) -> Vec<usize> {
+    let mut loops = vec![];
+    for ir in code {
+        if ir.op != IROp::Label {
+            continue;
+        }
+        let label = ir.lhs.unwrap();
+        if let Some((h, e)) = loop_bounds(code, label) {
+            loops.push((e - h, label));
+        }
+    }
+    loops.sort();
+    loops.into_iter().map(|(_, label)| label).collect()
+}
+
+fn overlaps(a: (usize, u8), b: (usize, u8)) -> bool {
+    // A local at offset o occupies [rbp-o, rbp-o+size).
+    let (a_start, b_start) = (-(a.0 as i64), -(b.0 as i64));
+    a_start < b_start + b.1 as i64 && b_start < a_start + a.1 as i64
+}
+
+// Moves computations that produce the same value on every iteration of
+// the loop headed by `label` to just before the loop. Their registers
+// stay alive for the whole loop, so they are killed after it.
+fn hoist_invariants(f: &mut Function, label: usize) {
+    use self::IROp::*;
+
+    let (h, e) = match loop_bounds(&f.ir, label) {
+        Some(bounds) => bounds,
+        None => return,
+    };
+    let body = h + 1..e;
+
+    // Code that jumps into the loop from outside would skip the preheader.
+    let labels: HashMap<usize, usize> =
+        f.ir.iter()
+            .enumerate()
+            .filter(|&(_, ir)| ir.op == Label)
+            .map(|(i, ir)| (ir.lhs.unwrap(), i))
+            .collect();
+    for (i, ir) in f.ir.iter().enumerate() {
+        if i >= h && i <= e {
+            continue;
+        }
+        if let Some(pos) = jump_target(ir).and_then(|t| labels.get(&t)) {
+            if *pos >= h && *pos <= e {
+                return;
+            }
+        }
+    }
+
+    // Find which locals the loop writes and which loads read a local.
+    let mut addrs = HashMap::new();
+    let mut stores = vec![];
+    let mut clobbers_all = false;
+    let mut loads = HashMap::new();
+    for i in body.clone() {
+        let ir = &f.ir[i];
+        match ir.op {
+            Label => addrs.clear(),
+            Store(size) => match addrs.get(&ir.lhs.unwrap()) {
+                Some(&offset) => stores.push((offset, size)),
+                None => clobbers_all = true,
+            },
+            Call(_, _, _) | CallReg(_, _, _) | StoreArg(_) | VolatileStore(_) | Asm(_) => {
+                clobbers_all = true
+            }
+            Load(size) => {
+                if let Some(&offset) = addrs.get(&ir.rhs.unwrap()) {
+                    loads.insert(i, (offset, size));
+                }
+            }
+            _ => (),
+        }
+        if let Some(r) = ir.def() {
+            match ir.op {
+                Bprel => addrs.insert(r, ir.rhs.unwrap()),
+                _ => addrs.remove(&r),
+            };
+        }
+    }
+    let unchanged = |i: &usize| {
+        !clobbers_all
+            && loads
+                .get(i)
+                .is_some_and(|&m| stores.iter().all(|&s| !overlaps(m, s)))
+    };
+
+    // Candidates are registers living entirely in straight-line code
+    // inside the loop body.
+    let mut span: HashMap<usize, (usize, usize)> = HashMap::new();
+    for (i, ir) in f.ir.iter().enumerate() {
+        let mut regs = ir.uses();
+        regs.extend(ir.def());
+        if ir.op == Kill {
+            regs.push(ir.lhs.unwrap());
+        }
+        for r in regs {
+            let s = span.entry(r).or_insert((i, i));
+            s.1 = i;
+        }
+    }
+    let mut defs: HashMap<usize, Vec<usize>> = HashMap::new();
+    for i in body.clone() {
+        if let Some(r) = f.ir[i].def() {
+            defs.entry(r).or_default().push(i);
+        }
+    }
+    let mut set: HashSet<usize> = defs
+        .keys()
+        .cloned()
+        .filter(|r| {
+            let (first, last) = span[r];
+            first > h
+                && last < e
+                && f.ir[last].op == Kill
+                && f.ir[first..last]
+                    .iter()
+                    .all(|ir| ir.op != Label && !ir.op.is_terminator())
+        })
+        .collect();
+
+    loop {
+        let invariant = |i: &usize| {
+            let ir = &f.ir[*i];
+            let pure = match ir.op {
+                Imm | Bprel | LabelAddr(_) | Mov | Add | Sub | Mul | AddImm | SubImm | MulImm
+                | Neg | EQ | NE | LE | LT | ULE | ULT | AND | OR | XOR | SHL | SHR | SAR
+                | Trunc | Zext => true,
+                Load(_) => unchanged(i),
+                _ => false,
+            };
+            pure && ir.uses().iter().all(|r| set.contains(r))
+        };
+        // Instructions left in the loop must see the final value.
+        let settled = |r: &usize| {
+            let last_def = *defs[r].last().unwrap();
+            body.clone().all(|i| {
+                let ir = &f.ir[i];
+                i > last_def
+                    || ir.op == Kill
+                    || !ir.uses().contains(r)
+                    || ir.def().is_some_and(|d| set.contains(&d))
+            })
+        };
+        let next: HashSet<usize> = set
+            .iter()
+            .cloned()
+            .filter(|r| defs[r].iter().all(&invariant) && settled(r))
+            .collect();
+        if next.len() == set.len() {
+            break;
+        }
+        set = next;
+    }
+
+    // Moving constants and addresses alone does not pay for the
+    // registers it ties up.
+    let mut hoist: HashSet<usize> = set
+        .iter()
+        .cloned()
+        .filter(|r| {
+            defs[r]
+                .iter()
+                .any(|&i| !matches!(f.ir[i].op, Imm | Bprel | LabelAddr(_)))
+        })
+        .collect();
+    loop {
+        let mut next = hoist.clone();
+        for r in &hoist {
+            for &i in &defs[r] {
+                next.extend(f.ir[i].uses());
+            }
+        }
+        if next.len() == hoist.len() {
+            break;
+        }
+        hoist = next;
+    }
+    if hoist.is_empty() {
+        return;
+    }
+
+    let moved = |ir: &IR| match ir.op {
+        Kill => hoist.contains(&ir.lhs.unwrap()),
+        _ => ir.def().is_some_and(|r| hoist.contains(&r)),
+    };
+    let mut code = f.ir[..h].to_vec();
+    code.extend(
+        f.ir[body.clone()]
+            .iter()
+            .filter(|ir| ir.op != Kill && moved(ir))
+            .cloned(),
+    );
+    code.extend(f.ir[h..e + 1].iter().filter(|ir| !moved(ir)).cloned());
+    let mut kills: Vec<usize> = hoist.into_iter().collect();
+    kills.sort();
+    code.extend(kills.into_iter().map(|r| IR {
+        op: Kill,
+        lhs: Some(r),
+        rhs: None,
+    }));
+    code.extend(f.ir[e + 1..].iter().cloned());
+
+    if max_pressure(&code) <= REGS_N {
+        f.ir = code;
+    }
+}
+
+fn loop_invariant_code_motion(f: &mut Function) {
+    for label in find_loops(&f.ir) {
+        hoist_invariants(f, label);
+    }
+}
+
+// Calls to functions of at most this many instructions are inlined.
+const INLINE_THRESHOLD: usize = 32;
+
+// Only leaf functions are inlined, which also rules out recursion.
+fn is_inlinable(f: &Function) -> bool {
+    f.ir.len() <= INLINE_THRESHOLD && !f.ir.iter().any(is_call)
+}
+
+fn ir(op: IROp, lhs: usize, rhs: Option<usize>) -> IR {
+    IR {
+        op,
+        lhs: Some(lhs),
+        rhs,
+    }
+}
+
+// Replaces calls to small functions with a copy of their code. The
+// callee's locals get their own space at the bottom of the caller's
+// frame, its arguments are stored there the way the callee's prologue
+// would, and a return becomes a move to the call's destination register
+// followed by a jump past the copy.
+fn inline_calls(fns: &mut [Function]) {
+    let callees: HashMap<String, Function> = fns
+        .iter()
+        .filter(|f| is_inlinable(f))
+        .map(|f| (f.name.clone(), f.clone()))
+        .collect();
+    for f in fns.iter_mut() {
+        let mut next_label = f.label_count();
+        let mut next_reg = f.reg_count();
+        let mut stacksize = f.stacksize;
+        let mut code = vec![];
+        for call in &f.ir {
+            let (callee, dst, args) = match call.op {
+                // Arguments on the stack would have to be loaded from
+                // their slots, so such calls are left alone.
+                IROp::Call(ref name, ref args, ref slots)
+                    if name != &f.name && slots.is_empty() =>
+                {
+                    match callees.get(name) {
+                        Some(callee) => (callee, call.lhs.unwrap(), args),
+                        None => {
+                            code.push(call.clone());
+                            continue;
+                        }
+                    }
+                }
+                _ => {
+                    code.push(call.clone());
+                    continue;
+                }
+            };
+
+            let body = callee.clone_with_renumbered_regs(next_reg, next_label);
+            next_reg += callee.reg_count();
+            let end = next_label + callee.label_count();
+            next_label = end + 1;
+            let base = roundup(stacksize, 8);
+            stacksize = base + callee.stacksize;
+
+            // The last return needs no jump, and the end label is only
+            // needed if there is a jump to it.
+            let last = body.ir.iter().rposition(|x| x.op != IROp::Kill);
+            let falls_off = last.is_none_or(|i| body.ir[i].op != IROp::Return);
+            let mut jumps = false;
+            for (i, x) in body.ir.into_iter().enumerate() {
+                match x.op {
+                    IROp::StoreArg(size) => {
+                        let addr = next_reg;
+                        next_reg += 1;
+                        code.push(ir(IROp::Bprel, addr, Some(x.lhs.unwrap() + base)));
+                        code.push(ir(IROp::Store(size), addr, Some(args[x.rhs.unwrap()])));
+                        code.push(ir(IROp::Kill, addr, None));
+                    }
+                    IROp::Bprel => {
+                        code.push(ir(IROp::Bprel, x.lhs.unwrap(), Some(x.rhs.unwrap() + base)))
+                    }
+                    IROp::Return => {
+                        code.push(ir(IROp::Mov, dst, x.lhs));
+                        if Some(i) != last {
+                            code.push(ir(IROp::Jmp, end, None));
+                            jumps = true;
+                        }
+                    }
+                    _ => code.push(x),
+                }
+            }
+            // Falling off the end of a function returns 0.
+            if falls_off {
+                code.push(ir(IROp::Imm, dst, Some(0)));
+            }
+            if jumps {
+                code.push(ir(IROp::Label, end, None));
+            }
+        }
+
+        if max_pressure(&code) <= REGS_N.max(max_pressure(&f.ir)) {
+            f.ir = code;
+            f.stacksize = stacksize;
+        }
+    }
+}
+
+fn refers_to(ir: &IR, r: usize) -> bool {
+    ir.uses().contains(&r) || ir.def() == Some(r)
+}
+
+// Some(true) if the instruction writes memory, Some(false) if it only
+// reads it.
+fn memory_access(ir: &IR) -> Option<bool> {
+    match ir.op {
+        IROp::Load(_) => Some(false),
+        IROp::Store(_)
+        | IROp::StoreArg(_)
+        | IROp::Call(_, _, _)
+        | IROp::CallReg(_, _, _)
+        | IROp::Asm(_) => Some(true),
+        // Treated as writes so that they keep their order.
+        IROp::VolatileLoad(_) | IROp::VolatileStore(_) => Some(true),
+        _ => None,
+    }
+}
+
+fn is_call(ir: &IR) -> bool {
+    matches!(ir.op, IROp::Call(_, _, _) | IROp::CallReg(_, _, _))
+}
+
+// Whether `b`, which comes after `a`, must stay after it.
+fn depends(a: &IR, b: &IR) -> bool {
+    // Nothing moves across a call. A value computed before it and used
+    // after would have to survive it in a register.
+    if is_call(a) || is_call(b) {
+        return true;
+    }
+    // Nothing that refers to a register moves across its Kill.
+    if a.op == IROp::Kill && refers_to(b, a.lhs.unwrap()) {
+        return true;
+    }
+    if b.op == IROp::Kill && refers_to(a, b.lhs.unwrap()) {
+        return true;
+    }
+    if let Some(r) = a.def() {
+        if refers_to(b, r) {
+            return true;
+        }
+    }
+    if let Some(r) = b.def() {
+        if a.uses().contains(&r) {
+            return true;
+        }
+    }
+    match (memory_access(a), memory_access(b)) {
+        (Some(wa), Some(wb)) => wa || wb,
+        _ => false,
+    }
+}
+
+// List scheduling of a basic block without its label and terminator.
+// An instruction is placed as soon as its dependencies are, but not
+// right after the instruction producing one of its operands if anything
+// else is ready. Kills go first since they emit no code and free a
+// register.
+fn schedule_block(code: Vec<IR>) -> Vec<IR> {
+    let n = code.len();
+    let preds: Vec<Vec<usize>> = (0..n)
+        .map(|j| (0..j).filter(|&i| depends(&code[i], &code[j])).collect())
+        .collect();
+
+    let mut done = vec![false; n];
+    let mut order = vec![];
+    let mut last: Option<usize> = None;
+    while order.len() < n {
+        let ready: Vec<usize> = (0..n)
+            .filter(|&j| !done[j] && preds[j].iter().all(|&i| done[i]))
+            .collect();
+        let stalls = |j: usize| match last.and_then(|l| code[l].def()) {
+            Some(r) => code[j].uses().contains(&r),
+            None => false,
+        };
+        let next = ready
+            .iter()
+            .cloned()
+            .find(|&j| code[j].op == IROp::Kill)
+            .or_else(|| ready.iter().cloned().find(|&j| !stalls(j)))
+            .unwrap_or(ready[0]);
+
+        done[next] = true;
+        order.push(next);
+        if code[next].op != IROp::Kill {
+            last = Some(next);
+        }
+    }
+    order.into_iter().map(|i| code[i].clone()).collect()
+}
+
+fn schedule_function(f: &mut Function) {
+    let mut code = vec![];
+    let mut block = vec![];
+    for ir in f.ir.iter().cloned() {
+        if ir.op == IROp::Label || ir.op.is_terminator() {
+            code.extend(schedule_block(block));
+            block = vec![];
+            code.push(ir);
+        } else {
+            block.push(ir);
+        }
+    }
+    code.extend(schedule_block(block));
+
+    // Moving a definition up keeps its register alive longer.
+    let old = max_pressure(&f.ir);
+    let new = max_pressure(&code);
+    if new <= REGS_N || new <= old {
+        f.ir = code;
+    }
+}
+
+pub fn optimize(fns: &mut Vec<Function>) {
+    inline_calls(fns);
+    for f in fns {
+        algebraic_identities(f);
+        remove_redundant_loads(f);
+        fold_branches(f);
+        remove_unreachable(f);
+        merge_labels(f);
+        remove_jumps_to_next(f);
+        remove_dead_regs(f);
+        fuse_compare_branch(f);
+        loop_invariant_code_motion(f);
+        remove_self_moves(f);
+    }
+}
+
+// Reorders instructions within basic blocks so that an instruction does
+// not directly follow the one producing its operand, which would stall
+// the pipeline. This is separate from optimize() so that IR dumps taken
+// after optimization stay in source order.
+pub fn schedule(fns: &mut Vec<Function>) {
+    for f in fns {
+        schedule_function(f);
+    }
+}
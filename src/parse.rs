@@ -1,7 +1,14 @@
-use token::Token;
+use target::target;
+#[cfg(feature = "std")]
+use token::{error_at, Token, TokenStream};
+#[cfg(feature = "std")]
 use util::roundup;
-use {Ctype, Scope, TokenType, Type};
+use {Ctype, Scope, Span, TokenType, Type};
 
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
 use std::collections::HashMap;
 
 // Quoted from 9cc
@@ -23,11 +30,12 @@ int main() {     ; +-+                        int   []         2
 }                ; +-+                  return        []      +->primary
                                                                  0
 */
+#[cfg(feature = "std")]
 pub fn parse(tokens: &Vec<Token>) -> Vec<Node> {
     let mut parser = Parser::new(tokens);
 
     let mut v = vec![];
-    while tokens.len() != parser.pos {
+    while !parser.ts.at_end() {
         if let Some(node) = parser.toplevel() {
             v.push(node);
         }
@@ -35,23 +43,28 @@ pub fn parse(tokens: &Vec<Token>) -> Vec<Node> {
     v
 }
 
+#[cfg(feature = "std")]
 #[derive(Debug, Clone)]
 struct Env {
     tags: HashMap<String, Type>,
     typedefs: HashMap<String, Type>,
+    enums: HashMap<String, i32>, // Enumerators and their values
     next: Option<Box<Env>>,
 }
 
+#[cfg(feature = "std")]
 impl Env {
     pub fn new(next: Option<Box<Env>>) -> Self {
         Env {
             next,
             tags: HashMap::new(),
             typedefs: HashMap::new(),
+            enums: HashMap::new(),
         }
     }
 }
 
+#[cfg(feature = "std")]
 macro_rules! new_expr(
     ($i:path, $expr:expr) => (
         Node::new($i(Box::new($expr)))
@@ -70,32 +83,41 @@ pub enum NodeType {
     BinOp(TokenType, Box<Node>, Box<Node>),          // left-hand, right-hand
     If(Box<Node>, Box<Node>, Option<Box<Node>>),     // "if" ( cond ) then "else" els
     Ternary(Box<Node>, Box<Node>, Box<Node>),        // cond ? then : els
+    Elvis(Box<Node>, Box<Node>),                     // cond ?: els, cond evaluated once (GNU)
     For(Box<Node>, Box<Node>, Box<Node>, Box<Node>), // "for" ( init; cond; inc ) body
     Break,
-    DoWhile(Box<Node>, Box<Node>), // do { body } while(cond)
-    Addr(Box<Node>),               // address-of operator("&"), expr
-    Deref(Box<Node>),              // pointer dereference ("*"), expr
-    Dot(Box<Node>, String, usize), // Struct member accessm, (expr, name, offset)
-    Exclamation(Box<Node>),        // !, expr
-    Neg(Box<Node>),                // -
-    PostInc(Box<Node>),            // post ++
-    PostDec(Box<Node>),            // post --
-    Return(Box<Node>),             // "return", stmt
-    Sizeof(Box<Node>),             // "sizeof", expr
-    Alignof(Box<Node>),            // "_Alignof", expr
-    Call(String, Vec<Node>),       // Function call(name, args)
+    Continue,
+    InlineAsm(String),                         // "asm" ( "text" ), emitted verbatim
+    Switch(Box<Node>, Box<Node>),              // "switch" ( cond ) body
+    Case(i32, Box<Node>),                      // "case" val: stmt
+    Default(Box<Node>),                        // "default": stmt
+    DoWhile(Box<Node>, Box<Node>),             // do { body } while(cond)
+    Addr(Box<Node>),                           // address-of operator("&"), expr
+    Deref(Box<Node>),                          // pointer dereference ("*"), expr
+    Dot(Box<Node>, String, usize),             // Struct member accessm, (expr, name, offset)
+    Exclamation(Box<Node>),                    // !, expr
+    Neg(Box<Node>),                            // -
+    PostInc(Box<Node>),                        // post ++
+    PostDec(Box<Node>),                        // post --
+    Return(Box<Node>),                         // "return", stmt
+    Sizeof(Box<Node>),                         // "sizeof", expr
+    Cast(Box<Node>),                           // (type) expr. The type is the node's type.
+    Alignof(Box<Node>),                        // "_Alignof", expr
+    Call(String, Vec<Node>),                   // Function call(name, args)
+    CallPtr(Box<Node>, Vec<Node>),             // Call through a pointer(callee, args)
     Func(String, Vec<Node>, Box<Node>, usize), // Function definition(name, args, body, stacksize)
-    CompStmt(Vec<Node>),           // Compound statement
-    VecStmt(Vec<Node>),            // For the purpose of assign a value when initializing an array.
-    ExprStmt(Box<Node>),           // Expression statement
-    StmtExpr(Box<Node>),           // Statement expression (GNU extn.)
+    CompStmt(Vec<Node>),                       // Compound statement
+    VecStmt(Vec<Node>), // For the purpose of assign a value when initializing an array.
+    ExprStmt(Box<Node>), // Expression statement
+    StmtExpr(Box<Node>), // Statement expression (GNU extn.)
     Null,
 }
 
 #[derive(Debug, Clone)]
 pub struct Node {
-    pub op: NodeType,  // Node type
-    pub ty: Box<Type>, // C type
+    pub op: NodeType,       // Node type
+    pub ty: Box<Type>,      // C type
+    pub span: Option<Span>, // Source location, for diagnostics
 }
 
 impl Node {
@@ -103,9 +125,16 @@ impl Node {
         Self {
             op,
             ty: Box::new(Type::default()),
+            span: None,
         }
     }
 
+    #[cfg(feature = "std")]
+    pub fn at(mut self, t: &Token) -> Self {
+        self.span = Some(t.span());
+        self
+    }
+
     pub fn new_int(val: i32) -> Self {
         Node::new(NodeType::Num(val))
     }
@@ -135,12 +164,27 @@ impl Node {
     }
 }
 
+// __builtin_min(a, b) and __builtin_max(a, b) are not functions. They
+// are lowered to a compare and a conditional move, which evaluates both
+// operands exactly once.
+pub fn is_minmax(name: &str) -> bool {
+    name == "__builtin_min" || name == "__builtin_max"
+}
+
+// __builtin_trap() aborts the program. It takes no arguments and
+// returns nothing.
+pub fn is_trap(name: &str) -> bool {
+    name == "__builtin_trap"
+}
+
 impl Type {
     pub fn new(ty: Ctype, size: usize) -> Self {
         Type {
             ty,
             size,
             align: size,
+            volatile: false,
+            restrict: false,
         }
     }
 
@@ -156,8 +200,22 @@ impl Type {
         Type::new(Ctype::Int, 4)
     }
 
+    pub fn uint_ty() -> Self {
+        Type::new(Ctype::Uint, 4)
+    }
+
+    // There is no `long` keyword yet. These types come only from integer
+    // constant suffixes, and are as wide as a pointer.
+    pub fn long_ty() -> Self {
+        Type::new(Ctype::Long, target().ptr_size)
+    }
+
+    pub fn ulong_ty() -> Self {
+        Type::new(Ctype::Ulong, target().ptr_size)
+    }
+
     pub fn ptr_to(base: Box<Type>) -> Self {
-        Type::new(Ctype::Ptr(base), 8)
+        Type::new(Ctype::Ptr(base), target().ptr_size)
     }
 
     pub fn ary_of(base: Box<Type>, len: usize) -> Self {
@@ -167,19 +225,28 @@ impl Type {
         ty.align = align;
         ty
     }
+
+    // Whether a value of this type does not fit in a register. Such a
+    // value is represented by its address and copied when assigned.
+    pub fn in_memory(&self) -> bool {
+        match self.ty {
+            Ctype::Struct(_) => !matches!(self.size, 1 | 4 | 8),
+            _ => false,
+        }
+    }
 }
 
+#[cfg(feature = "std")]
 pub struct Parser<'a> {
-    tokens: &'a Vec<Token>,
-    pos: usize,
+    ts: TokenStream<'a>,
     env: Env,
 }
 
+#[cfg(feature = "std")]
 impl<'a> Parser<'a> {
-    pub fn new(tokens: &'a Vec<Token>) -> Self {
+    pub fn new(tokens: &'a [Token]) -> Self {
         Parser {
-            tokens,
-            pos: 0,
+            ts: TokenStream::new(tokens),
             env: Env::new(None),
         }
     }
@@ -214,21 +281,27 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn expect(&mut self, ty: TokenType) {
-        let t = &self.tokens[self.pos];
-        if t.ty != ty {
-            t.bad_token(&format!("{:?} expected", ty));
+    fn find_enum(&self, name: &str) -> Option<i32> {
+        let mut next: &Option<Box<Env>> = &Some(Box::new(self.env.clone()));
+        loop {
+            if let Some(ref e) = next {
+                let val = e.enums.get(name);
+                if val.is_some() {
+                    return val.cloned();
+                }
+                next = &e.next;
+            } else {
+                return None;
+            }
         }
-        self.pos += 1;
+    }
+
+    fn expect(&mut self, ty: TokenType) {
+        self.ts.expect(ty);
     }
 
     fn consume(&mut self, ty: TokenType) -> bool {
-        let t = &self.tokens[self.pos];
-        if t.ty != ty {
-            return false;
-        }
-        self.pos += 1;
-        true
+        self.ts.consume_if(ty)
     }
 
     fn is_typename(&self, t: &Token) -> bool {
@@ -236,7 +309,14 @@ impl<'a> Parser<'a> {
         if let TokenType::Ident(ref name) = t.ty {
             return self.find_typedef(name).is_some();
         }
-        t.ty == Int || t.ty == Char || t.ty == Void || t.ty == Struct
+        t.ty == Int
+            || t.ty == Char
+            || t.ty == Void
+            || t.ty == Unsigned
+            || t.ty == Volatile
+            || t.ty == Struct
+            || t.ty == Enum
+            || t.ty == Typeof
     }
 
     fn set_offset(members: &mut Vec<Node>) -> (usize, usize) {
@@ -268,32 +348,60 @@ impl<'a> Parser<'a> {
     }
 
     fn decl_specifiers(&mut self) -> Option<Type> {
-        let t = &self.tokens[self.pos];
-        self.pos += 1;
+        // Accesses to a volatile object are kept as written.
+        if self.consume(TokenType::Volatile) {
+            return self.decl_specifiers().map(|mut ty| {
+                ty.volatile = true;
+                ty
+            });
+        }
+
+        let t = self.ts.advance();
         match t.ty {
             TokenType::Ident(ref name) => {
                 if let Some(ty) = self.find_typedef(name) {
                     return Some(ty.clone());
                 } else {
-                    self.pos -= 1;
+                    self.ts.unget();
                     return None;
                 }
             }
             TokenType::Int => Some(Type::int_ty()),
             TokenType::Char => Some(Type::char_ty()),
+            // GNU extension. The operand is a type name or an expression,
+            // which is not evaluated.
+            TokenType::Typeof => {
+                self.expect(TokenType::LeftParen);
+                let ty = if self.is_typename(self.ts.peek()) {
+                    self.ctype()
+                } else {
+                    Type::new(Ctype::Typeof(Box::new(self.expr())), 0)
+                };
+                self.expect(TokenType::RightParen);
+                Some(ty)
+            }
             TokenType::Void => Some(Type::void_ty()),
+            // char is already unsigned.
+            TokenType::Unsigned => {
+                if self.consume(TokenType::Char) {
+                    Some(Type::char_ty())
+                } else {
+                    self.consume(TokenType::Int);
+                    Some(Type::uint_ty())
+                }
+            }
             TokenType::Struct => {
                 let mut tag_may: Option<String> = None;
-                let t = &self.tokens[self.pos];
+                let t = self.ts.peek();
                 if let TokenType::Ident(ref name) = t.ty {
-                    self.pos += 1;
+                    self.ts.advance();
                     tag_may = Some(name.clone())
                 }
 
                 let mut members = vec![];
                 if self.consume(TokenType::LeftBrace) {
                     while !self.consume(TokenType::RightBrace) {
-                        members.push(self.declaration())
+                        members.extend(self.declaration_list())
                     }
                 }
 
@@ -313,14 +421,39 @@ impl<'a> Parser<'a> {
                 }
                 Some(ty.clone())
             }
+            TokenType::Enum => {
+                let t = self.ts.peek();
+                if let TokenType::Ident(ref name) = t.ty {
+                    self.ts.advance();
+                    self.env.tags.insert(name.clone(), Type::int_ty());
+                }
+
+                // Enumerators count up from the previous value.
+                if self.consume(TokenType::LeftBrace) {
+                    let mut val = 0;
+                    while !self.consume(TokenType::RightBrace) {
+                        let name = self.ident();
+                        if self.consume(TokenType::Equal) {
+                            val = self.const_expr();
+                        }
+                        self.env.enums.insert(name, val);
+                        val += 1;
+                        if !self.consume(TokenType::Comma) {
+                            self.expect(TokenType::RightBrace);
+                            break;
+                        }
+                    }
+                }
+                Some(Type::int_ty())
+            }
             _ => t.bad_token("typename expected"),
         }
     }
 
     fn ident(&mut self) -> String {
-        let t = &self.tokens[self.pos];
+        let t = self.ts.peek();
         if let TokenType::Ident(ref name) = t.ty {
-            self.pos += 1;
+            self.ts.advance();
             name.clone()
         } else {
             t.bad_token("variable name expected");
@@ -328,10 +461,18 @@ impl<'a> Parser<'a> {
     }
 
     fn primary(&mut self) -> Node {
-        let t = &self.tokens[self.pos];
-        self.pos += 1;
+        let t = self.ts.advance();
         match t.ty {
-            TokenType::Num(val) => Node::new_num(val),
+            TokenType::Num(val) => {
+                let mut node = Node::new_num(val);
+                node.ty = Box::new(match (t.unsigned, t.long) {
+                    (false, false) => Type::int_ty(),
+                    (true, false) => Type::uint_ty(),
+                    (false, true) => Type::long_ty(),
+                    (true, true) => Type::ulong_ty(),
+                });
+                node
+            }
             TokenType::Str(ref str, len) => {
                 let mut node = Node::new(NodeType::Str(str.clone(), len));
                 node.ty = Box::new(Type::ary_of(Box::new(Type::char_ty()), len));
@@ -339,20 +480,14 @@ impl<'a> Parser<'a> {
             }
             TokenType::Ident(ref name) => {
                 if !self.consume(TokenType::LeftParen) {
-                    return Node::new(NodeType::Ident(name.clone()));
-                }
-
-                let mut args = vec![];
-                if self.consume(TokenType::RightParen) {
-                    return Node::new(NodeType::Call(name.clone(), args));
+                    if let Some(val) = self.find_enum(name) {
+                        return Node::new_num(val);
+                    }
+                    return Node::new(NodeType::Ident(name.clone())).at(t);
                 }
 
-                args.push(self.assign());
-                while self.consume(TokenType::Comma) {
-                    args.push(self.assign());
-                }
-                self.expect(TokenType::RightParen);
-                Node::new(NodeType::Call(name.clone(), args))
+                let args = self.call_args();
+                Node::new(NodeType::Call(name.clone(), args)).at(t)
             }
             TokenType::LeftParen => {
                 if self.consume(TokenType::LeftBrace) {
@@ -368,6 +503,21 @@ impl<'a> Parser<'a> {
         }
     }
 
+    // Reads the arguments of a call after its `(`.
+    fn call_args(&mut self) -> Vec<Node> {
+        let mut args = vec![];
+        if self.consume(TokenType::RightParen) {
+            return args;
+        }
+
+        args.push(self.assign());
+        while self.consume(TokenType::Comma) {
+            args.push(self.assign());
+        }
+        self.expect(TokenType::RightParen);
+        args
+    }
+
     fn postfix(&mut self) -> Node {
         let mut lhs = self.primary();
 
@@ -382,18 +532,20 @@ impl<'a> Parser<'a> {
                 continue;
             }
 
+            let t = self.ts.peek();
             if self.consume(TokenType::Dot) {
                 // TODO: Use new_expr!
-                lhs = Node::new(NodeType::Dot(Box::new(lhs), self.ident(), 0));
+                lhs = Node::new(NodeType::Dot(Box::new(lhs), self.ident(), 0)).at(t);
                 continue;
             }
 
             if self.consume(TokenType::Arrow) {
                 lhs = Node::new(NodeType::Dot(
-                    Box::new(new_expr!(NodeType::Deref, lhs)),
+                    Box::new(new_expr!(NodeType::Deref, lhs).at(t)),
                     self.ident(),
                     0,
-                ));
+                ))
+                .at(t);
                 continue;
             }
 
@@ -405,19 +557,36 @@ impl<'a> Parser<'a> {
                 self.expect(TokenType::RightBracket);
                 continue;
             }
+
+            // A call of a name is read by primary(), so this calls
+            // whatever an expression such as `(*fp)` points to.
+            if self.consume(TokenType::LeftParen) {
+                let args = self.call_args();
+                lhs = Node::new(NodeType::CallPtr(Box::new(lhs), args)).at(t);
+                continue;
+            }
             return lhs;
         }
     }
 
     fn unary(&mut self) -> Node {
+        if self.ts.peek().ty == TokenType::LeftParen && self.is_typename(self.ts.peek_nth(1)) {
+            self.ts.advance();
+            let ty = self.ctype();
+            self.expect(TokenType::RightParen);
+            let mut node = new_expr!(NodeType::Cast, self.unary());
+            node.ty = Box::new(ty);
+            return node;
+        }
         if self.consume(TokenType::Minus) {
             return new_expr!(NodeType::Neg, self.unary());
         }
+        let t = self.ts.peek();
         if self.consume(TokenType::Mul) {
-            return new_expr!(NodeType::Deref, self.unary());
+            return new_expr!(NodeType::Deref, self.unary()).at(t);
         }
         if self.consume(TokenType::And) {
-            return new_expr!(NodeType::Addr, self.unary());
+            return new_expr!(NodeType::Addr, self.unary()).at(t);
         }
         if self.consume(TokenType::Exclamation) {
             return new_expr!(NodeType::Exclamation, self.unary());
@@ -439,124 +608,81 @@ impl<'a> Parser<'a> {
         self.postfix()
     }
 
-    fn mul(&mut self) -> Node {
-        let mut lhs = self.unary();
-
-        loop {
-            if self.consume(TokenType::Mul) {
-                lhs = Node::new_binop(TokenType::Mul, lhs, self.unary());
-            } else if self.consume(TokenType::Div) {
-                lhs = Node::new_binop(TokenType::Div, lhs, self.unary());
-            } else if self.consume(TokenType::Mod) {
-                lhs = Node::new_binop(TokenType::Mod, lhs, self.unary());
-            } else {
-                return lhs;
-            }
-        }
-    }
-
-    fn add(&mut self) -> Node {
-        let mut lhs = self.mul();
-
-        loop {
-            if self.consume(TokenType::Plus) {
-                lhs = Node::new_binop(TokenType::Plus, lhs, self.mul());
-            } else if self.consume(TokenType::Minus) {
-                lhs = Node::new_binop(TokenType::Minus, lhs, self.mul());
-            } else {
-                return lhs;
-            }
-        }
-    }
-
-    fn shift(&mut self) -> Node {
-        let mut lhs = self.add();
-        loop {
-            if self.consume(TokenType::SHL) {
-                lhs = Node::new_binop(TokenType::SHL, lhs, self.add());
-            } else if self.consume(TokenType::SHR) {
-                lhs = Node::new_binop(TokenType::SHR, lhs, self.add());
-            } else {
-                return lhs;
-            }
+    // Binding power of binary operators. A larger value binds tighter.
+    fn binop_prec(ty: &TokenType) -> Option<u8> {
+        use self::TokenType::*;
+        match ty {
+            Mul | Div | Mod => Some(10),
+            Plus | Minus => Some(9),
+            SHL | SHR => Some(8),
+            LeftAngleBracket | RightAngleBracket | LE | GE => Some(7),
+            EQ | NE => Some(6),
+            And => Some(5),
+            Hat => Some(4),
+            VerticalBar => Some(3),
+            Logand => Some(2),
+            Logor => Some(1),
+            _ => None,
         }
     }
 
-    fn relational(&mut self) -> Node {
-        let mut lhs = self.shift();
-        loop {
-            if self.consume(TokenType::LeftAngleBracket) {
-                lhs = Node::new_binop(TokenType::LeftAngleBracket, lhs, self.shift());
-            } else if self.consume(TokenType::RightAngleBracket) {
-                lhs = Node::new_binop(TokenType::LeftAngleBracket, self.shift(), lhs);
-            } else if self.consume(TokenType::LE) {
-                lhs = Node::new_binop(TokenType::LE, lhs, self.shift())
-            } else if self.consume(TokenType::GE) {
-                lhs = Node::new_binop(TokenType::LE, self.shift(), lhs);
-            } else {
-                return lhs;
-            }
+    // `a > b` and `a >= b` are represented as `b < a` and `b <= a`.
+    fn new_binop_node(ty: TokenType, lhs: Node, rhs: Node) -> Node {
+        match ty {
+            TokenType::RightAngleBracket => Node::new_binop(TokenType::LeftAngleBracket, rhs, lhs),
+            TokenType::GE => Node::new_binop(TokenType::LE, rhs, lhs),
+            _ => Node::new_binop(ty, lhs, rhs),
         }
     }
 
-    fn equality(&mut self) -> Node {
-        let mut lhs = self.relational();
+    // Precedence climbing. All binary operators are left-associative.
+    fn binary(&mut self, min_prec: u8) -> Node {
+        let start = self.ts.pos();
+        let mut lhs = self.unary();
+        let mut last_prec = None;
+        let mut last_rhs = (start, start);
         loop {
-            if self.consume(TokenType::EQ) {
-                lhs = Node::new_binop(TokenType::EQ, lhs, self.relational());
-            } else if self.consume(TokenType::NE) {
-                lhs = Node::new_binop(TokenType::NE, lhs, self.relational());
-            } else {
-                return lhs;
+            let ty = self.ts.peek().ty.clone();
+            let prec = match Self::binop_prec(&ty) {
+                Some(prec) if prec >= min_prec => prec,
+                _ => return lhs,
+            };
+
+            let op = self.ts.pos();
+            let t = self.ts.advance();
+            let rhs = self.binary(prec + 1);
+            let rhs_range = (op + 1, self.ts.pos());
+
+            // `a < b < c` compares the 0 or 1 of `a < b` with c.
+            if prec == 7 && last_prec == Some(7) {
+                let suggestion = format!(
+                    "({}) && ({} {} {})",
+                    self.ts.source(start, op),
+                    self.ts.source(last_rhs.0, last_rhs.1),
+                    t.tokstr(),
+                    self.ts.source(rhs_range.0, rhs_range.1)
+                );
+                t.warn_suggesting(
+                    "comparisons like 'a < b < c' do not have their mathematical meaning",
+                    suggestion,
+                );
             }
-        }
-    }
-
-    fn bit_and(&mut self) -> Node {
-        let mut lhs = self.equality();
-        while self.consume(TokenType::And) {
-            lhs = Node::new_binop(TokenType::And, lhs, self.equality());
-        }
-        lhs
-    }
-
-    fn bit_xor(&mut self) -> Node {
-        let mut lhs = self.bit_and();
-        while self.consume(TokenType::Hat) {
-            lhs = Node::new_binop(TokenType::Hat, lhs, self.bit_and());
-        }
-        lhs
-    }
+            last_prec = Some(prec);
+            last_rhs = rhs_range;
 
-    fn bit_or(&mut self) -> Node {
-        let mut lhs = self.bit_xor();
-        while self.consume(TokenType::VerticalBar) {
-            lhs = Node::new_binop(TokenType::VerticalBar, lhs, self.bit_xor());
+            lhs = Self::new_binop_node(ty, lhs, rhs).at(t);
         }
-        lhs
-    }
-
-    fn logand(&mut self) -> Node {
-        let mut lhs = self.bit_or();
-        while self.consume(TokenType::Logand) {
-            lhs = Node::new_binop(TokenType::Logand, lhs, self.logand());
-        }
-        lhs
-    }
-
-    fn logor(&mut self) -> Node {
-        let mut lhs = self.logand();
-        while self.consume(TokenType::Logor) {
-            lhs = Node::new_binop(TokenType::Logor, lhs, self.logand());
-        }
-        lhs
     }
 
     fn conditional(&mut self) -> Node {
-        let cond = self.logor();
+        let cond = self.binary(1);
         if !self.consume(TokenType::Question) {
             return cond;
         }
+        if self.consume(TokenType::Colon) {
+            let els = self.conditional();
+            return Node::new(NodeType::Elvis(Box::new(cond), Box::new(els)));
+        }
         let then = self.expr();
         self.expect(TokenType::Colon);
         let els = self.conditional();
@@ -578,8 +704,8 @@ impl<'a> Parser<'a> {
 
     fn assign(&mut self) -> Node {
         let lhs = self.conditional();
-        if let Some(op) = Self::assign_op(&self.tokens[self.pos].ty) {
-            self.pos += 1;
+        if let Some(op) = Self::assign_op(&self.ts.peek().ty) {
+            self.ts.advance();
             Node::new_binop(op.clone(), lhs, self.assign())
         } else {
             lhs
@@ -594,11 +720,107 @@ impl<'a> Parser<'a> {
         Node::new_binop(TokenType::Comma, lhs, self.expr())
     }
 
+    // Evaluates an expression that must be known at compile time,
+    // such as an array length or an enumerator value.
+    fn const_expr(&mut self) -> i32 {
+        let t = self.ts.peek();
+        let node = self.conditional();
+        match Self::eval(&node) {
+            Some(val) => val,
+            None => t.bad_token("constant expression expected"),
+        }
+    }
+
+    // Returns None if the expression is not constant.
+    fn eval(node: &Node) -> Option<i32> {
+        Self::eval_typed(node).map(|(val, _)| val)
+    }
+
+    // Like eval(), but also tells whether the value is unsigned. Signed
+    // overflow is an error rather than wrapping around, as the result
+    // would not be what the program meant. Unsigned arithmetic wraps
+    // modulo 2^32, as in C.
+    fn eval_typed(node: &Node) -> Option<(i32, bool)> {
+        use self::TokenType::*;
+        let overflow = || error_at(node.span.clone(), "integer overflow in constant expression");
+        match node.op {
+            NodeType::Num(val) => match node.ty.ty {
+                Ctype::Uint | Ctype::Ulong => Some((val, true)),
+                _ => Some((val, false)),
+            },
+            NodeType::Neg(ref expr) => match Self::eval_typed(expr)? {
+                (val, true) => Some((val.wrapping_neg(), true)),
+                (val, false) => Some((val.checked_neg().unwrap_or_else(overflow), false)),
+            },
+            NodeType::Exclamation(ref expr) => Some(((Self::eval(expr)? == 0) as i32, false)),
+            NodeType::Ternary(ref cond, ref then, ref els) => {
+                if Self::eval(cond)? != 0 {
+                    Self::eval_typed(then)
+                } else {
+                    Self::eval_typed(els)
+                }
+            }
+            NodeType::Elvis(ref cond, ref els) => match Self::eval_typed(cond)? {
+                (0, _) => Self::eval_typed(els),
+                val => Some(val),
+            },
+            NodeType::BinOp(ref op, ref lhs, ref rhs) => {
+                let (l, l_unsigned) = Self::eval_typed(lhs)?;
+                let (r, r_unsigned) = Self::eval_typed(rhs)?;
+                // A shift has the type of its left operand, and the other
+                // arithmetic operators the common type of both.
+                let unsigned = match op {
+                    SHL | SHR => l_unsigned,
+                    _ => l_unsigned || r_unsigned,
+                };
+                let (ul, ur) = (l as u32, r as u32);
+                let val = match op {
+                    Div | Mod if r == 0 => return None,
+                    Plus if unsigned => Some(ul.wrapping_add(ur) as i32),
+                    Minus if unsigned => Some(ul.wrapping_sub(ur) as i32),
+                    Mul if unsigned => Some(ul.wrapping_mul(ur) as i32),
+                    Div if unsigned => Some((ul / ur) as i32),
+                    Mod if unsigned => Some((ul % ur) as i32),
+                    SHR if unsigned => Some(ul.wrapping_shr(ur) as i32),
+                    LeftAngleBracket if unsigned => return Some(((ul < ur) as i32, false)),
+                    LE if unsigned => return Some(((ul <= ur) as i32, false)),
+                    Plus => l.checked_add(r),
+                    Minus => l.checked_sub(r),
+                    Mul => l.checked_mul(r),
+                    Div => l.checked_div(r),
+                    Mod => l.checked_rem(r),
+                    SHL => Some(l.wrapping_shl(ur)),
+                    SHR => Some(l.wrapping_shr(ur)),
+                    And => Some(l & r),
+                    Hat => Some(l ^ r),
+                    VerticalBar => Some(l | r),
+                    LeftAngleBracket => return Some(((l < r) as i32, false)),
+                    LE => return Some(((l <= r) as i32, false)),
+                    EQ => return Some(((l == r) as i32, false)),
+                    NE => return Some(((l != r) as i32, false)),
+                    Logand => return Some(((l != 0 && r != 0) as i32, false)),
+                    Logor => return Some(((l != 0 || r != 0) as i32, false)),
+                    _ => return None,
+                };
+                Some((val.unwrap_or_else(overflow), unsigned))
+            }
+            _ => None,
+        }
+    }
+
+    // `*` optionally followed by `restrict`, which qualifies the pointer
+    // rather than what it points to.
+    fn pointer(&mut self, ty: Type) -> Type {
+        let mut ty = Type::ptr_to(Box::new(ty));
+        ty.restrict = self.consume(TokenType::Restrict);
+        ty
+    }
+
     fn ctype(&mut self) -> Type {
-        let t = &self.tokens[self.pos];
+        let t = self.ts.peek();
         if let Some(mut ty) = self.decl_specifiers() {
             while self.consume(TokenType::Mul) {
-                ty = Type::ptr_to(Box::new(ty));
+                ty = self.pointer(ty);
             }
             ty
         } else {
@@ -614,13 +836,9 @@ impl<'a> Parser<'a> {
                 continue;
             }
 
-            let len = self.expr();
-            if let NodeType::Num(n) = len.op {
-                v.push(n as usize);
-                self.expect(TokenType::RightBracket);
-            } else {
-                panic!("number expected");
-            }
+            let len = self.const_expr();
+            v.push(len as usize);
+            self.expect(TokenType::RightBracket);
         }
 
         v.reverse();
@@ -630,26 +848,43 @@ impl<'a> Parser<'a> {
         *ty
     }
 
-    fn array_init_rval(&mut self, ident: Node) -> Node {
+    fn array_init_rval(&mut self, ident: Node, ty: &mut Type) -> Node {
+        let t = self.ts.peek();
+        let mut vals = vec![self.assign()];
+        while self.consume(TokenType::Comma) {
+            vals.push(self.assign());
+        }
+        self.expect(TokenType::RightBrace);
+
+        // `int x[] = {1, 2, 3}` takes its length from the initializer.
+        if let Ctype::Ary(ref ary_of, 0) = ty.ty.clone() {
+            *ty = Type::ary_of(ary_of.clone(), vals.len());
+        }
+        let len = match ty.ty {
+            Ctype::Ary(_, len) => len,
+            _ => t.bad_token("array expected"),
+        };
+        if vals.len() > len {
+            t.bad_token("excess elements in array initializer");
+        }
+
+        // Missing initializers are zero.
+        while vals.len() < len {
+            vals.push(Node::new_num(0));
+        }
+
         let mut init = vec![];
-        let mut i = 0;
-        loop {
-            let val = self.primary();
+        for (i, val) in vals.into_iter().enumerate() {
             let node = new_expr!(
                 NodeType::Deref,
-                Node::new_binop(TokenType::Plus, ident.clone(), Node::new(NodeType::Num(i)))
+                Node::new_binop(TokenType::Plus, ident.clone(), Node::new_num(i as i32))
             );
             init.push(Node::new(NodeType::ExprStmt(Box::new(Node::new_binop(
                 TokenType::Equal,
                 node,
                 val,
             )))));
-            if !self.consume(TokenType::Comma) {
-                break;
-            }
-            i += 1;
         }
-        self.expect(TokenType::RightBrace);
         Node::new(NodeType::VecStmt(init))
     }
 
@@ -661,21 +896,30 @@ impl<'a> Parser<'a> {
     }
 
     fn direct_decl(&mut self, ty: Box<Type>) -> Node {
-        let t = &self.tokens[self.pos];
+        let t = self.ts.peek();
         let mut placeholder = Box::new(Type::default());
         let mut node;
+        let nested;
 
         if let TokenType::Ident(_) = t.ty {
-            node = Node::new(NodeType::Vardef(self.ident(), None, Scope::Local(0)));
+            node = Node::new(NodeType::Vardef(self.ident(), None, Scope::Local(0))).at(t);
+            nested = false;
         } else if self.consume(TokenType::LeftParen) {
             node = self.declarator(&mut placeholder);
             self.expect(TokenType::RightParen);
+            nested = true;
         } else {
             t.bad_token("bad direct-declarator");
         }
 
         // Read the second half of type name (e.g. `[3][5]`).
-        let ty = self.read_array(ty);
+        let mut ty = self.read_array(ty);
+
+        // `int (*fp)(int)` declares a pointer to a function.
+        if nested && self.consume(TokenType::LeftParen) {
+            self.skip_params();
+            ty = Type::new(Ctype::Func(Box::new(ty)), 0);
+        }
         self.update_ptr_to(&mut node.ty, Box::new(ty));
 
         // Read an initializer.
@@ -687,9 +931,10 @@ impl<'a> Parser<'a> {
                     let mut stmts = vec![];
                     let mut ary_declaration =
                         Node::new(NodeType::Vardef(name.clone(), None, Scope::Local(0)));
+                    let init_ary = self
+                        .array_init_rval(Node::new(NodeType::Ident(name.clone())), &mut node.ty);
                     ary_declaration.ty = node.ty;
                     stmts.push(ary_declaration);
-                    let init_ary = self.array_init_rval(Node::new(NodeType::Ident(name.clone())));
                     stmts.push(init_ary);
                     return Node::new(NodeType::VecStmt(stmts));
                 }
@@ -704,18 +949,63 @@ impl<'a> Parser<'a> {
         node
     }
 
+    // Reads the parameter list of a function declarator after its `(`.
+    // Calls through a function pointer are not checked against it, so
+    // it is thrown away.
+    fn skip_params(&mut self) {
+        if self.consume(TokenType::RightParen) {
+            return;
+        }
+        loop {
+            let t = self.ts.peek();
+            if self.decl_specifiers().is_none() {
+                t.bad_token("typename expected");
+            }
+            while self.consume(TokenType::Mul) {}
+            if let TokenType::Ident(_) = self.ts.peek().ty {
+                self.ts.advance();
+            }
+            if !self.consume(TokenType::Comma) {
+                break;
+            }
+        }
+        self.expect(TokenType::RightParen);
+    }
+
     fn declarator(&mut self, ty: &mut Type) -> Node {
         while self.consume(TokenType::Mul) {
-            *ty = Type::ptr_to(Box::new(ty.clone()));
+            *ty = self.pointer(ty.clone());
         }
         self.direct_decl(Box::new(ty.clone()))
     }
 
-    fn declaration(&mut self) -> Node {
-        let mut ty = self.decl_specifiers().unwrap();
-        let node = self.declarator(&mut ty);
+    // A declaration may declare several variables, e.g. `int *p, q;`.
+    // A `*` belongs to the declarator, so q is an int, not a pointer.
+    fn declaration_list(&mut self) -> Vec<Node> {
+        let base = self.decl_specifiers().unwrap();
+        // A declaration of a tag or enumerators only, e.g. `enum { A, B };`.
+        if self.consume(TokenType::Semicolon) {
+            return vec![];
+        }
+        let mut v = vec![];
+        loop {
+            let mut ty = base.clone();
+            v.push(self.declarator(&mut ty));
+            if !self.consume(TokenType::Comma) {
+                break;
+            }
+        }
         self.expect(TokenType::Semicolon);
-        node
+        v
+    }
+
+    fn declaration(&mut self) -> Node {
+        let mut v = self.declaration_list();
+        match v.len() {
+            0 => Node::new(NodeType::Null),
+            1 => v.pop().unwrap(),
+            _ => Node::new(NodeType::VecStmt(v)),
+        }
     }
 
     fn param_declaration(&mut self) -> Node {
@@ -734,19 +1024,30 @@ impl<'a> Parser<'a> {
         node
     }
 
+    // Statements are located at their first token unless the node
+    // already points somewhere more specific.
     fn stmt(&mut self) -> Node {
-        let t = &self.tokens[self.pos];
-        self.pos += 1;
+        let t = self.ts.peek();
+        let node = self.stmt_body();
+        if node.span.is_none() {
+            return node.at(t);
+        }
+        node
+    }
+
+    fn stmt_body(&mut self) -> Node {
+        let t = self.ts.advance();
 
         match t.ty {
             TokenType::Typedef => {
-                let node = self.declaration();
-                if let NodeType::Vardef(name, _, _) = node.op {
-                    self.env.typedefs.insert(name, *node.ty);
-                    return Node::new(NodeType::Null);
-                } else {
-                    unreachable!();
+                for node in self.declaration_list() {
+                    if let NodeType::Vardef(name, _, _) = node.op {
+                        self.env.typedefs.insert(name, *node.ty);
+                    } else {
+                        unreachable!();
+                    }
                 }
+                Node::new(NodeType::Null)
             }
             TokenType::If => {
                 let mut els = None;
@@ -762,7 +1063,7 @@ impl<'a> Parser<'a> {
             TokenType::For => {
                 self.expect(TokenType::LeftParen);
 
-                let init: Box<Node> = if self.is_typename(&self.tokens[self.pos]) {
+                let init: Box<Node> = if self.is_typename(self.ts.peek()) {
                     Box::new(self.declaration())
                 } else if self.consume(TokenType::Semicolon) {
                     Box::new(Node::new(NodeType::Null))
@@ -808,10 +1109,44 @@ impl<'a> Parser<'a> {
                 Node::new(NodeType::DoWhile(body, cond))
             }
             TokenType::Break => Node::new(NodeType::Break),
+            TokenType::Continue => {
+                self.expect(TokenType::Semicolon);
+                Node::new(NodeType::Continue)
+            }
+            TokenType::Asm => {
+                self.expect(TokenType::LeftParen);
+                let t = self.ts.advance();
+                let text = match t.ty {
+                    TokenType::Str(ref s, _) => s.clone(),
+                    _ => t.bad_token("string literal expected"),
+                };
+                self.expect(TokenType::RightParen);
+                self.expect(TokenType::Semicolon);
+                Node::new(NodeType::InlineAsm(text))
+            }
+            TokenType::Switch => {
+                self.expect(TokenType::LeftParen);
+                let cond = Box::new(self.expr());
+                self.expect(TokenType::RightParen);
+                let body = Box::new(self.stmt());
+                Node::new(NodeType::Switch(cond, body))
+            }
+            TokenType::Case => {
+                let val = self.const_expr();
+                self.expect(TokenType::Colon);
+                Node::new(NodeType::Case(val, Box::new(self.stmt())))
+            }
+            TokenType::Default => {
+                self.expect(TokenType::Colon);
+                Node::new(NodeType::Default(Box::new(self.stmt())))
+            }
             TokenType::Return => {
+                if self.consume(TokenType::Semicolon) {
+                    return Node::new(NodeType::Return(Box::new(Node::new(NodeType::Null)))).at(t);
+                }
                 let expr = self.expr();
                 self.expect(TokenType::Semicolon);
-                Node::new(NodeType::Return(Box::new(expr)))
+                Node::new(NodeType::Return(Box::new(expr))).at(t)
             }
             TokenType::LeftBrace => {
                 let mut stmts = vec![];
@@ -822,8 +1157,8 @@ impl<'a> Parser<'a> {
             }
             TokenType::Semicolon => Node::new(NodeType::Null),
             _ => {
-                self.pos -= 1;
-                if self.is_typename(&self.tokens[self.pos]) {
+                self.ts.unget();
+                if self.is_typename(self.ts.peek()) {
                     return self.declaration();
                 }
                 self.expr_stmt()
@@ -846,17 +1181,36 @@ impl<'a> Parser<'a> {
 
     fn toplevel(&mut self) -> Option<Node> {
         let is_typedef = self.consume(TokenType::Typedef);
-        let is_extern = self.consume(TokenType::Extern);
+        let mut is_extern = self.consume(TokenType::Extern);
+
+        // A function defined without a return type, as in old C, returns
+        // an int: `main() { ... }`.
+        let t = self.ts.peek();
+        let implicit_int = match t.ty {
+            TokenType::Ident(_) => {
+                !self.is_typename(t) && self.ts.peek_nth(1).ty == TokenType::LeftParen
+            }
+            _ => false,
+        };
+        let mut ty = if implicit_int {
+            Type::int_ty()
+        } else {
+            self.ctype()
+        };
+
+        // A declaration of a struct tag only, e.g. `struct foo { int x; };`.
+        if self.consume(TokenType::Semicolon) {
+            return None;
+        }
 
-        let mut ty = self.ctype();
-        let t = &self.tokens[self.pos];
+        let name_tok = self.ts.peek();
         let name: String;
-        if let TokenType::Ident(ref name2) = t.ty {
+        if let TokenType::Ident(ref name2) = name_tok.ty {
             name = name2.clone();
         } else {
-            t.bad_token("function or variable name expected");
+            name_tok.bad_token("function or variable name expected");
         }
-        self.pos += 1;
+        self.ts.advance();
 
         // Function
         if self.consume(TokenType::LeftParen) {
@@ -875,19 +1229,38 @@ impl<'a> Parser<'a> {
                 return Some(node);
             }
 
-            let t = &self.tokens[self.pos];
+            let t = self.ts.peek();
             self.expect(TokenType::LeftBrace);
             if is_typedef {
                 t.bad_token("typedef {} has function definition");
             }
             let body = self.compound_stmt();
 
-            let mut node = Node::new(NodeType::Func(name, args, Box::new(body), 0));
+            let mut node = Node::new(NodeType::Func(name, args, Box::new(body), 0)).at(name_tok);
             node.ty = Box::new(Type::new(Ctype::Func(Box::new(ty)), 0));
             return Some(node);
         }
 
         ty = self.read_array(Box::new(ty));
+
+        // Global variable initializers are folded here and emitted
+        // into the data section, so they must be constant.
+        let mut data = String::new();
+        if !is_typedef && self.consume(TokenType::Equal) {
+            let t = self.ts.peek();
+            match ty.ty {
+                Ctype::Int | Ctype::Uint | Ctype::Char | Ctype::Ptr(_) => (),
+                _ => t.bad_token("only scalar global variables can be initialized"),
+            }
+            let val = match Self::eval(&self.assign()) {
+                Some(val) => val as i64,
+                None => t.bad_token("initializer element is not constant"),
+            };
+            data = (0..ty.size)
+                .map(|i| ((val >> (8 * i)) & 0xff) as u8 as char)
+                .collect();
+            is_extern = false;
+        }
         self.expect(TokenType::Semicolon);
 
         if is_typedef {
@@ -902,9 +1275,10 @@ impl<'a> Parser<'a> {
             if is_extern {
                 Scope::Global(String::new(), 0, true)
             } else {
-                Scope::Global(String::new(), ty.size, false)
+                Scope::Global(data, ty.size, false)
             },
-        ));
+        ))
+        .at(name_tok);
         node.ty = Box::new(ty);
         Some(node)
     }
@@ -130,6 +130,8 @@ impl Macro {
 pub struct Preprocessor {
     macros: HashMap<String, Macro>,
     pub env: Box<Env>,
+    // Number of #if blocks whose #endif has not been seen yet.
+    if_depth: usize,
 }
 
 impl Preprocessor {
@@ -137,6 +139,7 @@ impl Preprocessor {
         Preprocessor {
             macros: HashMap::new(),
             env: Box::new(Env::new(vec![], None)),
+            if_depth: 0,
         }
     }
 
@@ -244,7 +247,7 @@ impl Preprocessor {
             sb.push_str(&t.tokstr());
         }
 
-        let len = sb.len();
+        let len = sb.len() + 1; // Including the terminating NUL
         Token::new(TokenType::Str(sb, len), 0, filename, buf)
     }
 
@@ -276,7 +279,12 @@ impl Preprocessor {
         self.get(TokenType::LeftParen, "comma expected");
         let mut args = self.read_args();
         if params.len() != args.len() {
-            start.bad_token("number of parameter does not match");
+            start.bad_token(&format!(
+                "{}: {} arguments expected, but got {}",
+                start.tokstr(),
+                params.len(),
+                args.len()
+            ));
         }
 
         for t in tokens {
@@ -344,6 +352,68 @@ impl Preprocessor {
         self.env.output.append(&mut v);
     }
 
+    // Only integer constants, possibly behind an object-like macro,
+    // are supported as conditions.
+    fn if_condition(&mut self, start: &Token) -> bool {
+        let tokens = self.read_until_eol();
+        let mut t = match tokens.as_slice() {
+            [t] => t.clone(),
+            _ => start.bad_token("#if: integer constant expected"),
+        };
+        if let TokenType::Ident(ref name) = t.ty.clone() {
+            if let Some(m) = self.macros.get(name) {
+                if let [ref t2] = m.tokens.as_slice() {
+                    t = t2.clone();
+                }
+            }
+        }
+        match t.ty {
+            TokenType::Num(val) => val != 0,
+            _ => t.bad_token("#if: integer constant expected"),
+        }
+    }
+
+    // Skips tokens up to and including the #endif matching the current
+    // #if, taking nested #if blocks into account.
+    fn skip_if_block(&mut self, start: &Token) {
+        let mut depth = 1;
+        while let Some(t) = self.next() {
+            if t.ty != TokenType::HashMark {
+                continue;
+            }
+            if self.consume(TokenType::If) {
+                depth += 1;
+                continue;
+            }
+            let endif = TokenType::Ident("endif".into());
+            if self.peek().map(|t| &t.ty) != Some(&endif) {
+                continue;
+            }
+            depth -= 1;
+            if depth == 0 {
+                self.read_until_eol();
+                return;
+            }
+        }
+        start.bad_token("unterminated #if");
+    }
+
+    fn if_directive(&mut self, start: &Token) {
+        if self.if_condition(start) {
+            self.if_depth += 1;
+        } else {
+            self.skip_if_block(start);
+        }
+    }
+
+    fn endif(&mut self, start: &Token) {
+        if self.if_depth == 0 {
+            start.bad_token("#endif without #if");
+        }
+        self.if_depth -= 1;
+        self.read_until_eol();
+    }
+
     fn preprocess_impl(&mut self, tokens: Vec<Token>) -> Vec<Token> {
         self.env = Box::new(Env::new(tokens, Some(self.env.clone())));
 
@@ -369,11 +439,18 @@ impl Preprocessor {
                 continue;
             }
 
+            if self.consume(TokenType::If) {
+                self.if_directive(&t);
+                continue;
+            }
+
             let ident = self.ident("identifier expected");
             if &*ident == "define" {
                 self.define();
             } else if &*ident == "include" {
                 self.include();
+            } else if &*ident == "endif" {
+                self.endif(&t);
             } else {
                 t.bad_token("unknown directive");
             }
@@ -2,6 +2,7 @@ use gen_ir::{Function, IROp, IRType, IR};
 use irdump::IRInfo;
 use REGS_N;
 
+use std::collections::HashSet;
 use std::sync::Mutex;
 
 // Quoted from 9cc
@@ -68,6 +69,12 @@ fn visit(irv: &mut Vec<IR>) {
 
         match info.ty {
             Reg | RegImm | RegLabel | LabelAddr => ir.lhs = Some(alloc(ir.lhs.unwrap())),
+            RegRegLabel => {
+                ir.lhs = Some(alloc(ir.lhs.unwrap()));
+                if let IROp::IfEq(r) = ir.op {
+                    ir.op = IROp::IfEq(alloc(r));
+                }
+            }
             Mem | RegReg => {
                 ir.lhs = Some(alloc(ir.lhs.unwrap()));
                 ir.rhs = Some(alloc(ir.rhs.unwrap()));
@@ -75,16 +82,25 @@ fn visit(irv: &mut Vec<IR>) {
             Call => {
                 ir.lhs = Some(alloc(ir.lhs.unwrap()));
                 match ir.op {
-                    IROp::Call(name, nargs, args) => {
-                        let mut args_new: [usize; 6] = [0; 6];
-                        for i in 0..nargs {
-                            args_new[i] = alloc(args[i]);
-                        }
-                        ir.op = IROp::Call(name, nargs, args_new);
+                    IROp::Call(name, args, slots) => {
+                        let args_new = args.into_iter().map(alloc).collect();
+                        ir.op = IROp::Call(name, args_new, slots);
+                    }
+                    IROp::CallReg(r, args, slots) => {
+                        let args_new = args.into_iter().map(alloc).collect();
+                        ir.op = IROp::CallReg(alloc(r), args_new, slots);
                     }
                     _ => unreachable!(),
                 }
             }
+            CMov => {
+                ir.lhs = Some(alloc(ir.lhs.unwrap()));
+                ir.rhs = Some(alloc(ir.rhs.unwrap()));
+                if let IROp::CMov(cond) = ir.op {
+                    ir.op = IROp::CMov(alloc(cond));
+                }
+            }
+            Phi => panic!("PHI must be removed before register allocation"),
             _ => (),
         }
 
@@ -98,9 +114,44 @@ fn visit(irv: &mut Vec<IR>) {
     }
 }
 
+// Checks that every register is killed exactly once and is not used
+// after that. The allocator frees a register at its Kill, so a
+// violation would hand out a register that is still in use.
+pub fn verify_kills(f: &Function) {
+    let mut seen = HashSet::new();
+    let mut killed = HashSet::new();
+    for ir in &f.ir {
+        if ir.op == IROp::Kill {
+            let r = ir.lhs.unwrap();
+            if !killed.insert(r) {
+                panic!("{}(): r{} is killed twice", f.name, r);
+            }
+            continue;
+        }
+
+        for r in ir.uses().into_iter().chain(ir.def()) {
+            if killed.contains(&r) {
+                panic!("{}(): r{} is used after it is killed: {}", f.name, r, ir);
+            }
+            seen.insert(r);
+        }
+    }
+
+    let mut leaked: Vec<_> = seen.difference(&killed).collect();
+    leaked.sort();
+    if let Some(r) = leaked.first() {
+        panic!("{}(): r{} is never killed", f.name, r);
+    }
+}
+
 pub fn alloc_regs(fns: &mut Vec<Function>) {
     for f in fns {
+        if cfg!(debug_assertions) {
+            verify_kills(f);
+        }
+
         *USED.lock().unwrap() = [false; REGS_N];
+        *REG_MAP.lock().unwrap() = [None; 8192];
 
         visit(&mut f.ir);
     }
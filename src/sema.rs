@@ -1,6 +1,7 @@
-use parse::{Node, NodeType};
+use parse::{is_minmax, is_trap, Node, NodeType};
+use token::{catch_error, error_at, warn_at};
 use util::roundup;
-use {Ctype, Scope, TokenType, Type, Var};
+use {CompileError, Ctype, Diagnostic, Scope, Span, TokenType, Type, Var};
 
 use std::collections::HashMap;
 use std::mem;
@@ -36,6 +37,15 @@ macro_rules! matches(
     )
 );
 
+// Reports an error at the innermost node being analyzed that has a
+// source location.
+macro_rules! error(
+    ($($arg:tt)*) => ({
+        let span = SPAN.lock().unwrap().clone();
+        error_at(span, &format!($($arg)*))
+    })
+);
+
 fn swap(p: &mut Node, q: &mut Node) {
     mem::swap(p, q);
 }
@@ -45,11 +55,44 @@ lazy_static! {
     static ref ENV: Mutex<Env> = Mutex::new(Env::new(None));
     static ref STRLABEL: Mutex<usize> = Mutex::new(0);
     static ref STACKSIZE: Mutex<usize> = Mutex::new(0);
+    static ref FUNC_ARITY: Mutex<HashMap<String, usize>> = Mutex::new(HashMap::new());
+    // Return type of the function being analyzed. None inside a statement
+    // expression, where `return` gives the value of the expression instead.
+    static ref RETURN_TY: Mutex<Option<Type>> = Mutex::new(None);
+    static ref SPAN: Mutex<Option<Span>> = Mutex::new(None);
+    static ref MAX_NAME_LEN: Mutex<usize> = Mutex::new(DEFAULT_MAX_NAME_LEN);
+}
+
+// Longer symbol names are accepted but warned about, since some
+// assemblers and linkers cannot handle them.
+pub const DEFAULT_MAX_NAME_LEN: usize = 255;
+
+pub fn set_max_name_length(len: usize) {
+    *MAX_NAME_LEN.lock().unwrap() = len;
+}
+
+fn check_name_length(name: &str, span: &Option<Span>) {
+    let max = *MAX_NAME_LEN.lock().unwrap();
+    let len = name.chars().count();
+    if len > max {
+        let mut start: String = name.chars().take(16).collect();
+        if len > 16 {
+            start.push_str("...");
+        }
+        warn_at(
+            span.clone(),
+            &format!(
+                "name '{}' is {} characters long, more than {}",
+                start, len, max
+            ),
+        );
+    }
 }
 
 #[derive(Debug, Clone)]
 struct Env {
     vars: HashMap<String, Var>,
+    unused: Vec<(String, Option<Span>)>, // Local variables not referred to yet
     next: Option<Box<Env>>,
 }
 
@@ -57,6 +100,7 @@ impl Env {
     pub fn new(next: Option<Box<Env>>) -> Self {
         Env {
             vars: HashMap::new(),
+            unused: vec![],
             next,
         }
     }
@@ -68,10 +112,47 @@ fn into_new_range<T: Sized>(param: T, f: Box<Fn(T) -> T>) -> T {
     let ret = f(param);
     // Rollback
     let env = ENV.lock().unwrap().clone();
+    for (name, span) in env.unused {
+        warn_at(span, &format!("unused variable '{}'", name));
+    }
     *ENV.lock().unwrap() = *env.next.unwrap();
     ret
 }
 
+// Warns about the first statement in a block that follows a return,
+// break or continue. A case or default label makes the code after it
+// reachable again.
+fn check_reachable(stmts: &[Node]) {
+    let mut dead = false;
+    for stmt in stmts {
+        match stmt.op {
+            NodeType::Case(..) | NodeType::Default(_) => dead = false,
+            NodeType::Null => continue,
+            _ if dead => {
+                warn_at(stmt.span.clone(), "unreachable code");
+                return;
+            }
+            _ => (),
+        }
+        match stmt.op {
+            NodeType::Return(_) | NodeType::Break | NodeType::Continue => dead = true,
+            _ => (),
+        }
+    }
+}
+
+fn mark_used(name: &str) {
+    let mut env = ENV.lock().unwrap();
+    let mut next = Some(&mut *env);
+    while let Some(e) = next {
+        if e.vars.contains_key(name) {
+            e.unused.retain(|(n, _)| n != name);
+            return;
+        }
+        next = e.next.as_deref_mut();
+    }
+}
+
 fn find_var(name: &str) -> Option<Var> {
     let env = ENV.lock().unwrap().clone();
     let mut next: &Option<Box<Env>> = &Some(Box::new(env));
@@ -93,31 +174,266 @@ fn maybe_decay(base: Node, decay: bool) -> Node {
         return base;
     }
 
-    if let Ctype::Ary(ary_of, _) = base.ty.ty.clone() {
-        let mut node = Node::new(NodeType::Addr(Box::new(base)));
-        node.ty = Box::new(Type::ptr_to(ary_of.clone()));
+    match base.ty.ty.clone() {
+        Ctype::Ary(ary_of, _) => {
+            let mut node = Node::new(NodeType::Addr(Box::new(base)));
+            node.ty = Box::new(Type::ptr_to(ary_of.clone()));
+            node
+        }
+        // A function name stands for the function's address.
+        Ctype::Func(_) => {
+            let ty = base.ty.clone();
+            let mut node = Node::new(NodeType::Addr(Box::new(base)));
+            node.ty = Box::new(Type::ptr_to(ty));
+            node
+        }
+        _ => base,
+    }
+}
+
+fn is_func_ptr(ty: &Type) -> bool {
+    match ty.ty {
+        Ctype::Ptr(ref ptr_to) => matches!(ptr_to.ty, Ctype::Func(_)),
+        _ => false,
+    }
+}
+
+pub fn check_lval(node: &Node) -> Result<(), CompileError> {
+    let what = match node.op {
+        NodeType::Lvar(_)
+        | NodeType::Gvar(_, _, _)
+        | NodeType::Deref(_)
+        | NodeType::Dot(_, _, _) => return Ok(()),
+        NodeType::Ternary(_, _, _) | NodeType::Elvis(_, _) => "conditional expression".into(),
+        NodeType::Call(ref name, _) => format!("call to {}()", name),
+        NodeType::BinOp(ref op, _, _) => format!("binary {:?} expression", op),
+        ref op => format!("{:?}", op),
+    };
+    Err(CompileError::NotAnLvalue(what))
+}
+
+fn require_lval(node: &Node) {
+    if let Err(err) = check_lval(node) {
+        error!("{}", err);
+    }
+}
+
+// A struct that fits in a register is passed in one. A larger one is
+// copied by the caller to a temporary, and a pointer to the copy is
+// passed instead, like the Windows x64 ABI does. The SysV ABI copies it
+// to the stack, so such calls do not work with code built by gcc.
+fn pass_arg(arg: Node) -> Node {
+    if !arg.ty.in_memory() {
+        return arg;
+    }
+    let ty = *arg.ty.clone();
+    let mut tmp = Node::new(NodeType::Lvar(Scope::Local(alloc_local(&ty))));
+    tmp.ty = Box::new(ty.clone());
+    let mut assign = Node::new(NodeType::BinOp(
+        TokenType::Equal,
+        Box::new(tmp.clone()),
+        Box::new(arg),
+    ));
+    assign.ty = Box::new(ty.clone());
+    let mut copy = Node::new(NodeType::BinOp(
+        TokenType::Comma,
+        Box::new(assign),
+        Box::new(tmp),
+    ));
+    copy.ty = Box::new(ty);
+    copy
+}
+
+// The callee side of pass_arg(). The parameter holds the pointer, and
+// its name refers to the struct it points to.
+fn walk_param(mut param: Node) -> Node {
+    if !param.ty.in_memory() {
+        return walk(param, true);
+    }
+    let ty = param.ty.clone();
+    param.ty = Box::new(Type::ptr_to(ty.clone()));
+    let param = walk(param, true);
+    if let NodeType::Vardef(ref name, _, _) = param.op {
+        let mut env = ENV.lock().unwrap();
+        let var = env.vars.get_mut(name).unwrap();
+        var.ty = ty;
+        var.by_ref = true;
+    }
+    param
+}
+
+// A struct that does not fit in a register is returned through a hidden
+// first argument pointing to storage provided by the caller. The callee
+// copies the value there and returns the pointer. This is how the SysV
+// ABI returns structs of more than 16 bytes. Smaller ones are split
+// across two registers, which is not supported.
+const RET_PTR: &str = ".ret";
+
+fn check_return(ty: &Type) {
+    if ty.in_memory() && ty.size <= 16 {
+        error!("cannot return a struct of {} bytes by value", ty.size);
+    }
+}
+
+// Reserves a slot in the stack frame and returns its offset.
+fn alloc_local(ty: &Type) -> usize {
+    let mut stacksize = STACKSIZE.lock().unwrap();
+    *stacksize = roundup(*stacksize, ty.align) + ty.size;
+    *stacksize
+}
+
+// Passes a call returning `ty` the address of a temporary to return it
+// in, if it is returned in memory.
+fn add_ret_arg(ty: &Type, args: &mut Vec<Node>) {
+    if !ty.in_memory() {
+        return;
+    }
+    check_return(ty);
+    let mut tmp = Node::new(NodeType::Lvar(Scope::Local(alloc_local(ty))));
+    tmp.ty = Box::new(ty.clone());
+    let mut addr = Node::new(NodeType::Addr(Box::new(tmp)));
+    addr.ty = Box::new(Type::ptr_to(Box::new(ty.clone())));
+    args.insert(0, addr);
+}
+
+// A struct returned in a register has no address to take a member
+// from, so the call is stored to a temporary and the member is read
+// from there: `f().x` becomes `(*(tmp = f(), &tmp)).x`.
+fn spill_call(expr: Node) -> Node {
+    match expr.op {
+        NodeType::Call(_, _) | NodeType::CallPtr(_, _) => (),
+        _ => return expr,
+    }
+    match expr.ty.ty {
+        Ctype::Struct(_) if !expr.ty.in_memory() => (),
+        _ => return expr,
+    }
+    let ty = *expr.ty.clone();
+    let ptr = Type::ptr_to(Box::new(ty.clone()));
+    let typed = |op, ty: &Type| {
+        let mut node = Node::new(op);
+        node.ty = Box::new(ty.clone());
         node
+    };
+
+    let tmp = typed(NodeType::Lvar(Scope::Local(alloc_local(&ty))), &ty);
+    let assign = typed(
+        NodeType::BinOp(TokenType::Equal, Box::new(tmp.clone()), Box::new(expr)),
+        &ty,
+    );
+    let addr = typed(NodeType::Addr(Box::new(tmp)), &ptr);
+    let comma = typed(
+        NodeType::BinOp(TokenType::Comma, Box::new(assign), Box::new(addr)),
+        &ptr,
+    );
+    typed(NodeType::Deref(Box::new(comma)), &ty)
+}
+
+// Replaces `typeof(expr)` in a declared type with the type of `expr`,
+// which is analyzed but not evaluated.
+fn resolve_typeof(ty: Type) -> Type {
+    match ty.ty {
+        Ctype::Typeof(expr) => {
+            let mut resolved = *walk(*expr, false).ty;
+            resolved.volatile |= ty.volatile;
+            resolved.restrict |= ty.restrict;
+            resolved
+        }
+        Ctype::Ptr(base) => Type {
+            ty: Ctype::Ptr(Box::new(resolve_typeof(*base))),
+            ..ty
+        },
+        Ctype::Ary(base, len) => {
+            let base = resolve_typeof(*base);
+            Type {
+                size: base.size * len,
+                align: base.align,
+                ty: Ctype::Ary(Box::new(base), len),
+                ..ty
+            }
+        }
+        _ => ty,
+    }
+}
+
+fn cast(expr: Box<Node>, ty: Type) -> Box<Node> {
+    let mut node = Node::new(NodeType::Cast(expr));
+    node.ty = Box::new(ty);
+    Box::new(node)
+}
+
+// Integer promotion: a char operand of an arithmetic operator is
+// converted to int. A char is already zero-extended in its register, so
+// this changes only the type.
+fn promote(expr: Box<Node>) -> Box<Node> {
+    match expr.ty.ty {
+        Ctype::Char => cast(expr, Type::int_ty()),
+        _ => expr,
+    }
+}
+
+// The usual arithmetic conversions, as far as they matter here: char
+// operands are promoted to int. Otherwise the operand of lower rank is
+// converted to the type of the other one, with long ranking above int,
+// and an unsigned type above its signed one. Returns the converted
+// operands and the common type.
+fn usual_arith_conv(lhs: Box<Node>, rhs: Box<Node>) -> (Box<Node>, Box<Node>, Box<Type>) {
+    let lhs = promote(lhs);
+    let rhs = promote(rhs);
+    let (l, r) = match (int_rank(&lhs.ty), int_rank(&rhs.ty)) {
+        (Some(l), Some(r)) => (l, r),
+        _ => {
+            let ty = lhs.ty.clone();
+            return (lhs, rhs, ty);
+        }
+    };
+    // A long as wide as an int cannot hold every unsigned int.
+    let ty = if l.max(r) == 2 && l.min(r) == 1 && Type::long_ty().size == 4 {
+        Type::ulong_ty()
+    } else if l >= r {
+        *lhs.ty.clone()
+    } else {
+        *rhs.ty.clone()
+    };
+    let rank = int_rank(&ty);
+    let lhs = if int_rank(&lhs.ty) == rank {
+        lhs
+    } else {
+        cast(lhs, ty.clone())
+    };
+    let rhs = if int_rank(&rhs.ty) == rank {
+        rhs
     } else {
-        base
+        cast(rhs, ty.clone())
+    };
+    (lhs, rhs, Box::new(ty))
+}
+
+fn int_rank(ty: &Type) -> Option<u8> {
+    match ty.ty {
+        Ctype::Int => Some(0),
+        Ctype::Uint => Some(1),
+        Ctype::Long => Some(2),
+        Ctype::Ulong => Some(3),
+        _ => None,
     }
 }
 
-fn check_lval(node: &Node) {
-    let op = &node.op;
-    if !matches!(op, NodeType::Lvar(_))
-        && !matches!(op, NodeType::Gvar(_, _, _))
-        && !matches!(op, NodeType::Deref(_))
-        && !matches!(op, NodeType::Dot(_, _, _))
-    {
-        panic!("not an lvalue: {:?}", node.op);
+fn walk(node: Node, decay: bool) -> Node {
+    let outer = SPAN.lock().unwrap().clone();
+    if node.span.is_some() {
+        *SPAN.lock().unwrap() = node.span.clone();
     }
+    let node = walk_node(node, decay);
+    *SPAN.lock().unwrap() = outer;
+    node
 }
 
-fn walk(mut node: Node, decay: bool) -> Node {
+fn walk_node(mut node: Node, decay: bool) -> Node {
     use self::NodeType::*;
     let op = node.op.clone();
     match op {
-        Num(_) | Null | Break => (),
+        Num(_) | Null | Break | Continue | InlineAsm(_) => (),
         Str(data, len) => {
             // Quoted from 9cc
             // > A string literal is converted to a reference to an anonymous
@@ -133,8 +449,16 @@ fn walk(mut node: Node, decay: bool) -> Node {
             return maybe_decay(ret, decay);
         }
         Ident(ref name) => {
+            mark_used(name);
             if let Some(var) = find_var(name) {
                 match var.scope {
+                    Scope::Local(offset) if var.by_ref => {
+                        let mut ptr = Node::new(NodeType::Lvar(Scope::Local(offset)));
+                        ptr.ty = Box::new(Type::ptr_to(var.ty.clone()));
+                        let mut ret = Node::new(NodeType::Deref(Box::new(ptr)));
+                        ret.ty = var.ty.clone();
+                        return maybe_decay(ret, decay);
+                    }
                     Scope::Local(offset) => {
                         let mut ret = Node::new(NodeType::Lvar(Scope::Local(offset)));
                         ret.ty = var.ty.clone();
@@ -148,19 +472,25 @@ fn walk(mut node: Node, decay: bool) -> Node {
                     }
                 }
             } else {
-                panic!("undefined variable: {}", name);
+                error!("undefined variable: {}", name);
             }
         }
         Vardef(name, init_may, _) => {
-            let stacksize = *STACKSIZE.lock().unwrap();
-            *STACKSIZE.lock().unwrap() = roundup(stacksize, node.ty.align);
-            *STACKSIZE.lock().unwrap() += node.ty.size;
-            let offset = *STACKSIZE.lock().unwrap();
-
-            ENV.lock().unwrap().vars.insert(
-                name.clone(),
-                Var::new(node.ty.clone(), name.clone(), Scope::Local(offset)),
-            );
+            node.ty = Box::new(resolve_typeof(*node.ty));
+            let offset = alloc_local(&node.ty);
+
+            {
+                let mut env = ENV.lock().unwrap();
+                env.vars.insert(
+                    name.clone(),
+                    Var::new(node.ty.clone(), name.clone(), Scope::Local(offset)),
+                );
+                // Parameters live in the outermost scope and are never
+                // reported as unused.
+                if env.next.is_some() {
+                    env.unused.push((name.clone(), node.span.clone()));
+                }
+            }
 
             let mut init = None;
             if let Some(mut init2) = init_may {
@@ -184,6 +514,12 @@ fn walk(mut node: Node, decay: bool) -> Node {
             node.ty = then.ty.clone();
             node.op = Ternary(cond, then, els);
         }
+        Elvis(mut cond, mut els) => {
+            cond = Box::new(walk(*cond, true));
+            els = Box::new(walk(*els, true));
+            node.ty = cond.ty.clone();
+            node.op = Elvis(cond, els);
+        }
         For(init, cond, inc, body) => {
             let f = |(init, cond, inc, body)| -> (Node, Node, Node, Node) {
                 (
@@ -201,15 +537,20 @@ fn walk(mut node: Node, decay: bool) -> Node {
                 Box::new(body),
             );
         }
+        Switch(cond, body) => {
+            node.op = Switch(Box::new(walk(*cond, true)), Box::new(walk(*body, true)));
+        }
+        Case(val, body) => node.op = Case(val, Box::new(walk(*body, true))),
+        Default(body) => node.op = Default(Box::new(walk(*body, true))),
         DoWhile(body, cond) => {
             node.op = DoWhile(Box::new(walk(*body, true)), Box::new(walk(*cond, true)));
         }
         Dot(mut expr, name, _) => {
-            expr = Box::new(walk(*expr, true));
+            expr = Box::new(spill_call(walk(*expr, true)));
             let offset;
             if let Ctype::Struct(ref members) = expr.ty.ty {
                 if members.is_empty() {
-                    panic!("incomplete type");
+                    error!("incomplete type");
                 }
                 let m_may = members.iter().find(|m| {
                     if let NodeType::Vardef(ref m_name, _, _) = m.op {
@@ -229,10 +570,10 @@ fn walk(mut node: Node, decay: bool) -> Node {
                         unreachable!()
                     }
                 } else {
-                    panic!("member missing: {}", name);
+                    error!("member missing: {}", name);
                 }
             } else {
-                panic!("struct expected before '.'");
+                error!("struct expected before '.'");
             }
 
             node.op = NodeType::Dot(expr, name, offset);
@@ -249,19 +590,20 @@ fn walk(mut node: Node, decay: bool) -> Node {
                         swap(&mut lhs, &mut rhs);
                     }
                     if matches!(rhs.ty.ty, Ctype::Ptr(_)) {
-                        panic!("'pointer {:?} pointer' is not defined", node.op)
+                        error!("'pointer {:?} pointer' is not defined", node.op)
                     }
 
                     if matches!(lhs.ty.ty, Ctype::Ptr(_)) {
                         rhs = Box::new(Node::scale_ptr(rhs, &lhs.ty));
                     }
 
-                    node.op = BinOp(token_type, lhs.clone(), rhs);
-                    node.ty = lhs.ty;
+                    let (lhs, rhs, ty) = usual_arith_conv(lhs, rhs);
+                    node.op = BinOp(token_type, lhs, rhs);
+                    node.ty = ty;
                 }
                 AddEQ | SubEQ => {
                     lhs = Box::new(walk(*lhs, false));
-                    check_lval(&*lhs);
+                    require_lval(&*lhs);
                     rhs = Box::new(walk(*rhs, true));
 
                     if matches!(lhs.ty.ty, Ctype::Ptr(_)) {
@@ -272,10 +614,36 @@ fn walk(mut node: Node, decay: bool) -> Node {
                 }
                 Equal | MulEQ | DivEQ | ModEQ | ShlEQ | ShrEQ | BitandEQ | XorEQ | BitorEQ => {
                     lhs = Box::new(walk(*lhs, false));
-                    check_lval(&*lhs);
+                    require_lval(&*lhs);
                     node.op = BinOp(token_type, lhs.clone(), Box::new(walk(*rhs, true)));
                     node.ty = lhs.ty;
                 }
+                Mul | Div | Mod | And | VerticalBar | Hat => {
+                    let (lhs, rhs, ty) =
+                        usual_arith_conv(Box::new(walk(*lhs, true)), Box::new(walk(*rhs, true)));
+                    node.op = BinOp(token_type, lhs, rhs);
+                    node.ty = ty;
+                }
+                EQ | NE | LE | LeftAngleBracket | RightAngleBracket => {
+                    let (lhs, rhs, _) =
+                        usual_arith_conv(Box::new(walk(*lhs, true)), Box::new(walk(*rhs, true)));
+                    node.op = BinOp(token_type, lhs, rhs);
+                    node.ty = Box::new(Type::int_ty());
+                }
+                // The result has the promoted type of the left operand.
+                SHL | SHR => {
+                    lhs = promote(Box::new(walk(*lhs, true)));
+                    rhs = promote(Box::new(walk(*rhs, true)));
+                    node.op = BinOp(token_type, lhs.clone(), rhs);
+                    node.ty = lhs.ty;
+                }
+                // Yields 0 or 1 whatever the operands are, pointers included.
+                Logand | Logor => {
+                    lhs = Box::new(walk(*lhs, true));
+                    rhs = Box::new(walk(*rhs, true));
+                    node.op = BinOp(token_type, lhs, rhs);
+                    node.ty = Box::new(Type::int_ty());
+                }
                 _ => {
                     lhs = Box::new(walk(*lhs, true));
                     rhs = Box::new(walk(*rhs, true));
@@ -295,18 +663,35 @@ fn walk(mut node: Node, decay: bool) -> Node {
             node.op = PostDec(expr);
         }
         Neg(mut expr) => {
-            expr = Box::new(walk(*expr, true));
+            expr = promote(Box::new(walk(*expr, true)));
             node.ty = expr.ty.clone();
             node.op = Neg(expr);
         }
+        Cast(mut expr) => {
+            expr = Box::new(walk(*expr, true));
+            node.ty = Box::new(resolve_typeof(*node.ty));
+            match node.ty.ty {
+                Ctype::Struct(_) | Ctype::Ary(_, _) | Ctype::Func(_) => {
+                    error!("cannot cast to {:?}", node.ty.ty)
+                }
+                _ => (),
+            }
+            node.op = Cast(expr);
+        }
         Exclamation(mut expr) => {
             expr = Box::new(walk(*expr, true));
-            node.ty = expr.ty.clone();
+            node.ty = Box::new(Type::int_ty());
             node.op = Exclamation(expr);
         }
         Addr(mut expr) => {
             expr = Box::new(walk(*expr, true));
-            check_lval(&*expr);
+            // `&f` is the same as `f`, which has decayed already.
+            if let Addr(ref f) = expr.op {
+                if let Ctype::Func(_) = f.ty.ty {
+                    return *expr;
+                }
+            }
+            require_lval(&*expr);
             node.ty = Box::new(Type::ptr_to(expr.ty.clone()));
             node.op = Addr(expr);
         }
@@ -314,21 +699,92 @@ fn walk(mut node: Node, decay: bool) -> Node {
             expr = Box::new(walk(*expr, true));
             match expr.ty.ty {
                 Ctype::Ptr(ref ptr_to) => node.ty = ptr_to.clone(),
-                Ctype::Void => panic!("cannot dereference void pointer"),
-                _ => panic!("operand must be a pointer"),
+                Ctype::Void => error!("cannot dereference void pointer"),
+                _ => error!("operand must be a pointer"),
             }
             node.op = Deref(expr);
             return maybe_decay(node, decay);
         }
-        Return(expr) => node.op = Return(Box::new(walk(*expr, true))),
+        Return(expr) => {
+            let ret = RETURN_TY.lock().unwrap().clone();
+            if let Some(ty) = ret {
+                if ty.in_memory() && !expr.is_null() {
+                    let ptr = Box::new(Node::new(Ident(RET_PTR.into())));
+                    let assign = BinOp(TokenType::Equal, Box::new(Node::new(Deref(ptr))), expr);
+                    let copy = Node::new(ExprStmt(Box::new(Node::new(assign))));
+                    let ptr = walk(Node::new(Ident(RET_PTR.into())), true);
+                    node.op = VecStmt(vec![walk(copy, true), Node::new(Return(Box::new(ptr)))]);
+                    return node;
+                }
+                match (ty.ty, expr.is_null()) {
+                    (Ctype::Void, false) => {
+                        error!("return with a value in function returning void")
+                    }
+                    (Ctype::Void, true) | (_, false) => (),
+                    (_, true) => error!("return with no value in function returning non-void"),
+                }
+            }
+            let expr = walk(*expr, true);
+            // The frame is gone once the function returns, so the
+            // pointer dangles. This includes a local array that decays.
+            if let Addr(ref var) = expr.op {
+                if let Lvar(Scope::Local(_)) = var.op {
+                    let span = expr.span.clone().or_else(|| SPAN.lock().unwrap().clone());
+                    warn_at(span, "function returns address of local variable");
+                }
+            }
+            node.op = Return(Box::new(expr))
+        }
         ExprStmt(expr) => node.op = ExprStmt(Box::new(walk(*expr, true))),
+        // Both yield a size_t. That would be an unsigned long, but there
+        // is no `long` keyword to declare one with, so an unsigned int is
+        // used, which holds any size this compiler can handle.
         Sizeof(mut expr) => {
             expr = Box::new(walk(*expr, false));
-            node = Node::new_int(expr.ty.size as i32)
+            node = Node::new_int(expr.ty.size as i32);
+            node.ty = Box::new(Type::uint_ty());
         }
         Alignof(mut expr) => {
             expr = Box::new(walk(*expr, false));
-            node = Node::new_int(expr.ty.align as i32)
+            node = Node::new_int(expr.ty.align as i32);
+            node.ty = Box::new(Type::uint_ty());
+        }
+        Call(name, args) if is_minmax(&name) => {
+            if args.len() != 2 {
+                error!("{}: 2 arguments expected, but got {}", name, args.len());
+            }
+            let mut args = args.into_iter().map(|arg| Box::new(walk(arg, true)));
+            let (lhs, rhs, ty) = usual_arith_conv(args.next().unwrap(), args.next().unwrap());
+            node.ty = ty;
+            node.op = Call(name, vec![*lhs, *rhs]);
+        }
+        Call(ref name, ref args) if is_trap(name) => {
+            if !args.is_empty() {
+                error!("{}: 0 arguments expected, but got {}", name, args.len());
+            }
+            node.ty = Box::new(Type::void_ty());
+        }
+        Call(name, args) if find_var(&name).is_some_and(|var| is_func_ptr(&var.ty)) => {
+            let mut callee = Node::new(Ident(name));
+            callee.span = node.span.clone();
+            node.op = CallPtr(Box::new(callee), args);
+            return walk_node(node, decay);
+        }
+        CallPtr(callee, mut args) => {
+            let callee = walk(*callee, true);
+            match callee.ty.ty {
+                Ctype::Ptr(ref ptr_to) => match ptr_to.ty {
+                    Ctype::Func(ref returning) => node.ty = returning.clone(),
+                    _ => error!("called object is not a function"),
+                },
+                _ => error!("called object is not a function"),
+            }
+            args = args
+                .into_iter()
+                .map(|arg| pass_arg(walk(arg, true)))
+                .collect();
+            add_ret_arg(&node.ty, &mut args);
+            node.op = CallPtr(Box::new(callee), args);
         }
         Call(name, mut args) => {
             if let Some(var) = find_var(&name) {
@@ -341,10 +797,29 @@ fn walk(mut node: Node, decay: bool) -> Node {
                 eprint!("bad function: {}", name);
             }
 
-            args = args.into_iter().map(|arg| walk(arg, true)).collect();
+            // Only definitions are checked. A declaration such as `int printf();`
+            // says nothing about its parameters.
+            let nargs = FUNC_ARITY.lock().unwrap().get(&name).cloned();
+            if let Some(nargs) = nargs {
+                if nargs != args.len() {
+                    error!(
+                        "{}: {} arguments expected, but got {}",
+                        name,
+                        nargs,
+                        args.len()
+                    );
+                }
+            }
+
+            args = args
+                .into_iter()
+                .map(|arg| pass_arg(walk(arg, true)))
+                .collect();
+            add_ret_arg(&node.ty, &mut args);
             node.op = Call(name, args);
         }
         CompStmt(mut stmts) => {
+            check_reachable(&stmts);
             let f = |stmts: Vec<Node>| -> Vec<Node> {
                 stmts.into_iter().map(|stmt| walk(stmt, true)).collect()
             };
@@ -356,7 +831,9 @@ fn walk(mut node: Node, decay: bool) -> Node {
             node.op = VecStmt(stmts);
         }
         StmtExpr(body) => {
+            let ret = RETURN_TY.lock().unwrap().take();
             node.op = StmtExpr(Box::new(walk(*body, true)));
+            *RETURN_TY.lock().unwrap() = ret;
             node.ty = Box::new(Type::int_ty())
         }
         _ => panic!("unknown node type"),
@@ -364,45 +841,111 @@ fn walk(mut node: Node, decay: bool) -> Node {
     node
 }
 
-pub fn sema(nodes: Vec<Node>) -> (Vec<Node>, Vec<Var>) {
+fn walk_func(mut node: Node) -> Node {
+    if let NodeType::Func(name, args, body, _) = node.op {
+        let mut args2 = vec![];
+        for arg in args {
+            args2.push(walk_param(arg));
+        }
+        if let Ctype::Func(ref returning) = node.ty.ty {
+            if returning.in_memory() {
+                check_return(returning);
+                let mut ptr = Node::new(NodeType::Vardef(RET_PTR.into(), None, Scope::Local(0)));
+                ptr.ty = Box::new(Type::ptr_to(returning.clone()));
+                args2.insert(0, walk(ptr, true));
+            }
+            *RETURN_TY.lock().unwrap() = Some(*returning.clone());
+        }
+        let body2 = walk(*body, true);
+        *RETURN_TY.lock().unwrap() = None;
+        node.op = NodeType::Func(name, args2, Box::new(body2), *STACKSIZE.lock().unwrap());
+        *STACKSIZE.lock().unwrap() = 0;
+        return node;
+    }
+    unreachable!()
+}
+
+// If `recover` is set, an error in a function is recorded and analysis
+// goes on with the next function.
+fn analyze(nodes: Vec<Node>, recover: bool) -> (Vec<Node>, Vec<Var>, Vec<Diagnostic>) {
+    // Start afresh in case a program has been analyzed before.
+    *GLOBALS.lock().unwrap() = vec![];
+    *ENV.lock().unwrap() = Env::new(None);
+    *STRLABEL.lock().unwrap() = 0;
+    *STACKSIZE.lock().unwrap() = 0;
+    FUNC_ARITY.lock().unwrap().clear();
+    *RETURN_TY.lock().unwrap() = None;
+    *SPAN.lock().unwrap() = None;
+
     let mut new_nodes = vec![];
+    let mut errors = vec![];
+
+    // Register all functions before walking any of their bodies,
+    // so that a function can call another one defined later in the file.
+    for node in &nodes {
+        match node.op {
+            NodeType::Func(ref name, ref args, _, _) => {
+                let var = Var::new_global(node.ty.clone(), name.clone(), "".into(), 0, false);
+                ENV.lock().unwrap().vars.insert(name.clone(), var);
+                FUNC_ARITY.lock().unwrap().insert(name.clone(), args.len());
+            }
+            NodeType::Decl(ref name) => {
+                let var = Var::new_global(node.ty.clone(), name.clone(), "".into(), 0, false);
+                ENV.lock().unwrap().vars.insert(name.clone(), var);
+            }
+            NodeType::Vardef(_, _, _) => (),
+            _ => unreachable!(),
+        }
+    }
 
-    for mut node in nodes {
+    for node in nodes {
+        match node.op {
+            NodeType::Func(ref name, _, _, _)
+            | NodeType::Vardef(ref name, _, Scope::Global(_, _, false)) => {
+                check_name_length(name, &node.span)
+            }
+            _ => (),
+        }
         if let NodeType::Vardef(name, _, Scope::Global(data, len, is_extern)) = node.op {
-            let var = Var::new_global(node.ty, name.clone(), data, len, is_extern);
+            let ty = resolve_typeof(*node.ty);
+            let len = if is_extern { len } else { ty.size };
+            let var = Var::new_global(Box::new(ty), name.clone(), data, len, is_extern);
             GLOBALS.lock().unwrap().push(var.clone());
             ENV.lock().unwrap().vars.insert(name, var);
             continue;
         }
 
-        let mut var;
-        match &node.op {
-            NodeType::Func(name, _, _, _) | NodeType::Decl(name) => {
-                var = Var::new_global(node.ty.clone(), name.clone(), "".into(), 0, false);
-                ENV.lock().unwrap().vars.insert(name.clone(), var);
-            }
-            _ => unreachable!(),
+        if matches!(node.op, NodeType::Decl(_)) {
+            continue;
         }
 
-        if matches!(node.op, NodeType::Decl(_)) {
+        if !recover {
+            new_nodes.push(walk_func(node));
             continue;
         }
 
-        if let NodeType::Func(name, args, body, _) = node.op {
-            let mut args2 = vec![];
-            for arg in args {
-                args2.push(walk(arg, true));
+        let env = ENV.lock().unwrap().clone();
+        match catch_error(|| walk_func(node)) {
+            Ok(node) => new_nodes.push(node),
+            Err(err) => {
+                errors.push(err);
+                *ENV.lock().unwrap() = env;
+                *STACKSIZE.lock().unwrap() = 0;
+                *RETURN_TY.lock().unwrap() = None;
+                *SPAN.lock().unwrap() = None;
             }
-            let body2 = walk(*body, true);
-            node.op = NodeType::Func(
-                name.clone(),
-                args2,
-                Box::new(body2),
-                *STACKSIZE.lock().unwrap(),
-            );
-            *STACKSIZE.lock().unwrap() = 0;
-            new_nodes.push(node);
         }
     }
-    (new_nodes, GLOBALS.lock().unwrap().clone())
+    (new_nodes, GLOBALS.lock().unwrap().clone(), errors)
+}
+
+pub fn sema(nodes: Vec<Node>) -> (Vec<Node>, Vec<Var>) {
+    let (nodes, globals, _) = analyze(nodes, false);
+    (nodes, globals)
+}
+
+// Same as sema(), but reports every function with an error instead of
+// stopping at the first one. Returns the errors found.
+pub fn check(nodes: Vec<Node>) -> Vec<Diagnostic> {
+    analyze(nodes, true).2
 }
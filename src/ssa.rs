@@ -0,0 +1,473 @@
+use cfg::{basic_blocks, dominance_frontiers, dominators, BasicBlock, BlockId};
+use gen_ir::{Function, IROp, IR};
+
+use std::collections::{HashMap, HashSet};
+use std::mem;
+
+// Conversion to static single assignment (SSA) form.
+//
+// gen_ir gives most registers a single definition, but the result of a
+// conditional, of && and || or of a statement expression is assigned on
+// several paths. to_ssa() gives such a register a new name in each block
+// that assigns it, and inserts a PHI at the start of each block where
+// paths with different names meet while the value is still needed. A
+// PHI picks the name for the block that control came from.
+//
+// The IR is two-address: ADD r1, r2 both reads and writes r1. So a name
+// may still be assigned more than once, but only within its own block.
+// If the first assignment in a block also reads the register, the
+// incoming value is copied to the new name with a MOV first.
+//
+// Kills of the renamed registers are dropped, since they no longer say
+// anything about the new names. Blocks that cannot be reached keep their
+// old names.
+//
+// from_ssa() turns the PHIs back into MOVs, which the later passes
+// understand.
+
+struct Phi {
+    reg: usize, // The register before renaming
+    dst: usize,
+    args: Vec<(BlockId, usize)>,
+}
+
+// Applies `f` to every register that `ir` reads.
+fn map_uses<F: FnMut(usize) -> usize>(ir: &mut IR, mut f: F) {
+    use self::IROp::*;
+    match ir.op {
+        Add | Sub | Mul | Div | UDiv | Mod | UMod | EQ | NE | LE | LT | ULE | ULT | AND | OR
+        | XOR | SHL | SHR | SAR | Store(_) | VolatileStore(_) => {
+            ir.lhs = ir.lhs.map(&mut f);
+            ir.rhs = ir.rhs.map(&mut f);
+        }
+        AddImm | SubImm | MulImm | Neg | Trunc | Zext | Return | If | Unless => {
+            ir.lhs = ir.lhs.map(&mut f)
+        }
+        Mov | Load(_) | VolatileLoad(_) => ir.rhs = ir.rhs.map(&mut f),
+        Call(_, ref mut args, _) => {
+            for arg in args.iter_mut() {
+                *arg = f(*arg);
+            }
+        }
+        CallReg(ref mut r, ref mut args, _) => {
+            *r = f(*r);
+            for arg in args.iter_mut() {
+                *arg = f(*arg);
+            }
+        }
+        CMov(ref mut cond) => {
+            *cond = f(*cond);
+            ir.lhs = ir.lhs.map(&mut f);
+            ir.rhs = ir.rhs.map(&mut f);
+        }
+        IfEq(ref mut r) => {
+            *r = f(*r);
+            ir.lhs = ir.lhs.map(&mut f);
+        }
+        Phi(ref mut args) => {
+            for &mut (_, ref mut r) in args.iter_mut() {
+                *r = f(*r);
+            }
+        }
+        Imm | Bprel | Label | LabelAddr(_) | Jmp | StoreArg(_) | Asm(_) | Trap | Kill | Nop => (),
+    }
+}
+
+// Which of `regs` are live at the start of each block.
+fn live_in(code: &[IR], blocks: &[BasicBlock], regs: &HashSet<usize>) -> Vec<HashSet<usize>> {
+    let mut used = vec![HashSet::new(); blocks.len()];
+    let mut defined = vec![HashSet::new(); blocks.len()];
+    for (id, b) in blocks.iter().enumerate() {
+        for ir in &code[b.start..b.end] {
+            for r in ir.uses() {
+                if regs.contains(&r) && !defined[id].contains(&r) {
+                    used[id].insert(r);
+                }
+            }
+            if let Some(r) = ir.def().filter(|r| regs.contains(r)) {
+                defined[id].insert(r);
+            }
+        }
+    }
+
+    let mut live: Vec<HashSet<usize>> = used.clone();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for id in (0..blocks.len()).rev() {
+            let mut set = used[id].clone();
+            for &s in &blocks[id].succ {
+                set.extend(live[s].difference(&defined[id]));
+            }
+            if set != live[id] {
+                live[id] = set;
+                changed = true;
+            }
+        }
+    }
+    live
+}
+
+struct Renamer<'a> {
+    blocks: &'a [BasicBlock],
+    children: Vec<Vec<BlockId>>,
+    regs: HashSet<usize>,
+    code: Vec<Vec<IR>>,
+    phis: Vec<Vec<Phi>>,
+    names: HashMap<usize, Vec<usize>>, // The current name is the last one
+    next_reg: usize,
+}
+
+impl<'a> Renamer<'a> {
+    fn current(&self, r: usize) -> usize {
+        self.names
+            .get(&r)
+            .and_then(|v| v.last().cloned())
+            .unwrap_or(r)
+    }
+
+    // Renames the registers in block `id` and in the blocks it
+    // dominates.
+    fn rename(&mut self, id: BlockId) {
+        let mut pushed = vec![];
+        let mut named = HashSet::new();
+        for i in 0..self.phis[id].len() {
+            let dst = self.next_reg;
+            self.next_reg += 1;
+            let phi = &mut self.phis[id][i];
+            phi.dst = dst;
+            self.names.entry(phi.reg).or_default().push(dst);
+            pushed.push(phi.reg);
+            named.insert(phi.reg);
+        }
+
+        let mut code = vec![];
+        for mut ir in mem::take(&mut self.code[id]) {
+            let def = ir.def().filter(|r| self.regs.contains(r));
+            let reads_def = def.is_some_and(|r| ir.uses().contains(&r));
+            map_uses(&mut ir, |r| self.current(r));
+
+            if let Some(r) = def {
+                if named.insert(r) {
+                    let new = self.next_reg;
+                    self.next_reg += 1;
+                    if reads_def {
+                        code.push(IR {
+                            op: IROp::Mov,
+                            lhs: Some(new),
+                            rhs: Some(self.current(r)),
+                        });
+                    }
+                    self.names.entry(r).or_default().push(new);
+                    pushed.push(r);
+                }
+                ir.lhs = Some(self.current(r));
+            }
+            code.push(ir);
+        }
+        self.code[id] = code;
+
+        for &s in &self.blocks[id].succ {
+            for i in 0..self.phis[s].len() {
+                let arg = self.current(self.phis[s][i].reg);
+                self.phis[s][i].args.push((id, arg));
+            }
+        }
+
+        for c in self.children[id].clone() {
+            self.rename(c);
+        }
+        for r in pushed {
+            self.names.get_mut(&r).unwrap().pop();
+        }
+    }
+}
+
+pub fn to_ssa(f: &mut Function) {
+    let blocks = basic_blocks(f);
+    if blocks.is_empty() {
+        return;
+    }
+
+    // Registers assigned in more than one block.
+    let mut def_blocks: HashMap<usize, Vec<BlockId>> = HashMap::new();
+    for (id, b) in blocks.iter().enumerate() {
+        for r in f.ir[b.start..b.end].iter().filter_map(|ir| ir.def()) {
+            let v = def_blocks.entry(r).or_default();
+            if !v.contains(&id) {
+                v.push(id);
+            }
+        }
+    }
+    def_blocks.retain(|_, v| v.len() > 1);
+    let regs: HashSet<usize> = def_blocks.keys().cloned().collect();
+
+    // A PHI goes at the dominance frontier of every block assigning the
+    // register, and a PHI is itself an assignment.
+    let live = live_in(&f.ir, &blocks, &regs);
+    let df = dominance_frontiers(f);
+    let mut phis: Vec<Vec<Phi>> = blocks.iter().map(|_| vec![]).collect();
+    let mut sorted: Vec<usize> = regs.iter().cloned().collect();
+    sorted.sort();
+    for r in sorted {
+        let mut work = def_blocks[&r].clone();
+        let mut placed = HashSet::new();
+        while let Some(b) = work.pop() {
+            for &d in df.get(&b).map_or(&[][..], |v| &v[..]) {
+                if live[d].contains(&r) && placed.insert(d) {
+                    phis[d].push(Phi {
+                        reg: r,
+                        dst: 0,
+                        args: vec![],
+                    });
+                    if !def_blocks[&r].contains(&d) {
+                        work.push(d);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut children = vec![vec![]; blocks.len()];
+    let mut idom: Vec<(BlockId, BlockId)> = dominators(f).into_iter().collect();
+    idom.sort();
+    for (b, d) in idom {
+        children[d].push(b);
+    }
+
+    let code = blocks
+        .iter()
+        .map(|b| {
+            f.ir[b.start..b.end]
+                .iter()
+                .filter(|ir| ir.op != IROp::Kill || !regs.contains(&ir.lhs.unwrap()))
+                .cloned()
+                .collect()
+        })
+        .collect();
+
+    let mut renamer = Renamer {
+        blocks: &blocks,
+        children,
+        regs,
+        code,
+        phis,
+        names: HashMap::new(),
+        next_reg: f.reg_count(),
+    };
+    renamer.rename(0);
+
+    let mut ir = vec![];
+    for (code, phis) in renamer.code.into_iter().zip(renamer.phis) {
+        let mut code = code.into_iter().peekable();
+        if code.peek().is_some_and(|ir| ir.op == IROp::Label) {
+            ir.extend(code.next());
+        }
+        for mut phi in phis {
+            phi.args.sort();
+            ir.push(IR {
+                op: IROp::Phi(phi.args),
+                lhs: Some(phi.dst),
+                rhs: None,
+            });
+        }
+        ir.extend(code);
+    }
+    f.ir = ir;
+}
+
+// Orders copies that are meant to happen at once, so that none
+// overwrites a register that another one still has to read. When the
+// remaining copies form a cycle, like the swap a, b = b, a, one
+// destination is saved to the scratch register `tmp` first.
+fn sequentialize(mut copies: Vec<(usize, usize)>, next_reg: &mut usize) -> Vec<IR> {
+    let mov = |dst, src| IR {
+        op: IROp::Mov,
+        lhs: Some(dst),
+        rhs: Some(src),
+    };
+    copies.retain(|&(dst, src)| dst != src);
+
+    let mut code = vec![];
+    while !copies.is_empty() {
+        let free = copies
+            .iter()
+            .position(|&(dst, _)| copies.iter().all(|&(_, src)| src != dst));
+        match free {
+            Some(i) => {
+                let (dst, src) = copies.remove(i);
+                code.push(mov(dst, src));
+            }
+            None => {
+                let tmp = *next_reg;
+                *next_reg += 1;
+                let saved = copies[0].0;
+                code.push(mov(tmp, saved));
+                for copy in &mut copies {
+                    if copy.1 == saved {
+                        copy.1 = tmp;
+                    }
+                }
+            }
+        }
+    }
+    code
+}
+
+// Gives every register that is never killed a kill after the last
+// instruction at which it is live. to_ssa() drops the kills of the
+// registers it renames, and the copies made by from_ssa() introduce new
+// registers, but the register allocator needs a kill for each.
+fn add_kills(f: &mut Function) {
+    let killed: HashSet<usize> =
+        f.ir.iter()
+            .filter(|ir| ir.op == IROp::Kill)
+            .filter_map(|ir| ir.lhs)
+            .collect();
+
+    let mut last: HashMap<usize, usize> = HashMap::new();
+    for (i, ir) in f.ir.iter().enumerate() {
+        for r in ir.uses().into_iter().chain(ir.def()) {
+            if !killed.contains(&r) {
+                last.insert(r, i);
+            }
+        }
+    }
+
+    // A register that is live out of a block must stay allocated at
+    // least up to the end of that block, which may come later in the
+    // code than the register's last use when the block loops back.
+    let regs: HashSet<usize> = last.keys().cloned().collect();
+    let blocks = basic_blocks(f);
+    let live = live_in(&f.ir, &blocks, &regs);
+    for b in &blocks {
+        for &s in &b.succ {
+            for r in &live[s] {
+                let end = last.get_mut(r).unwrap();
+                *end = (*end).max(b.end - 1);
+            }
+        }
+    }
+
+    let mut kills: Vec<(usize, usize)> = last.into_iter().map(|(r, i)| (i, r)).collect();
+    kills.sort();
+    let mut kills = kills.into_iter().peekable();
+    let mut code = vec![];
+    for (i, ir) in mem::take(&mut f.ir).into_iter().enumerate() {
+        code.push(ir);
+        while let Some(&(_, r)) = kills.peek().filter(|&&(j, _)| j == i) {
+            code.push(IR {
+                op: IROp::Kill,
+                lhs: Some(r),
+                rhs: None,
+            });
+            kills.next();
+        }
+    }
+    f.ir = code;
+}
+
+// Translates out of SSA form by replacing each PHI with a MOV on every
+// edge into its block, so that the register allocator and the backends,
+// which know nothing of PHI, can run.
+//
+// The copies for an edge go at the end of the predecessor. If that
+// block ends with a conditional jump, they must only run when control
+// takes the edge: the copies for falling through go right after the
+// jump, and the ones for the jump target get a block of their own, to
+// which the jump is redirected. That block is placed just before the
+// target, so that the registers it copies are not kept alive across
+// unrelated code.
+pub fn from_ssa(f: &mut Function) {
+    let blocks = basic_blocks(f);
+    let mut next_reg = f.reg_count();
+    let mut next_label = f.label_count();
+
+    let mut copies: HashMap<(BlockId, BlockId), Vec<(usize, usize)>> = HashMap::new();
+    for (id, b) in blocks.iter().enumerate() {
+        for ir in &f.ir[b.start..b.end] {
+            if let IROp::Phi(ref args) = ir.op {
+                for &(pred, r) in args {
+                    let v = copies.entry((pred, id)).or_default();
+                    v.push((ir.lhs.unwrap(), r));
+                }
+            }
+        }
+    }
+    let mut edge = |from: BlockId, to: BlockId| {
+        let v = copies.remove(&(from, to)).unwrap_or_default();
+        sequentialize(v, &mut next_reg)
+    };
+
+    let mut bodies = vec![];
+    let mut split: HashMap<BlockId, Vec<Vec<IR>>> = HashMap::new();
+    for (id, b) in blocks.iter().enumerate() {
+        let mut body: Vec<IR> = f.ir[b.start..b.end]
+            .iter()
+            .filter(|ir| !matches!(ir.op, IROp::Phi(_)))
+            .cloned()
+            .collect();
+        let last = body.pop();
+        let op = last.as_ref().map(|ir| ir.op.clone());
+        match op {
+            Some(IROp::If) | Some(IROp::Unless) | Some(IROp::IfEq(_)) => {
+                let mut jump = last.unwrap();
+                let target = b.succ[b.succ.len() - 1];
+                let moves = edge(id, target);
+                if !moves.is_empty() {
+                    let mut code = vec![IR {
+                        op: IROp::Label,
+                        lhs: Some(next_label),
+                        rhs: None,
+                    }];
+                    code.extend(moves);
+                    jump.rhs = Some(next_label);
+                    next_label += 1;
+                    split.entry(target).or_default().push(code);
+                }
+                body.push(jump);
+                if b.succ.len() == 2 {
+                    body.extend(edge(id, b.succ[0]));
+                }
+            }
+            Some(IROp::Jmp) => {
+                body.extend(edge(id, b.succ[0]));
+                body.extend(last);
+            }
+            _ => {
+                body.extend(last);
+                if let Some(&s) = b.succ.first() {
+                    body.extend(edge(id, s));
+                }
+            }
+        }
+        bodies.push(body);
+    }
+
+    let mut code: Vec<IR> = vec![];
+    for (id, body) in bodies.into_iter().enumerate() {
+        if let Some(preds) = split.remove(&id) {
+            let jmp = IR {
+                op: IROp::Jmp,
+                lhs: f.ir[blocks[id].start].lhs,
+                rhs: None,
+            };
+            let falls_through = code
+                .last()
+                .is_some_and(|ir| !matches!(ir.op, IROp::Jmp | IROp::Return | IROp::Trap));
+            if falls_through {
+                code.push(jmp.clone());
+            }
+            let n = preds.len();
+            for (i, moves) in preds.into_iter().enumerate() {
+                code.extend(moves);
+                if i + 1 < n {
+                    code.push(jmp.clone());
+                }
+            }
+        }
+        code.extend(body);
+    }
+    f.ir = code;
+    add_kills(f);
+}
@@ -0,0 +1,54 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+// The data model of the machine being compiled for. Pointers get their
+// size from here when types are built, and the x86 backend does its
+// arithmetic in registers of the word size.
+//
+// The 32-bit model is ILP32 on x86-64, as in the x32 ABI: pointers and
+// words take 4 bytes and the 32-bit halves of the usual registers are
+// used, but the instruction set and calling convention stay the same.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Target {
+    pub word_size: usize,
+    pub ptr_size: usize,
+}
+
+impl Target {
+    pub fn lp64() -> Self {
+        Target {
+            word_size: 8,
+            ptr_size: 8,
+        }
+    }
+
+    pub fn ilp32() -> Self {
+        Target {
+            word_size: 4,
+            ptr_size: 4,
+        }
+    }
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Target::lp64()
+    }
+}
+
+// Kept in atomics rather than behind a Mutex, so that types can be
+// built without std.
+static WORD_SIZE: AtomicUsize = AtomicUsize::new(8);
+static PTR_SIZE: AtomicUsize = AtomicUsize::new(8);
+
+// Selects the target for the programs compiled from now on.
+pub fn set_target(target: Target) {
+    WORD_SIZE.store(target.word_size, Ordering::SeqCst);
+    PTR_SIZE.store(target.ptr_size, Ordering::SeqCst);
+}
+
+pub fn target() -> Target {
+    Target {
+        word_size: WORD_SIZE.load(Ordering::SeqCst),
+        ptr_size: PTR_SIZE.load(Ordering::SeqCst),
+    }
+}
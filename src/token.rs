@@ -1,15 +1,26 @@
 use preprocess;
 use CharacterType;
 use TokenType;
+use {Diagnostic, Severity, Span};
 
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
+use std::mem;
+use std::panic;
 use std::rc::Rc;
+use std::sync::Mutex;
 
 pub fn tokenize(path: String, ctx: &mut preprocess::Preprocessor) -> Vec<Token> {
-    let mut tokenizer = Tokenizer::new(Rc::new(path));
+    let input = Tokenizer::read_file(&path);
+    tokenize_str(path, input, ctx)
+}
+
+// Tokenizes `input` as if it had been read from `path`.
+pub fn tokenize_str(path: String, input: String, ctx: &mut preprocess::Preprocessor) -> Vec<Token> {
+    let mut tokenizer = Tokenizer::new(Rc::new(path), input);
     tokenizer.canonicalize_newline();
     tokenizer.remove_backslash_newline();
     tokenizer.scan(&keyword_map());
@@ -23,19 +34,30 @@ pub fn tokenize(path: String, ctx: &mut preprocess::Preprocessor) -> Vec<Token>
 fn keyword_map() -> HashMap<String, TokenType> {
     let mut map = HashMap::new();
     map.insert("_Alignof".into(), TokenType::Alignof);
+    map.insert("asm".into(), TokenType::Asm);
     map.insert("break".into(), TokenType::Break);
+    map.insert("case".into(), TokenType::Case);
     map.insert("char".into(), TokenType::Char);
+    map.insert("continue".into(), TokenType::Continue);
+    map.insert("default".into(), TokenType::Default);
     map.insert("void".into(), TokenType::Void);
     map.insert("do".into(), TokenType::Do);
     map.insert("else".into(), TokenType::Else);
+    map.insert("enum".into(), TokenType::Enum);
     map.insert("extern".into(), TokenType::Extern);
     map.insert("for".into(), TokenType::For);
     map.insert("if".into(), TokenType::If);
     map.insert("int".into(), TokenType::Int);
+    map.insert("restrict".into(), TokenType::Restrict);
     map.insert("return".into(), TokenType::Return);
     map.insert("sizeof".into(), TokenType::Sizeof);
+    map.insert("typeof".into(), TokenType::Typeof);
+    map.insert("__typeof__".into(), TokenType::Typeof);
     map.insert("struct".into(), TokenType::Struct);
+    map.insert("switch".into(), TokenType::Switch);
     map.insert("typedef".into(), TokenType::Typedef);
+    map.insert("unsigned".into(), TokenType::Unsigned);
+    map.insert("volatile".into(), TokenType::Volatile);
     map.insert("while".into(), TokenType::While);
     map
 }
@@ -47,6 +69,10 @@ pub struct Token {
     // For preprocessor
     pub stringize: bool,
 
+    // Integer constant suffixes (`U` and `L`)
+    pub unsigned: bool,
+    pub long: bool,
+
     // For error reporting
     pub buf: Rc<Vec<char>>,
     pub filename: Rc<String>,
@@ -63,6 +89,8 @@ impl Default for Token {
             start: 0,
             end: 0,
             stringize: false,
+            unsigned: false,
+            long: false,
         }
     }
 }
@@ -80,7 +108,31 @@ impl Token {
 
     pub fn bad_token(&self, msg: &str) -> ! {
         print_line(&*self.buf, &*self.filename, self.start);
-        panic!("{}", msg);
+        error_at(Some(self.span()), msg);
+    }
+
+    // Reports a problem that does not stop compilation.
+    pub fn warn(&self, msg: &str) {
+        warn_at(Some(self.span()), msg);
+    }
+
+    // Like warn(), with a replacement that is probably what was meant.
+    pub fn warn_suggesting(&self, msg: &str, suggestion: String) {
+        report_warning(Diagnostic {
+            severity: Severity::Warning,
+            message: msg.into(),
+            span: Some(self.span()),
+            suggestion: Some(suggestion),
+        });
+    }
+
+    pub fn span(&self) -> Span {
+        Span {
+            filename: (*self.filename).clone(),
+            line: self.get_line_number() + 1,
+            start: self.start,
+            end: self.end,
+        }
     }
 
     pub fn tokstr(&self) -> String {
@@ -99,6 +151,122 @@ impl Token {
     }
 }
 
+// How a token of type `ty` is written in the source, if it is always
+// written the same way.
+pub fn spelling(ty: &TokenType) -> Option<String> {
+    if let Some((name, _)) = keyword_map().into_iter().find(|(_, t)| t == ty) {
+        return Some(name);
+    }
+    if let Some(sym) = SYMBOLS.iter().find(|sym| &sym.ty == ty) {
+        return Some(sym.name.into());
+    }
+    (b'!'..b'~')
+        .map(char::from)
+        .find(|&c| TokenType::new_single_letter(c).as_ref() == Some(ty))
+        .map(|c| c.to_string())
+}
+
+fn describe(ty: &TokenType) -> String {
+    match spelling(ty) {
+        Some(s) => format!("'{}'", s),
+        None => format!("{:?}", ty),
+    }
+}
+
+// A cursor over the tokens of a translation unit, for the parser.
+pub struct TokenStream<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> TokenStream<'a> {
+    pub fn new(tokens: &'a [Token]) -> Self {
+        TokenStream { tokens, pos: 0 }
+    }
+
+    pub fn at_end(&self) -> bool {
+        self.pos == self.tokens.len()
+    }
+
+    // Returns the token `n` tokens ahead without consuming anything.
+    pub fn peek_nth(&self, n: usize) -> &'a Token {
+        match self.tokens.get(self.pos + n) {
+            Some(t) => t,
+            None => error_at(
+                self.tokens.last().map(|t| t.span()),
+                "unexpected end of input",
+            ),
+        }
+    }
+
+    pub fn peek(&self) -> &'a Token {
+        self.peek_nth(0)
+    }
+
+    // Consumes and returns the next token. Unlike Iterator::next, this
+    // reports an error at the end of input.
+    pub fn advance(&mut self) -> &'a Token {
+        let t = self.peek();
+        self.pos += 1;
+        t
+    }
+
+    // Puts back the token consumed last.
+    pub fn unget(&mut self) {
+        self.pos -= 1;
+    }
+
+    // Index of the next token, to be passed to source() later.
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    // The source text of tokens `start..end`. Tokens that come from
+    // different places, e.g. from a macro expansion, are joined with
+    // spaces instead.
+    pub fn source(&self, start: usize, end: usize) -> String {
+        let tokens = &self.tokens[start..end];
+        let (first, last) = (&tokens[0], &tokens[tokens.len() - 1]);
+        if Rc::ptr_eq(&first.buf, &last.buf) && first.start <= last.end {
+            return first.buf[first.start..last.end].iter().collect();
+        }
+        let strs: Vec<String> = tokens.iter().map(|t| t.tokstr()).collect();
+        strs.join(" ")
+    }
+
+    pub fn expect(&mut self, ty: TokenType) -> &'a Token {
+        let t = self.peek();
+        if t.ty != ty {
+            t.bad_token(&format!(
+                "expected {}, found '{}'",
+                describe(&ty),
+                t.tokstr()
+            ));
+        }
+        self.advance()
+    }
+
+    pub fn consume_if(&mut self, ty: TokenType) -> bool {
+        if self.at_end() || self.peek().ty != ty {
+            return false;
+        }
+        self.pos += 1;
+        true
+    }
+}
+
+impl<'a> Iterator for TokenStream<'a> {
+    type Item = &'a Token;
+
+    fn next(&mut self) -> Option<&'a Token> {
+        if self.at_end() {
+            None
+        } else {
+            Some(self.advance())
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 struct Symbol {
     name: &'static str,
@@ -111,6 +279,80 @@ impl Symbol {
     }
 }
 
+lazy_static! {
+    static ref WARNINGS: Mutex<Vec<Diagnostic>> = Mutex::new(vec![]);
+    static ref NESTED_COMMENTS: Mutex<bool> = Mutex::new(false);
+}
+
+// Makes `/*` inside a block comment open a nested one, which needs its
+// own `*/`. Standard C ends the comment at the first `*/`.
+pub fn set_nested_comments(on: bool) {
+    *NESTED_COMMENTS.lock().unwrap() = on;
+}
+
+thread_local! {
+    // Location of the error being reported on this thread.
+    static ERROR_SPAN: RefCell<Option<Span>> = const { RefCell::new(None) };
+}
+
+pub fn warn_at(span: Option<Span>, msg: &str) {
+    report_warning(Diagnostic {
+        severity: Severity::Warning,
+        message: msg.into(),
+        span,
+        suggestion: None,
+    });
+}
+
+fn report_warning(warning: Diagnostic) {
+    eprintln!("{}", warning);
+    WARNINGS.lock().unwrap().push(warning);
+}
+
+// Errors abort compilation by panicking with `msg`. The location is
+// kept aside so that a caller catching the panic can tell where it was.
+pub fn error_at(span: Option<Span>, msg: &str) -> ! {
+    ERROR_SPAN.with(|s| *s.borrow_mut() = span);
+    panic!("{}", msg);
+}
+
+// Runs `f`, turning an error reported by it into a diagnostic.
+pub fn catch_error<T, F: FnOnce() -> T>(f: F) -> Result<T, Diagnostic> {
+    ERROR_SPAN.with(|s| s.borrow_mut().take());
+    panic::catch_unwind(panic::AssertUnwindSafe(f)).map_err(|e| {
+        let message = if let Some(msg) = e.downcast_ref::<String>() {
+            msg.clone()
+        } else if let Some(msg) = e.downcast_ref::<&str>() {
+            msg.to_string()
+        } else {
+            "unknown error".into()
+        };
+        Diagnostic {
+            severity: Severity::Error,
+            message,
+            span: ERROR_SPAN.with(|s| s.borrow_mut().take()),
+            suggestion: None,
+        }
+    })
+}
+
+// Returns the warnings reported so far and forgets them.
+pub fn take_diagnostics() -> Vec<Diagnostic> {
+    mem::take(&mut *WARNINGS.lock().unwrap())
+}
+
+// Turns the first warning reported so far into an error, like -Werror.
+pub fn promote_warnings() {
+    if let Some(w) = take_diagnostics().into_iter().next() {
+        error_at(w.span, &w.message);
+    }
+}
+
+// Same as take_diagnostics(), formatted like they are printed.
+pub fn take_warnings() -> Vec<String> {
+    take_diagnostics().iter().map(|w| w.to_string()).collect()
+}
+
 lazy_static! {
     static ref SYMBOLS: Vec<Symbol> = [
         Symbol::new("<<=", TokenType::ShlEQ),
@@ -143,18 +385,20 @@ struct Tokenizer {
     p: Rc<Vec<char>>,
     pos: usize,
     tokens: Vec<Token>,
+    nested_comments: bool,
 
     // Error reporting
     filename: Rc<String>,
 }
 
 impl Tokenizer {
-    fn new(filename: Rc<String>) -> Self {
+    fn new(filename: Rc<String>, input: String) -> Self {
         Tokenizer {
-            p: Rc::new(Self::read_file(&filename).chars().collect()),
+            p: Rc::new(input.chars().collect()),
             filename,
             pos: 0,
             tokens: vec![],
+            nested_comments: *NESTED_COMMENTS.lock().unwrap(),
         }
     }
 
@@ -254,7 +498,7 @@ impl Tokenizer {
                         self.tokens.push(t);
                         continue 'outer;
                     }
-                    self.bad_position("Unknown symbol.");
+                    self.bad_position(&format!("unexpected character '{}'", c));
                 }
                 CharacterType::Unknown(_) => self.bad_position("Unknwon character type."),
             }
@@ -271,12 +515,19 @@ impl Tokenizer {
 
     fn block_comment(&mut self) {
         self.pos += 2;
+        let mut depth = 1;
         loop {
             if let Some(two_char) = self.p.get(self.pos..self.pos + 2) {
                 self.pos += 1;
                 if two_char == ['*', '/'] {
                     self.pos += 1;
-                    return;
+                    depth -= 1;
+                    if depth == 0 {
+                        return;
+                    }
+                } else if two_char == ['/', '*'] && self.nested_comments {
+                    self.pos += 1;
+                    depth += 1;
                 }
             } else {
                 self.bad_position("unclosed comment");
@@ -335,7 +586,11 @@ impl Tokenizer {
             if c2 == &'"' {
                 len += 1;
                 self.pos += len;
-                let mut t = self.new_token(TokenType::Str(sb, len));
+                // `len` counts the characters in the source, where an
+                // escape sequence takes two. The array has one more
+                // element than the string for the terminating NUL.
+                let size = sb.len() + 1;
+                let mut t = self.new_token(TokenType::Str(sb, size));
                 t.start = self.pos - len - 1;
                 t.end = self.pos + 1;
                 self.tokens.push(t);
@@ -394,23 +649,68 @@ impl Tokenizer {
         }
     }
 
+    // A decimal constant must fit in an int. Hexadecimal and octal ones
+    // may use all 32 bits, and are unsigned if they do not fit in an int,
+    // as in C. So may any constant with a `U` suffix. (With an `L`
+    // suffix, C would make such a constant a signed long, but a Num
+    // holds only 32 bits, so it becomes an unsigned long here.)
     fn parse_number(&mut self, base: u32) {
-        let mut sum: i32 = 0;
+        let (unsigned, long, suffix_len) = self.int_suffix(base);
+        let max = if base == 10 && !unsigned {
+            i32::MAX as u64
+        } else {
+            u64::from(u32::MAX)
+        };
+        let mut sum: u64 = 0;
         let mut len = 0;
         for c in self.p[self.pos..].iter() {
             if let Some(val) = c.to_digit(base) {
-                sum = sum * base as i32 + val as i32;
+                sum = sum * u64::from(base) + u64::from(val);
+                if sum > max {
+                    self.new_token(TokenType::Num(0))
+                        .bad_token("integer constant is too large");
+                }
                 len += 1;
             } else {
                 break;
             }
         }
-        let mut t = self.new_token(TokenType::Num(sum as i32));
-        self.pos += len;
+        let mut t = self.new_token(TokenType::Num(sum as u32 as i32));
+        t.unsigned = unsigned || sum > i32::MAX as u64;
+        t.long = long;
+        self.pos += len + suffix_len;
         t.end = self.pos;
         self.tokens.push(t);
     }
 
+    // Reads the suffix after the digits of a number: `U`, `L` or `LL`,
+    // in either case and either order. Returns whether the constant is
+    // unsigned, whether it is long, and the length of the suffix.
+    fn int_suffix(&self, base: u32) -> (bool, bool, usize) {
+        let mut pos = self.pos;
+        while self.p.get(pos).is_some_and(|c| c.is_digit(base)) {
+            pos += 1;
+        }
+        let start = pos;
+        let (mut unsigned, mut long) = (false, false);
+        loop {
+            match self.p.get(pos) {
+                Some(&'u') | Some(&'U') if !unsigned => {
+                    unsigned = true;
+                    pos += 1;
+                }
+                Some(&c) if (c == 'l' || c == 'L') && !long => {
+                    long = true;
+                    pos += 1;
+                    if self.p.get(pos) == Some(&c) {
+                        pos += 1;
+                    }
+                }
+                _ => return (unsigned, long, pos - start),
+            }
+        }
+    }
+
     fn canonicalize_newline(&mut self) {
         let mut pos = 0;
         while pos < self.p.len() {
@@ -429,11 +729,10 @@ impl Tokenizer {
         let mut pos = 0;
         let mut cnt = 0;
         while pos < self.p.len() {
-            if self.p[pos] == '\\' && self.p[pos + 1] == '\n' {
+            if self.p[pos] == '\\' && self.p.get(pos + 1) == Some(&'\n') {
                 cnt += 1;
                 Rc::get_mut(&mut self.p).unwrap().remove(pos);
                 Rc::get_mut(&mut self.p).unwrap().remove(pos);
-                pos += 1;
             } else if self.p[pos] == '\n' {
                 for _ in 0..cnt {
                     Rc::get_mut(&mut self.p).unwrap().insert(pos, '\n');
@@ -489,9 +788,11 @@ impl Tokenizer {
             .collect()
     }
 
-    fn bad_position(&self, msg: &'static str) {
-        print_line(&self.p, &self.filename, self.pos);
-        panic!(msg);
+    // Reports an error at the character at the current position.
+    fn bad_position(&self, msg: &str) -> ! {
+        let mut t = self.new_token(TokenType::Int);
+        t.end = (self.pos + 1).min(self.p.len());
+        t.bad_token(msg)
     }
 }
 
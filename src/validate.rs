@@ -0,0 +1,58 @@
+use gen_ir::{Function, IROp};
+use CompileError;
+
+use std::collections::HashSet;
+
+// Checks the invariants that the backends rely on but do not check
+// themselves, so that a bug in IR generation or optimization shows up
+// here rather than as wrong assembly:
+//
+// - every register is defined before it is read,
+// - every jump goes to a label that exists in the function,
+// - no argument of a call has been killed before the call.
+pub fn validate(f: &Function) -> Result<(), CompileError> {
+    let err = |message: String| {
+        Err(CompileError::InvalidIR {
+            function: f.name.clone(),
+            message,
+        })
+    };
+
+    let labels: HashSet<usize> =
+        f.ir.iter()
+            .filter(|ir| ir.op == IROp::Label)
+            .map(|ir| ir.lhs.unwrap())
+            .collect();
+
+    let mut defined = HashSet::new();
+    let mut killed = HashSet::new();
+    for ir in &f.ir {
+        let target = match ir.op {
+            IROp::Jmp => ir.lhs,
+            IROp::If | IROp::Unless | IROp::IfEq(_) => ir.rhs,
+            _ => None,
+        };
+        if let Some(label) = target {
+            if !labels.contains(&label) {
+                return err(format!("jump to undefined label .L{}: {}", label, ir));
+            }
+        }
+
+        if ir.op == IROp::Kill {
+            killed.insert(ir.lhs.unwrap());
+            continue;
+        }
+
+        let is_call = matches!(ir.op, IROp::Call(..) | IROp::CallReg(..));
+        for r in ir.uses() {
+            if !defined.contains(&r) {
+                return err(format!("r{} is used before it is defined: {}", r, ir));
+            }
+            if is_call && killed.contains(&r) {
+                return err(format!("call argument r{} is not live: {}", r, ir));
+            }
+        }
+        defined.extend(ir.def());
+    }
+    Ok(())
+}
@@ -1,3 +1,9 @@
 // This file is compiled by gcc.
 
  int global_arr[1] = {5};
+
+struct pair { int x; int y; };
+int gcc_pair_sum(struct pair p) { return p.x * 10 + p.y; }
+
+struct five { int a, b, c, d, e; };
+struct five gcc_five(int n) { struct five f = {n, n + 1, n + 2, n + 3, n + 4}; return f; }
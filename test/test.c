@@ -3,6 +3,7 @@ extern void *stderr;
 int printf();
 int fprintf();
 int exit();
+int strlen();
 
 #define EXPECT(expected, expr)                                  \
   do {                                                          \
@@ -22,18 +23,59 @@ int two() { return 2; }
 int plus(int x, int y) { return x + y; }
 int mul(int x, int y) { return x * y; }
 int add(int a, int b, int c, int d, int e, int f) { return a+b+c+d+e+f; }
+int digits7(int a, int b, int c, int d, int e, int f, int g) { return (((((a*10+b)*10+c)*10+d)*10+e)*10+f)*10+g; }
+int digits8(int a, int b, int c, int d, int e, int f, int g, int h) { return digits7(a, b, c, d, e, f, g)*10+h; }
 int add2(int (*a)[2]) { return a[0][0] + a[1][0]; }
 int add3(int a[][2]) { return a[0][0] + a[1][0]; }
 int add4(int a[2][2]) { return a[0][0] + a[1][0]; }
 void nop() {}
+void early(int *p) { *p = 1; return; *p = 2; }
+int bump(int *p) { *p = *p + 1; return *p; }
+twice(int x) { return x * 2; }
+int sign(int x) { if (x < 0) return 0 - 1; if (x) return 1; return 0; }
+int min(int x, int y) { return x < y ? x : y; }
+int safe_div(int x, int y) { return y ? x / y : 0; }
+void set3(int *p) { *p = 3; }
 
 int var1;
 int var2[5];
+int var3 = 2 * 3;
+int var4 = -300;
+char var5 = 300;
+typeof(var5) var6[3];
 extern int global_arr[1];
 typedef int myint;
 
+struct pair { int x; int y; };
+int pair_sum(struct pair p) { return p.x * 10 + p.y; }
+int gcc_pair_sum();
+struct pair make_pair(int x, int y) { struct pair p; p.x = x; p.y = y; return p; }
+
+struct five { int a; int b; int c; int d; int e; };
+struct five make_five(int n) { struct five f; f.a = n; f.b = n + 1; f.c = n + 2; f.d = n + 3; f.e = n + 4; return f; }
+struct five gcc_five();
+
+enum color { RED, GREEN = 5, BLUE };
+int by_color[BLUE + 1];
+
+#define SQ(x) ((x) * (x))
+#define ADD(a, b) a + b
+int sq_macro() { return SQ(1 + 2); }
+int add_macro() { return ADD(1, 2) * 3; }
+
 // Single-line comment test
 
+#if 0
+This is not C code, but it is never compiled.
+#if 1
+int not_defined() { return
+#endif
+#endif
+
+#if 1
+int in_if_1() { return 3; }
+#endif
+
 /***************************
  * Multi-line comment test *
  ***************************/
@@ -62,8 +104,13 @@ int main() {
   EXPECT(5, plus(2, 3));
   EXPECT(1, one());
   EXPECT(3, one()+two());
+  EXPECT(8, twice(4));
   EXPECT(6, mul(2, 3));
   EXPECT(21, add(1,2,3,4,5,6));
+  EXPECT(1234567, digits7(1,2,3,4,5,6,7));
+  EXPECT(12345678, digits8(1,2,3,4,5,6,7,8));
+  EXPECT(12345679, digits8(1,2,3,4,5,6,7,digits8(0,0,0,0,0,0,0,9)));
+  EXPECT(10, defined_later(5));
 
   EXPECT(0, 0 || 0);
   EXPECT(1, 1 || 0);
@@ -102,6 +149,10 @@ int main() {
   EXPECT(0, 9 % 3);
 
   EXPECT(0-3, -3);
+  EXPECT(0-5, ({ int x=5; return -x; }));
+  EXPECT(5, ({ int x=5; return -(-x); }));
+  EXPECT(2, ({ int x=1; int y=x+1; return y; }));
+  EXPECT(12, ({ int x=1; int y=x+1; x=10; int z=x+y; return z; }));
 
   EXPECT(0, !1);
   EXPECT(1, !0);
@@ -113,6 +164,25 @@ int main() {
 
   EXPECT(5, 0 ? 3 : 5);
   EXPECT(3, 1 ? 3 : 5);
+  EXPECT(2, 0 ? 1 : 1 ? 2 : 3);
+  EXPECT(3, 0 ? 1 : 0 ? 2 : 3);
+  EXPECT(1, 1 ? 1 : 0 ? 2 : 3);
+  EXPECT(5, 0 ?: 5);
+  EXPECT(3, 3 ?: 5);
+  EXPECT(11, ({ int n=0; int x = bump(&n) ?: 5; return x*10+n; }));
+  EXPECT(6, ({ int x; x = 0 ? 5 : 6; return x; }));
+  EXPECT(2, min(2, 3));
+  EXPECT(2, min(3, 2));
+  EXPECT(7, __builtin_max(3, 7));
+  EXPECT(3, __builtin_min(3, 7));
+  EXPECT(0-4, ({ int x=0-4; return __builtin_min(x, 2); }));
+  EXPECT(5, ({ int i=0; int m=__builtin_max(i++, 5); return m+i-1; }));
+  EXPECT(1, ({ unsigned u=0-1; return __builtin_max(u, 1) == u; }));
+  EXPECT(1, ({ int flag; flag = 3 < 5; return flag; }));
+  EXPECT(0, ({ int flag; flag = 5 < 3; return flag; }));
+  EXPECT(3, safe_div(7, 2));
+  EXPECT(0, safe_div(7, 0));
+  EXPECT(0, ({ int n=0; 0 ? n++ : 1; return n; }));
 
   EXPECT(3, (1, 2, 3));
 
@@ -122,6 +192,21 @@ int main() {
   EXPECT(2, 6 & 3);
   EXPECT(0, 6 & 0);
 
+  EXPECT(2, 10 - 4 - 4);
+  EXPECT(1, 16 / 4 / 4);
+  EXPECT(7, 1 | 4 & 6 ^ 2);
+  EXPECT(1, 2 + 3 > 4 && 1 << 2 == 4);
+  EXPECT(1, 0 || 1 && 1);
+  EXPECT(0, 1 + 1 >= 3 || 6 % 4 != 2);
+
+  EXPECT(7, ({ int x=7; return x*1+0; }));
+  EXPECT(7, ({ int x=7; return 1*x-0; }));
+  EXPECT(7, ({ int x=7; return x/1; }));
+  EXPECT(0, ({ int x=7; return x*0; }));
+  EXPECT(0, ({ int x=7; return x-x; }));
+  EXPECT(2, ({ int x=5; int *p=&x; int a=x; *p=7; return x-a; }));
+  EXPECT(3, ({ int x=3; 0*one(); return x; }));
+
   EXPECT(45, ({ int x=0; int y=0; do { y=y+x; x=x+1; } while (x < 10); return y; }));
 
   EXPECT(3, ({ int x; int y; x=y=3; return x; }));
@@ -132,16 +217,38 @@ int main() {
   EXPECT(1, ({ int i=1; for (int i = 5; i < 10; i++); return i; }));
   EXPECT(5, ({ int i=0; for (0; i < 10; i++) if (i==5) break; return i; }));
   EXPECT(10, ({ int i=0; for (;;) { i++; if (i==10) break; } return i; }));
+  EXPECT(25, ({ int i; int s=0; for (i=0; i<10; i++) { if (i%2==0) continue; s+=i; } return s; }));
+  EXPECT(3, ({ int x=5; int n=0; while (x) { x--; if (x%2) continue; n++; } return n; }));
+  EXPECT(6, ({ int sum=0; int i; for (i=0; i<3; i++, sum=sum+i) ; return sum; }));
+  EXPECT(5, ({ int i; int j; for (i=0, j=10; i<j; i++, j--) ; return i; }));
+  EXPECT(3, ({ int i=0; int n=0; do { i++; if (i==2) continue; n++; } while (i<4); return n; }));
+
+  EXPECT(5, ({ int x=0; switch (2) { case 1: x=3; break; case 2: x=5; break; default: x=7; } return x; }));
+  EXPECT(7, ({ int x=0; switch (9) { case 1: x=3; break; case 2: x=5; break; default: x=7; } return x; }));
+  EXPECT(0, ({ int x=0; switch (9) { case 1: x=3; break; case 2: x=5; } return x; }));
+  EXPECT(8, ({ int x=0; switch (1) { case 1: x+=3; case 2: x+=5; } return x; }));
+  EXPECT(6, ({ int x=0; switch (0-1) { case 0-1: x=6; break; } return x; }));
+  EXPECT(14, ({ int i; int s=0; for (i=0; i<6; i++) { switch (i) { case 2: continue; case 4: break; default: s+=i; } s+=1; } return s; }));
 
   EXPECT(45, ({ int i=0; int j=0; while (i<10) { j=j+i; i=i+1; } return j;}));
+  EXPECT(7, ({ int i=0; int n=7; while (i != n) i++; return i; }));
+  EXPECT(3, ({ int i=0; int j=0; while (i != 3) { j = j + (i != 1); i++; } return i + j - 2; }));
 
   EXPECT(3, ({ int ary[2]; *ary=1; *(ary+1)=2; return *ary + *(ary+1);}));
   EXPECT(5, ({ int x; int *p = &x; x = 5; return *p;}));
+  EXPECT(2, ({ int *p; p = 0; if (p) return 1; return 2; }));
+  EXPECT(1, ({ int *p; p = 0; if (p == 0) return 1; return 2; }));
+  EXPECT(3, ({ int x; int *p = &x; return (p ? 1 : 0) + (p != 0) + !!p - !p; }));
+  EXPECT(0, ({ int *p = 0; int n = 0; while (p) n++; return n; }));
+  EXPECT(5, ({ int x; int *p = &x; int *q = 0; return (p && 1) + (q || p) * 2 + (q && p) + (!q) * 2; }));
 
   EXPECT(40, ({ int ary[2][5]; return sizeof(ary);}));
   EXPECT(8, ({ int ary[2][2]; ary[0][0]=3; ary[1][0]=5; return add2(ary);}));
   EXPECT(8, ({ int ary[2][2]; ary[0][0]=3; ary[1][0]=5; return add3(ary);}));
   EXPECT(8, ({ int ary[2][2]; ary[0][0]=3; ary[1][0]=5; return add4(ary);}));
+  EXPECT(24, ({ int a[2][3]; return sizeof(a);}));
+  EXPECT(7, ({ int a[2][3]; int *p=a[0]; a[1][2]=7; return p[5];}));
+  EXPECT(4, ({ int a[3][4]; int i; int j; for (i=0; i<3; i++) for (j=0; j<4; j++) a[i][j]=i*4+j; return a[2][3]+a[0][1]-a[2][0];}));
 
   EXPECT(3, ({ int ary[2]; ary[0]=1; ary[1]=2; return ary[0] + ary[0+1];}));
   EXPECT(5, ({ int x; int *p = &x; x = 5; return p[0];}));
@@ -150,8 +257,19 @@ int main() {
 
   EXPECT(1, ({ char x; return sizeof x; }));
   EXPECT(4, ({ int x; return sizeof(x); }));
+  EXPECT(4, ({ int x; typeof(x) y; return sizeof(y); }));
+  EXPECT(8, ({ char c; __typeof__(&c) p; return sizeof(p); }));
+  EXPECT(3, ({ int a[3]; typeof(a) b; return sizeof(b) / sizeof(b[0]); }));
+  EXPECT(5, ({ int x = 5; typeof(x) *p = &x; return *p; }));
+  EXPECT(1, ({ int x = 1; typeof(x++) y; return x; }));
+  EXPECT(44, ({ char c; return (typeof(c))300; }));
+  EXPECT(4, ({ typeof(int) x; return sizeof(x); }));
+  EXPECT(3, sizeof(var6));
   EXPECT(8, ({ int *x; return sizeof x; }));
   EXPECT(16, ({ int x[4]; return sizeof x; }));
+  EXPECT(4, sizeof(one()));
+  EXPECT(0, ({ int n=0; int s=sizeof(bump(&n)); return n; }));
+  EXPECT(1, ({ int n=0; bump(&n); return n; }));
 
   EXPECT(1, ({ char x; return _Alignof x; }));
   EXPECT(4, ({ int x; return _Alignof(x); }));
@@ -159,15 +277,34 @@ int main() {
   EXPECT(4, ({ int x[4]; return _Alignof x; }));
   EXPECT(8, ({ int *x[4]; return _Alignof x; }));
   EXPECT(4, sizeof("abc"));
+  EXPECT(3, sizeof("a\n"));
 
   EXPECT(5, ({ char x = 5; return x; }));
   EXPECT(42, ({ int x = 0; char *p = &x; p[0] = 42; return x; }));
 
+  EXPECT(44, (char)300);
+  EXPECT(44, ({ char c; c = 300; return c; }));
+  EXPECT(2, ({ int x; int *p = &x; x = 1; *p = 2; return x; }));
+  EXPECT(300, (int)300);
+  EXPECT(1, sizeof((char)300));
+  EXPECT(4, ({ char a=1; char b=2; return sizeof(a+b); }));
+  EXPECT(4, ({ char a=1; return sizeof(-a); }));
+  EXPECT(4, ({ char a=1; return sizeof(a<<1); }));
+  EXPECT(300, ({ char a=200; char b=100; return a+b; }));
+  EXPECT(1, ({ char a=200; char b=100; return a+b > 255; }));
+  EXPECT(8, sizeof((int *)0));
+  EXPECT(3, ({ int x = 3; char *p = (char *)&x; return *p; }));
+  EXPECT(5, ({ int x = 261; char c = (char)x; return c; }));
+  EXPECT(5, ({ (void)one(); return 5; }));
+  EXPECT(3, ({ myint x = (myint)3; return x; }));
+
   EXPECT('a', ({ char *p = "abc"; return p[0]; }));
   EXPECT('b', ({ char *p = "abc"; return p[1]; }));
   EXPECT('c', ({ char *p = "abc"; return p[2]; }));
   EXPECT(0, ({ char *p = "abc"; return p[3]; }));
   EXPECT(7, sizeof("abc" "def"));
+  EXPECT(5, strlen("hello"));
+  EXPECT(0, strlen(""));
   EXPECT(9, sizeof("ab\0c" "\0def"));
 
   EXPECT(1, ({ int x = 1; { int x = 2; } return x; }));
@@ -175,6 +312,9 @@ int main() {
   EXPECT(0, var1);
   EXPECT(5, ({ var1 = 5; return var1; }));
   EXPECT(20, sizeof(var2));
+  EXPECT(6, var3);
+  EXPECT(-300, var4);
+  EXPECT(44, var5);
   EXPECT(15, ({ var2[0] = 5; var2[4] = 10; return var2[0] + var2[4]; }));
   EXPECT(5, global_arr[0]);
 
@@ -203,6 +343,22 @@ int main() {
 	return x.a[0].b + x.a[0].c[1];
       }));
 
+  EXPECT(20, ({ int a[3]; a[0]=10; a[1]=20; a[2]=30; int *p=a+2; return p[-1]; }));
+  EXPECT(10, ({ int a[3]; a[0]=10; a[1]=20; a[2]=30; int *p=a+2; int i=-1; return p[i*2]; }));
+  EXPECT(20, ({ int a[3]; a[0]=10; a[1]=20; a[2]=30; int *p=a+2; p--; return *p; }));
+  EXPECT(20, ({ int a[3]; a[0]=10; a[1]=20; a[2]=30; int *p=a+2; --p; return *p; }));
+  EXPECT(1, ({ int x=-3; return x < 0; }));
+  EXPECT(0-3, ({ int x=-7; return x / 2; }));
+  EXPECT(0-1, ({ int x=-7; return x % 2; }));
+  EXPECT(1, ({ int x=-1; return (int)x == x; }));
+
+  EXPECT(34, ({ struct pair p; p.x=3; p.y=4; return pair_sum(p); }));
+  EXPECT(34, ({ struct pair p; p.x=3; p.y=4; return gcc_pair_sum(p); }));
+  EXPECT(34, ({ struct pair p; p = make_pair(3, 4); return p.x * 10 + p.y; }));
+  EXPECT(9, ({ struct five f = make_five(5); return f.e; }));
+  EXPECT(24, ({ struct five f; struct five g; f = make_five(1); g = f; return g.a + g.b + g.c + g.d + g.e + make_five(5).e; }));
+  EXPECT(7, ({ struct five f = gcc_five(5); return f.c; }));
+
   EXPECT(3, ({ typedef int foo; foo x = 3; return x; }));
   EXPECT(4, ({ myint foo = 3; return sizeof(foo); }));
 
@@ -220,7 +376,154 @@ int main() {
   EXPECT(7, ({ int i=5; i|=3; return i; }));
 
   EXPECT(11, ({ int x[3] = {10, 11, 12}; return x[1]; }));
+  EXPECT(8, ({ int x[3] = {7, 8}; return x[1]; }));
+  EXPECT(0, ({ int x[3] = {7, 8}; return x[2]; }));
+  EXPECT(0-1, ({ int x[2] = {-1, 2}; return x[0]; }));
+  EXPECT(12, ({ int x[] = {1, 2, 3}; return sizeof(x); }));
+  EXPECT(3, ({ char x[4] = {1, 2}; return x[0] + x[1] + x[2] + x[3]; }));
+
+  EXPECT(3, in_if_1());
+
+  EXPECT(0, RED);
+  EXPECT(5, GREEN);
+  EXPECT(6, BLUE);
+  EXPECT(28, sizeof(by_color));
+  EXPECT(4, ({ enum color c = BLUE; return sizeof(c); }));
+  EXPECT(2, ({ enum { A, B, C, }; return C; }));
+  EXPECT(9, ({ enum { X = 2 * 4, Y }; return Y; }));
+  EXPECT(3, ({ enum { N = 1 + 2 }; int x[N]; return sizeof(x) / 4; }));
+  EXPECT(5, ({ enum color c = GREEN; return c; }));
+
+  EXPECT(1, ({ int x = 0; early(&x); return x; }));
+  EXPECT(2, ({ if (0) return 1; return 2; }));
+  EXPECT(1, ({ if (1) return 1; else return 2; }));
+  EXPECT(3, ({ int x=3; while (0) x=5; return x; }));
+  EXPECT(4, ({ int x=3; do x++; while (0); return x; }));
+  EXPECT(7, 1 ? 7 : 8);
+  EXPECT(8, 0 ? 7 : 8);
+  EXPECT(0, 0 && one());
+  EXPECT(1, 1 || one());
+
+  EXPECT(12, ({ int a=3; int b=4; int y=0; int i; for (i=0; i<10; i++) y = a * b; return y; }));
+  EXPECT(0, ({ int a=3; int y=0; int i; for (i=0; i<0; i++) y = a * 2; return y; }));
+  EXPECT(30, ({ int a=0; int y=0; int i; for (i=0; i<4; i++) { y = y + a * 5; a++; } return y; }));
+  EXPECT(10, ({ int a=1; int *p=&a; int y=0; int i; for (i=0; i<4; i++) { y = y + a; *p = *p + 1; } return y; }));
+  EXPECT(4, ({ int a=0; int y=0; int i; for (i=0; i<4; i++) { y = a + 1; bump(&a); } return y; }));
+  EXPECT(24, ({ int a=2; int y=0; int i; int j; for (i=0; i<3; i++) for (j=0; j<4; j++) y = y + a; return y; }));
+
+  EXPECT(0, -1 / 2);
+  EXPECT(2147483647, (unsigned)-1 / 2);
+  EXPECT(1, (unsigned)-1 % 2);
+  EXPECT(0-4, -8 >> 1);
+  EXPECT(2147483644, (unsigned)-8 >> 1);
+  EXPECT(2147483647, ({ unsigned x = -1; return x / 2; }));
+  EXPECT(2147483647, ({ unsigned int x = -1; x /= 2; return x; }));
+  EXPECT(1, ({ unsigned x = 0; return x - 1 > 0; }));
+  EXPECT(0, -1 < (unsigned)1);
+  EXPECT(0, ({ unsigned x = 65536; return x * x; }));
+  EXPECT(4, ({ unsigned x; return sizeof(x); }));
+  EXPECT(0-6, ({ int x; return sizeof(x) - 10; }));
+  EXPECT(1, ({ int x; return sizeof(x) - 10 > 0; }));
+  EXPECT(0, ({ int x; return 0-1 < sizeof(x); }));
+  EXPECT(1, ({ int x; return (int)sizeof(x) - 10 < 0; }));
+  EXPECT(255, ({ unsigned char c = 255; return c; }));
+
+  EXPECT(8, ({ int *p, q; return sizeof(p); }));
+  EXPECT(4, ({ int *p, q; return sizeof(q); }));
+  EXPECT(7, ({ int *p, q = 7; p = &q; return *p; }));
+  EXPECT(3, ({ int a = 1, b = 2; return a + b; }));
+  EXPECT(8, ({ struct { int a, b; } x; return sizeof(x); }));
+  EXPECT(12, ({ typedef int *ip, i; ip x; i y; return sizeof(x) + sizeof(y); }));
+
+  EXPECT(9, sq_macro());
+  EXPECT(7, add_macro());
+
+  EXPECT(0-1, sign(0-5));
+  EXPECT(0, sign(0));
+  EXPECT(1, sign(7));
+  EXPECT(3, ({ int x = 0; set3(&x); return x; }));
+  EXPECT(4, ({ nop(); return 4; }));
+  EXPECT(5, ({ int x = 2; return plus(x, 3); }));
+
+  EXPECT(9, ({ volatile int v=3; return v*v; }));
+  EXPECT(4, ({ volatile int n=0; int i; for (i=0; i<4; i++) n++; return n; }));
+  EXPECT(7, ({ int x=5; volatile int *p=&x; *p=7; return x; }));
+
+  EXPECT(5, ({ int (*fp)() = &plus; return fp(2, 3); }));
+  EXPECT(6, ({ int (*fp)() = plus; return (*fp)(2, 4); }));
+
+  test_suffixes();
+  test_do_while_zero();
+  test_load_forwarding();
+  test_large_struct_args();
 
   printf("OK\n");
   return 0;
  }
+
+int defined_later(int x) { return x * 2; }
+
+// Virtual registers are numbered across the whole file, and main() uses
+// nearly all the register allocator can map, so newer tests go into
+// functions defined after it.
+int test_suffixes() {
+  EXPECT(4, sizeof(5U));
+  EXPECT(8, sizeof(10L));
+  EXPECT(8, sizeof(3UL));
+  EXPECT(8, sizeof(1 + 10L));
+  EXPECT(4, sizeof(1 + 5u));
+  EXPECT(1, -1 > 0U);
+  EXPECT(0, -1 > 0L);
+  EXPECT(1, -1L < 0);
+  EXPECT(1, 0xFFFFFFFF > 0);
+  EXPECT(1, 0x80000000 > 0);
+  EXPECT(1, 020000000000 > 0);
+  EXPECT(0, 0x7FFFFFFF + 0 < 0);
+  EXPECT(4, sizeof(0xFFFFFFFF));
+  EXPECT(1, ({ int x = -1; return x == 0xFFFFFFFF; }));
+  EXPECT(1, ({ typeof(5U) x = -1; return x > 0; }));
+  EXPECT(1, 4000000000U / 2 == 2000000000);
+  EXPECT(1, ({ unsigned x = 4000000000U; return x == 4000000000U; }));
+  EXPECT(1, 0UL - 1 > 0);
+  EXPECT(1, -1 > 0UL);
+  EXPECT(1, -1 >= 0UL);
+  EXPECT(0, 0UL - 1 < 0);
+  EXPECT(1, 0U <= -1);
+  EXPECT(1, -1L < 0);
+  EXPECT(4000000000U, __builtin_max(4000000000U, 5));
+}
+
+int test_do_while_zero() {
+  EXPECT(1, ({ int x = 0; do { x = 1; } while (0); return x; }));
+  EXPECT(1, ({ int x = 0; do { x = 1; break; x = 2; } while (0); return x; }));
+  EXPECT(1, ({ int x = 0; do { x = 1; continue; x = 2; } while (0); return x; }));
+  EXPECT(3, ({ int x = 0; do { do { x = x + 1; } while (0); x = x + 2; } while (0); return x; }));
+}
+
+// A value stored and loaded again is forwarded from its register, which
+// must stay live until the last such load. These need functions of their
+// own, as a statement expression ends in a label that stops forwarding.
+int fwd_square() { int x = 3; return x * x; }
+int fwd_if() { int x=9; if(x>5) x=x-5; else x=x+5; return x; }
+int fwd_ternary(int x) { int y = x ? x : 3; return y; }
+int fwd_deref() { int x=1; int *p=&x; *p=*p+1; return x; }
+int fwd_unsigned() { unsigned x=7; return x/2 + x%2; }
+
+int test_load_forwarding() {
+  EXPECT(9, fwd_square());
+  EXPECT(4, fwd_if());
+  EXPECT(3, fwd_ternary(0));
+  EXPECT(5, fwd_ternary(5));
+  EXPECT(2, fwd_deref());
+  EXPECT(4, fwd_unsigned());
+}
+
+// Passed as a pointer to a copy made by the caller, so changes made by
+// the callee are not seen by the caller.
+struct triple { int a; int b; int c; };
+int triple_sum(struct triple t) { int s = t.a + t.b + t.c; t.a = 100; return s; }
+
+int test_large_struct_args() {
+  EXPECT(6, ({ struct triple t; t.a=1; t.b=2; t.c=3; return triple_sum(t); }));
+  EXPECT(1, ({ struct triple t; t.a=1; t.b=2; t.c=3; triple_sum(t); return t.a; }));
+}
@@ -0,0 +1,116 @@
+#[macro_use]
+extern crate lazy_static;
+extern crate r9cc;
+
+mod common;
+
+use common::LOCK;
+
+use r9cc::backend::Backend;
+use r9cc::gen_ir::gen_ir;
+use r9cc::gen_wasm::Wasm;
+use r9cc::gen_x86::X86;
+use r9cc::irdump::IRDumper;
+use r9cc::parse::parse;
+use r9cc::preprocess::Preprocessor;
+use r9cc::regalloc::alloc_regs;
+use r9cc::sema::sema;
+use r9cc::token::tokenize_str;
+
+// Compiles `src` with each backend in turn, the way the driver does.
+fn compile(src: &str, backends: &[Box<dyn Backend>]) -> Vec<String> {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let tokens = tokenize_str("test.c".into(), src.into(), &mut Preprocessor::new());
+    let (nodes, globals) = sema(parse(&tokens));
+    let mut fns = gen_ir(nodes);
+    alloc_regs(&mut fns);
+    backends.iter().map(|b| b.emit(&globals, &fns)).collect()
+}
+
+#[test]
+fn driver_is_parameterized_over_backends() {
+    let backends: Vec<Box<dyn Backend>> = vec![Box::new(X86::new()), Box::new(IRDumper)];
+    let out = compile("int main() { return 42; }", &backends);
+
+    assert!(out[0].contains(".global main\n"));
+    assert!(out[0].contains("\tmov r10, 42\n"));
+    assert!(out[1].starts_with("main() [stack=0]:\n"));
+    assert!(out[1].contains("MOV r0, 42"));
+    assert_ne!(out[0], out[1]);
+}
+
+#[test]
+fn wasm() {
+    let out = compile(
+        "int main() { int x = 0; while (x < 3) x++; return x; }",
+        &[Box::new(Wasm)],
+    );
+    let wat = &out[0];
+
+    assert!(wat.starts_with("(module\n"));
+    assert!(wat.contains("(func $main (export \"main\") (result i64)\n"));
+    assert!(wat.contains("\treturn\n"));
+    assert!(wat.contains("\tbr_table $B0 $B1 $B2 $B3 $B0\n"));
+
+    // Blocks and the dispatch loop must be balanced.
+    let opened = wat
+        .lines()
+        .filter(|l| {
+            ["\tloop", "\tblock", "\tif"]
+                .iter()
+                .any(|s| l.starts_with(s))
+        })
+        .count();
+    let closed = wat.lines().filter(|l| *l == "\tend").count();
+    assert_eq!(opened, closed);
+}
+
+#[test]
+fn function_pointers() {
+    let backends: Vec<Box<dyn Backend>> = vec![Box::new(X86::new()), Box::new(Wasm)];
+    let out = compile(
+        "int one() { return 1; } int main() { int (*fp)() = &one; return fp(); }",
+        &backends,
+    );
+
+    assert!(out[0].contains("\tlea r10, one\n"));
+    assert!(out[0].contains("\tcall r10\n"));
+    assert!(out[1].contains("(table 3 funcref)\n(elem (i32.const 1) $one $main)\n"));
+    assert!(out[1].contains("\tcall_indirect (result i64)\n"));
+}
+
+#[test]
+fn symbol_map() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let tokens = tokenize_str(
+        "test.c".into(),
+        "int x; char s[3]; int main() { return x; }".into(),
+        &mut Preprocessor::new(),
+    );
+    let (nodes, globals) = sema(parse(&tokens));
+    let mut fns = gen_ir(nodes);
+    alloc_regs(&mut fns);
+
+    assert_eq!(IRDumper.symbol_map(&globals, &fns), None);
+
+    // The assembler places the code, so only globals have addresses.
+    let map = X86::new().symbol_map(&globals, &fns).unwrap();
+    let lines: Vec<Vec<&str>> = map
+        .lines()
+        .map(|l| l.split_whitespace().collect())
+        .collect();
+    assert_eq!(lines[0], ["name", "kind", "addr", "size"]);
+    assert_eq!(lines[1], ["x", "data", "0", "4"]);
+    assert_eq!(lines[2], ["s", "data", "4", "3"]);
+    assert_eq!(lines[3], ["main", "func", "-", "-"]);
+
+    let map = Wasm.symbol_map(&globals, &fns).unwrap();
+    let lines: Vec<Vec<&str>> = map
+        .lines()
+        .map(|l| l.split_whitespace().collect())
+        .collect();
+    assert_eq!(lines[0], ["name", "kind", "addr", "size"]);
+    assert_eq!(lines[1], ["x", "data", "8", "4"]);
+    assert_eq!(lines[2], ["s", "data", "16", "3"]);
+    assert_eq!(&lines[3][..3], ["main", "func", "0"]);
+}
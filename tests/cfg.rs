@@ -0,0 +1,85 @@
+#[macro_use]
+extern crate lazy_static;
+extern crate r9cc;
+
+mod common;
+
+use common::{function, ir};
+
+use r9cc::cfg::{basic_blocks, dominance_frontiers, dominators};
+use r9cc::gen_ir::{Function, IROp};
+
+// if (r0) r1 = 1; else r1 = 2; return r1;
+fn diamond() -> Function {
+    function(vec![
+        ir(IROp::Imm, 0, Some(1)),
+        ir(IROp::Unless, 0, Some(1)),
+        ir(IROp::Imm, 1, Some(1)),
+        ir(IROp::Jmp, 2, None),
+        ir(IROp::Label, 1, None),
+        ir(IROp::Imm, 1, Some(2)),
+        ir(IROp::Label, 2, None),
+        ir(IROp::Return, 1, None),
+        ir(IROp::Kill, 1, None),
+    ])
+}
+
+#[test]
+fn diamond_blocks() {
+    let blocks = basic_blocks(&diamond());
+    assert_eq!(blocks.len(), 5);
+    assert_eq!(blocks[0].succ, vec![1, 2]);
+    assert_eq!(blocks[1].succ, vec![3]);
+    assert_eq!(blocks[2].succ, vec![3]);
+    assert_eq!(blocks[3].pred, vec![1, 2]);
+    assert!(blocks[4].pred.is_empty());
+}
+
+#[test]
+fn diamond_dominators() {
+    let idom = dominators(&diamond());
+    assert_eq!(idom.get(&1), Some(&0));
+    assert_eq!(idom.get(&2), Some(&0));
+    assert_eq!(idom.get(&3), Some(&0));
+    assert!(!idom.contains_key(&0));
+    assert!(!idom.contains_key(&4));
+}
+
+#[test]
+fn loop_dominators() {
+    // r0 = 0; L1: unless r0 goto L2; r0 = 1; goto L1; L2: return r0;
+    let idom = dominators(&function(vec![
+        ir(IROp::Imm, 0, Some(0)),
+        ir(IROp::Label, 1, None),
+        ir(IROp::Unless, 0, Some(2)),
+        ir(IROp::Imm, 0, Some(1)),
+        ir(IROp::Jmp, 1, None),
+        ir(IROp::Label, 2, None),
+        ir(IROp::Return, 0, None),
+    ]));
+    assert_eq!(idom.get(&1), Some(&0));
+    assert_eq!(idom.get(&2), Some(&1));
+    assert_eq!(idom.get(&3), Some(&1));
+}
+
+#[test]
+fn dominance_frontiers_of_diamond_and_loop() {
+    let df = dominance_frontiers(&diamond());
+    assert_eq!(df.get(&1), Some(&vec![3]));
+    assert_eq!(df.get(&2), Some(&vec![3]));
+    assert!(!df.contains_key(&0));
+    assert!(!df.contains_key(&3));
+
+    let df = dominance_frontiers(&function(vec![
+        ir(IROp::Imm, 0, Some(0)),
+        ir(IROp::Label, 1, None),
+        ir(IROp::Unless, 0, Some(2)),
+        ir(IROp::Imm, 0, Some(1)),
+        ir(IROp::Jmp, 1, None),
+        ir(IROp::Label, 2, None),
+        ir(IROp::Return, 0, None),
+    ]));
+    assert_eq!(df.get(&1), Some(&vec![1]));
+    assert_eq!(df.get(&2), Some(&vec![1]));
+    assert!(!df.contains_key(&0));
+}
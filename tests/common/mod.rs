@@ -0,0 +1,30 @@
+// Helpers shared by the integration tests. Every test binary compiles
+// its own copy of this module and uses only part of it.
+#![allow(dead_code)]
+
+use r9cc::gen_ir::{Function, IROp, IR};
+
+use std::sync::Mutex;
+
+// The passes keep their state in globals, so tests must not run them
+// concurrently.
+lazy_static! {
+    pub static ref LOCK: Mutex<()> = Mutex::new(());
+}
+
+pub fn ir(op: IROp, lhs: usize, rhs: Option<usize>) -> IR {
+    IR {
+        op,
+        lhs: Some(lhs),
+        rhs,
+    }
+}
+
+pub fn function(ir: Vec<IR>) -> Function {
+    Function {
+        name: "f".into(),
+        ir,
+        stacksize: 0,
+        vars: vec![],
+    }
+}
@@ -0,0 +1,417 @@
+#[macro_use]
+extern crate lazy_static;
+extern crate r9cc;
+
+mod common;
+
+use common::LOCK;
+
+use r9cc::driver::{
+    check, check_with, compile, compile_file, compile_file_with, compile_to_object,
+    compile_to_object_with, compile_with, Options, Stage,
+};
+use r9cc::target::Target;
+use r9cc::token::catch_error;
+use r9cc::Severity;
+
+use std::env;
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn define_array_length() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = env::temp_dir().join("r9cc_define_array_length.c");
+    fs::write(
+        &path,
+        "#define N 5\nint main() { int a[N]; return sizeof(a); }\n",
+    )
+    .unwrap();
+    let asm = compile_file(&path);
+    fs::remove_file(&path).unwrap();
+
+    assert!(asm.contains("\tsub rsp, 32\n"));
+    assert!(asm.contains("\tmov r10, 20\n"));
+}
+
+#[test]
+fn command_line_options() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = env::temp_dir().join("r9cc_command_line_options.c");
+    fs::write(&path, "int main() { return 42; }\n").unwrap();
+    let x86 = compile_file_with(&path, &Options::default());
+    let opts = Options {
+        wasm: true,
+        ..Options::default()
+    };
+    let wasm = compile_file_with(&path, &opts);
+    fs::remove_file(&path).unwrap();
+
+    assert!(x86.text.contains("main:\n"));
+    assert!(x86.map.unwrap().contains("main"));
+    assert!(wasm.text.starts_with("(module"));
+    assert!(wasm.map.unwrap().contains("main"));
+}
+
+#[test]
+fn stages() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let src = "int main() { return 42; }";
+
+    let tokens = compile(src, Stage::Tokens);
+    assert_eq!(tokens.lines().next(), Some("Int"));
+    assert!(tokens.contains("Num(42)\n"));
+
+    let ast = compile(src, Stage::Ast);
+    assert_eq!(ast.lines().count(), 1);
+    assert!(ast.contains("Func(\"main\""));
+
+    let ir = compile(src, Stage::Ir);
+    assert!(ir.starts_with("main() [stack=0]:\n"));
+    assert!(ir.contains("  MOV r0, 42\n"));
+
+    let asm = compile(src, Stage::Asm);
+    assert!(asm.contains("main:\n"));
+    assert!(asm.contains("\tmov r10, 42\n"));
+}
+
+#[test]
+fn check_reports_all_diagnostics() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let src = "int f() { int x = 1; return *x; }\nint main() { int y; return f(); }\n";
+    let diags = check(src);
+    assert_eq!(diags.len(), 2);
+
+    assert_eq!(diags[0].severity, Severity::Error);
+    assert_eq!(diags[0].message, "operand must be a pointer");
+    let span = diags[0].span.clone().unwrap();
+    assert_eq!((span.line, &src[span.start..span.end]), (1, "*"));
+
+    assert_eq!(diags[1].severity, Severity::Warning);
+    assert_eq!(diags[1].message, "unused variable 'y'");
+    let span = diags[1].span.clone().unwrap();
+    assert_eq!((span.line, &src[span.start..span.end]), (2, "y"));
+}
+
+#[test]
+fn check_reports_parse_error() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let diags = check("int main() { return 1 }");
+    assert_eq!(diags.len(), 1);
+    assert_eq!(diags[0].severity, Severity::Error);
+    assert_eq!(diags[0].span.as_ref().map(|s| s.line), Some(1));
+}
+
+#[test]
+fn returning_address_of_local() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let src = "int *f() { int x = 1; return &x; }\nint *g() { int a[2]; return a; }\n";
+    let diags = check(src);
+    assert_eq!(diags.len(), 2);
+    for (diag, (line, text)) in diags.iter().zip(vec![(1, "&"), (2, "return")]) {
+        assert_eq!(diag.severity, Severity::Warning);
+        assert_eq!(diag.message, "function returns address of local variable");
+        let span = diag.span.clone().unwrap();
+        assert_eq!((span.line, &src[span.start..span.end]), (line, text));
+    }
+
+    let ir = compile(src, Stage::Ir);
+    assert!(ir.contains("  BPREL r2, 4\n  RET r2\n"), "{}", ir);
+}
+
+#[test]
+fn warnings_as_errors() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let src = "int main() {\n  int y;\n  return 0;\n}\n";
+    assert!(compile(src, Stage::Asm).contains("main:\n"));
+
+    let opts = Options {
+        warnings_as_errors: true,
+        ..Default::default()
+    };
+    let err = catch_error(|| compile_with(src, Stage::Asm, &opts)).unwrap_err();
+    assert_eq!(err.severity, Severity::Error);
+    assert_eq!(err.message, "unused variable 'y'");
+    assert_eq!(err.span.unwrap().line, 2);
+
+    let src = "int main() { return 0; }";
+    assert!(compile_with(src, Stage::Asm, &opts).contains("main:\n"));
+}
+
+#[test]
+fn canonical_regs() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let opts = Options {
+        canonical_regs: true,
+        ..Default::default()
+    };
+    // The dead statement uses up a register number before it is removed.
+    let a = "int main(int x) { return x + 1; }";
+    let b = "int main(int x) { 5; return x + 1; }";
+    assert_ne!(compile(a, Stage::Ir), compile(b, Stage::Ir));
+    let ir = compile_with(a, Stage::Ir, &opts);
+    assert_eq!(ir, compile_with(b, Stage::Ir, &opts));
+    assert!(ir.contains("BPREL r1, 4\n"), "{}", ir);
+}
+
+#[test]
+fn check_reports_unexpected_character() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let src = "int a@;";
+    let diags = check(src);
+    assert_eq!(diags.len(), 1);
+    assert_eq!(diags[0].severity, Severity::Error);
+    assert_eq!(diags[0].message, "unexpected character '@'");
+    let span = diags[0].span.clone().unwrap();
+    assert_eq!(
+        (span.line, span.start, &src[span.start..span.end]),
+        (1, 5, "@")
+    );
+}
+
+#[test]
+fn struct_return_needing_two_registers() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let diags = check("struct s { int a; int b; int c; }; struct s f() { struct s x; return x; }");
+    assert_eq!(diags.len(), 1);
+    assert_eq!(
+        diags[0].message,
+        "cannot return a struct of 12 bytes by value"
+    );
+}
+
+#[test]
+fn ilp32_target() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let opts = Options {
+        target: Target::ilp32(),
+        ..Default::default()
+    };
+    let src = "int main() { int *p; int x; p = &x; return sizeof(p); }";
+    let ir = compile_with(src, Stage::Ir, &opts);
+    assert!(ir.contains("main() [stack=8]:\n"), "{}", ir);
+    let asm = compile_with(src, Stage::Asm, &opts);
+    assert!(asm.contains("\tlea r10d, [rbp-8]\n"), "{}", asm);
+    assert!(asm.contains("\tmov eax, "), "{}", asm);
+
+    // Registers are 32 bits wide, so an unsigned int is only compared
+    // correctly by an unsigned compare.
+    let asm = compile_with("int f(int x) { return x > 0U; }", Stage::Asm, &opts);
+    assert!(asm.contains("\tsetb "), "{}", asm);
+    assert!(!asm.contains("\tsetl "), "{}", asm);
+
+    // Options::default() goes back to 64 bits.
+    let asm = compile(src, Stage::Asm);
+    assert!(asm.contains("\tmov rax, "), "{}", asm);
+}
+
+#[test]
+fn inline_asm() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let src = "int main() { asm(\"nop\"); return 0; }";
+    let ir = compile(src, Stage::Ir);
+    assert!(ir.contains("  ASM \"nop\"\n"), "{}", ir);
+    let asm = compile(src, Stage::Asm);
+    assert!(asm.contains("\tnop\n"), "{}", asm);
+}
+
+#[test]
+fn long_name_warning() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let name = "f".repeat(300);
+    let src = format!(
+        "int {}() {{ return 0; }} int main() {{ return {}(); }}",
+        name, name
+    );
+    let diags = check(&src);
+    assert_eq!(diags.len(), 1);
+    assert_eq!(diags[0].severity, Severity::Warning);
+    assert_eq!(
+        diags[0].message,
+        "name 'ffffffffffffffff...' is 300 characters long, more than 255"
+    );
+    assert_eq!(diags[0].span.clone().unwrap().start, 4);
+
+    let opts = Options {
+        max_name_length: Some(3),
+        warnings_as_errors: true,
+        ..Default::default()
+    };
+    let err =
+        catch_error(|| compile_with("int main() { return 0; }", Stage::Asm, &opts)).unwrap_err();
+    assert_eq!(err.message, "name 'main' is 4 characters long, more than 3");
+}
+
+#[test]
+fn global_initializer_is_folded() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let src = "int g = 2 * 3; int main() { return g; }";
+    let asm = compile(src, Stage::Asm);
+    assert!(
+        asm.contains("g:\n\t.ascii \"\\006\\000\\000\\000\"\n"),
+        "{}",
+        asm
+    );
+
+    let diags = check("int x; int g = x + 1;");
+    assert_eq!(diags.len(), 1);
+    assert_eq!(diags[0].message, "initializer element is not constant");
+}
+
+#[test]
+fn constant_overflow() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let src = "int a[2000000000 + 2000000000];";
+    let diags = check(src);
+    assert_eq!(diags.len(), 1);
+    assert_eq!(diags[0].severity, Severity::Error);
+    assert_eq!(diags[0].message, "integer overflow in constant expression");
+    let span = diags[0].span.clone().unwrap();
+    assert_eq!(&src[span.start..span.end], "+");
+
+    assert!(check("int a[-2147483647 - 1 < 0]; int b[1 << 31 != 0];").is_empty());
+    let diags = check("int a[-(-2147483647 - 1)];");
+    assert_eq!(diags[0].message, "integer overflow in constant expression");
+
+    // Unsigned arithmetic wraps around.
+    assert!(check("unsigned g = 2000000000U + 2000000000U; int a[0U - 1 > 0];").is_empty());
+    let asm = compile("unsigned g = 2000000000U + 2000000000U;", Stage::Asm);
+    assert!(asm.contains("\t.ascii \"\\000(k\\356\"\n"), "{}", asm);
+    assert_eq!(
+        check("int a[1 / 0];")[0].message,
+        "constant expression expected"
+    );
+}
+
+#[test]
+fn unreachable_code_after_return() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let src = "int main() { int x; return 1; x = 2; x = 3; }";
+    let diags = check(src);
+    assert_eq!(diags.len(), 1);
+    assert_eq!(diags[0].severity, Severity::Warning);
+    assert_eq!(diags[0].message, "unreachable code");
+    let span = diags[0].span.clone().unwrap();
+    assert_eq!(&src[span.start..span.end], "x");
+    assert_eq!(span.start, 30);
+
+    // Code after a case label can still be reached.
+    let src = "int main() { switch (1) { case 0: return 1; case 1: return 2; } return 0; }";
+    assert!(check(src).is_empty());
+}
+
+#[test]
+fn stack_arguments() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let src = "int f(int a, int b, int c, int d, int e, int g, int h, int i) { return h + i; }
+int main() { return f(1, 2, 3, 4, 5, 6, 7, 8); }";
+    let ir = compile(src, Stage::Ir);
+    assert!(ir.contains(", [bp-8], [bp-16])\n"), "{}", ir);
+    let asm = compile(src, Stage::Asm);
+    assert!(
+        asm.contains(
+            "\tpush qword ptr [rbp-16]\n\tpush qword ptr [rbp-8]\n\tmov rax, 0\n\tcall f\n\tadd rsp, 16\n"
+        ),
+        "{}",
+        asm
+    );
+    assert!(asm.contains("\tmov rax, [rbp+16]\n"), "{}", asm);
+    assert!(asm.contains("\tmov rax, [rbp+24]\n"), "{}", asm);
+}
+
+#[test]
+fn nested_comments() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let src = "1 /* a /* b */ 2 */ 3";
+    let opts = Options {
+        nested_comments: true,
+        ..Default::default()
+    };
+    assert_eq!(compile_with(src, Stage::Tokens, &opts), "Num(1)\nNum(3)\n");
+
+    // Off by default: the comment ends at the first "*/".
+    let tokens = compile(src, Stage::Tokens);
+    assert!(tokens.starts_with("Num(1)\nNum(2)\nMul\n"), "{}", tokens);
+
+    let err = catch_error(|| compile_with("/* /* */", Stage::Tokens, &opts)).unwrap_err();
+    assert_eq!(err.message, "unclosed comment");
+}
+
+#[test]
+fn max_errors() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let src: String = (0..10)
+        .map(|i| format!("int f{}() {{ 1 = 2; return 0; }}\n", i))
+        .collect();
+    assert_eq!(check(&src).len(), 10);
+
+    let opts = Options {
+        max_errors: Some(3),
+        ..Default::default()
+    };
+    let diags = check_with(&src, &opts);
+    assert_eq!(diags.len(), 4);
+    for diag in &diags[..3] {
+        assert_eq!(diag.severity, Severity::Error);
+    }
+    assert_eq!(diags[2].span.as_ref().map(|s| s.line), Some(3));
+    assert_eq!(diags[3].severity, Severity::Note);
+    assert_eq!(
+        diags[3].to_string(),
+        "note: too many errors; stopping after 3"
+    );
+}
+
+#[test]
+fn labels_are_numbered_per_function() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let src = "int f(int x) { while (x) x = x - 1; return x; }
+int main(int x) { while (x) x = x - 2; return f(x); }";
+    let ir = compile(src, Stage::Ir);
+    for name in &["f", "main"] {
+        let start = ir.find(&format!("{}() [", name)).unwrap();
+        let first = ir[start..]
+            .find(".L")
+            .map(|i| &ir[start + i..start + i + 4]);
+        assert_eq!(first, Some(".L0:"), "{}", ir);
+    }
+    let asm = compile(src, Stage::Asm);
+    assert!(asm.contains("\n.Lf_0:\n"), "{}", asm);
+    assert!(asm.contains("\n.Lmain_0:\n"), "{}", asm);
+    assert!(asm.contains("\tjmp .Lmain_end\n"), "{}", asm);
+}
+
+#[test]
+fn executable() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    // Needs a C compiler driver to assemble and link.
+    if Command::new("cc").arg("--version").output().is_err() {
+        return;
+    }
+    let path = env::temp_dir().join("r9cc_executable");
+    compile_to_object("int main() { return 42; }", &path).unwrap();
+    let status = Command::new(&path).status().unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(status.code(), Some(42));
+
+    let obj = env::temp_dir().join("r9cc_executable.o");
+    compile_to_object("int f() { return 1; }", &obj).unwrap();
+    assert!(fs::metadata(&obj).unwrap().len() > 0);
+    fs::remove_file(&obj).unwrap();
+}
+
+#[test]
+fn missing_toolchain() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let opts = Options {
+        cc: Some("/nonexistent/cc".into()),
+        ..Default::default()
+    };
+    let path = env::temp_dir().join("r9cc_missing_toolchain");
+    let err = compile_to_object_with("int main() { return 0; }", &path, &opts).unwrap_err();
+    assert!(
+        err.to_string().starts_with("cannot run /nonexistent/cc: "),
+        "{}",
+        err
+    );
+}
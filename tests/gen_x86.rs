@@ -0,0 +1,113 @@
+extern crate r9cc;
+
+use r9cc::backend::Backend;
+use r9cc::gen_ir::{Function, IROp, IR};
+use r9cc::gen_x86::{CallConv, X86};
+
+#[test]
+fn sysv_argument_registers() {
+    let conv = CallConv::sysv();
+    assert_eq!(conv.argreg(0, 8), "rdi");
+    assert_eq!(conv.argreg(1, 4), "esi");
+    assert_eq!(conv.argreg(5, 1), "r9b");
+}
+
+#[test]
+fn win64_argument_registers() {
+    let conv = CallConv::win64();
+    assert_eq!(conv.argreg(0, 8), "rcx");
+    assert_eq!(conv.argreg(1, 4), "edx");
+    assert_eq!(conv.argreg(3, 1), "r9b");
+    assert_eq!(conv.shadow_space, 32);
+}
+
+#[test]
+#[should_panic(expected = "too many arguments: at most 4 are supported")]
+fn win64_has_four_argument_registers() {
+    CallConv::win64().argreg(4, 8);
+}
+
+#[test]
+fn canary() {
+    let f = Function {
+        name: "main".into(),
+        ir: vec![],
+        stacksize: 4,
+        vars: vec![],
+    };
+    let x86 = X86 {
+        canary: true,
+        ..X86::new()
+    };
+    let asm = x86.emit(&[], &[f]);
+
+    assert!(asm.contains("\tsub rsp, 32\n"));
+    assert!(asm.contains("\tmov r11, 0xdeadbeefcafebabe\n\tmov [rbp-24], r11\n"));
+    assert!(asm.contains("\tcmp [rbp-24], r11\n"));
+    assert!(asm.contains("\tud2\n"));
+
+    let f = Function {
+        name: "main".into(),
+        ir: vec![],
+        stacksize: 4,
+        vars: vec![],
+    };
+    assert!(!X86::new().emit(&[], &[f]).contains("ud2"));
+}
+
+#[test]
+fn frame_is_rounded_up_to_stack_alignment() {
+    let f = Function {
+        name: "main".into(),
+        ir: vec![],
+        stacksize: 8,
+        vars: vec![],
+    };
+    assert_eq!(CallConv::sysv().stack_align, 16);
+    assert!(X86::new().emit(&[], &[f]).contains("\tsub rsp, 16\n"));
+
+    let f = Function {
+        name: "main".into(),
+        ir: vec![],
+        stacksize: 8,
+        vars: vec![],
+    };
+    let x86 = X86 {
+        conv: CallConv {
+            stack_align: 32,
+            ..CallConv::sysv()
+        },
+        ..X86::new()
+    };
+    assert!(x86.emit(&[], &[f]).contains("\tsub rsp, 32\n"));
+}
+
+#[test]
+fn verbose_annotates_ir() {
+    let ir = |op, lhs, rhs| IR {
+        op,
+        lhs: Some(lhs),
+        rhs,
+    };
+    let f = || Function {
+        name: "main".into(),
+        ir: vec![
+            ir(IROp::Imm, 0, Some(1)),
+            ir(IROp::Imm, 1, Some(2)),
+            ir(IROp::Add, 0, Some(1)),
+            ir(IROp::Return, 0, None),
+        ],
+        stacksize: 0,
+        vars: vec![],
+    };
+    let x86 = X86 {
+        verbose: true,
+        ..X86::new()
+    };
+    let asm = x86.emit(&[], &[f()]);
+
+    assert!(asm.contains("\tadd r10, r11\t# ADD r0, r1\n"));
+    assert!(asm.contains("\tmov rax, r10\n\tjmp .Lmain_end"));
+    assert!(asm.contains("\t# RET r0\n"));
+    assert!(!X86::new().emit(&[], &[f()]).contains('#'));
+}
@@ -0,0 +1,210 @@
+#[macro_use]
+extern crate lazy_static;
+extern crate r9cc;
+
+mod common;
+
+use common::LOCK;
+
+use r9cc::gen_ir::{gen_ir, Function, IROp};
+use r9cc::interp::run;
+use r9cc::opt::optimize;
+use r9cc::parse::parse;
+use r9cc::preprocess::Preprocessor;
+use r9cc::regalloc::alloc_regs;
+use r9cc::sema::sema;
+use r9cc::token::tokenize_str;
+
+use std::panic;
+
+fn compile(src: &str) -> Vec<Function> {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let tokens = tokenize_str("test.c".into(), src.into(), &mut Preprocessor::new());
+    let (nodes, _) = sema(parse(&tokens));
+    let mut fns = gen_ir(nodes);
+    optimize(&mut fns);
+    fns
+}
+
+#[test]
+fn main_with_argument() {
+    let fns = compile("int main(int n) { return n * 2; }");
+    assert_eq!(run(&fns, "main", &[21]), 42);
+    assert_eq!(run(&fns, "main", &[]), 0);
+}
+
+#[test]
+fn calls_and_locals() {
+    let fns = compile(
+        "int fib(int n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+         int main() { int x[3]; int i; for (i = 0; i < 3; i++) x[i] = fib(i + 8); return x[0] + x[2]; }",
+    );
+    assert_eq!(run(&fns, "main", &[]), 21 + 55);
+}
+
+#[test]
+fn signedness() {
+    let fns = compile(
+        "int s(int x) { return x / 2; }
+         unsigned u(unsigned x) { return x / 2; }
+         int c(int x) { char c = x; return c; }",
+    );
+    assert_eq!(run(&fns, "s", &[-1]), 0);
+    assert_eq!(run(&fns, "u", &[0xffff_ffff]), 0x7fff_ffff);
+    assert_eq!(run(&fns, "c", &[300]), 44);
+}
+
+#[test]
+fn unsigned_comparisons() {
+    let fns = compile(
+        "int gt(int x) { return x > 0UL; }
+         int le(unsigned x, unsigned y) { return x <= y; }",
+    );
+    assert_eq!(run(&fns, "gt", &[-1]), 1);
+    assert_eq!(run(&fns, "gt", &[0]), 0);
+    assert_eq!(run(&fns, "le", &[0xffff_ffff, 1]), 0);
+    assert_eq!(run(&fns, "le", &[1, 0xffff_ffff]), 1);
+}
+
+#[test]
+fn call_through_function_pointer() {
+    let fns = compile(
+        "int one() { return 1; }
+         int two() { return 2; }
+         int main(int n) { int (*fp)() = &one; if (n) fp = &two; return fp() * 10 + (*fp)(); }",
+    );
+    assert_eq!(run(&fns, "main", &[0]), 11);
+    assert_eq!(run(&fns, "main", &[1]), 22);
+}
+
+#[test]
+fn struct_return() {
+    let fns = compile(
+        "struct pair { int x; int y; };
+         struct pair mk(int x, int y) { struct pair p; p.x = x; p.y = y; return p; }
+         int main() { struct pair p; p = mk(3, 4); return p.x * 10 + p.y; }",
+    );
+    assert_eq!(run(&fns, "main", &[]), 34);
+
+    // Too big for a register, so returned through a hidden pointer.
+    let fns = compile(
+        "struct big { int a; int b; int c; int d; int e; };
+         struct big mk(int n) { struct big s; s.a = n; s.e = n * 2; return s; }
+         int main(int n) { struct big s = mk(n); return s.a + mk(5).e; }",
+    );
+    assert_eq!(run(&fns, "main", &[7]), 17);
+
+    // Returned in a register, so it is spilled to take a member.
+    let fns = compile(
+        "struct pair { int x; int y; };
+         struct pair mk(int x, int y) { struct pair p; p.x = x; p.y = y; return p; }
+         int main() { return mk(8, 9).y; }",
+    );
+    assert_eq!(run(&fns, "main", &[]), 9);
+}
+
+#[test]
+fn struct_arguments() {
+    let fns = compile(
+        "struct pair { int x; int y; };
+         int second(struct pair p) { return p.y; }
+         int main() { struct pair p; p.x = 3; p.y = 4; return second(p); }",
+    );
+    assert_eq!(run(&fns, "main", &[]), 4);
+
+    // Too big for a register, so passed as a pointer to a copy.
+    let fns = compile(
+        "struct big { int a; int b; int c; int d; int e; };
+         int f(struct big s) { s.a = 0; return s.e; }
+         int main(int n) { struct big s; s.a = n; s.e = n * 2; return f(s) + s.a; }",
+    );
+    assert_eq!(run(&fns, "main", &[7]), 21);
+}
+
+#[test]
+fn after_register_allocation() {
+    let mut fns = compile("int main(int n) { int a = n + 1; int b = n * 3; return a * b; }");
+    let before = run(&fns, "main", &[5]);
+    alloc_regs(&mut fns);
+    assert_eq!(run(&fns, "main", &[5]), before);
+    assert_eq!(before, 90);
+}
+
+#[test]
+fn if_else_truthiness() {
+    let fns = compile("int f(int c) { int x; if (c) x = 10; else x = 20; return x; }");
+    assert_eq!(run(&fns, "f", &[0]), 20);
+    assert_eq!(run(&fns, "f", &[1]), 10);
+    assert_eq!(run(&fns, "f", &[-1]), 10);
+}
+
+#[test]
+fn initializers_run_in_order() {
+    let fns = compile(
+        "int f() { int x = 1; int y = x + 1; return y; }
+         int next(int *p) { *p = *p + 1; return *p; }
+         int g() { int n = 0; int a = next(&n); n = 10; int b = next(&n); return a * 100 + b; }",
+    );
+    assert_eq!(run(&fns, "f", &[]), 2);
+    assert_eq!(run(&fns, "g", &[]), 111);
+}
+
+#[test]
+fn two_dimensional_array() {
+    let fns = compile(
+        "int size() { int a[2][3]; return sizeof(a); }
+         int index() { int a[2][3]; int *p = a[0]; a[1][2] = 7; return p[5]; }
+         int f() { int a[2][3]; int i; int j;
+                   for (i = 0; i < 2; i++) for (j = 0; j < 3; j++) a[i][j] = i * 10 + j;
+                   return a[1][2]; }",
+    );
+    assert_eq!(run(&fns, "size", &[]), 6 * 4);
+    // a[1][2] is element 1 * 3 + 2 of the flattened array.
+    assert_eq!(run(&fns, "index", &[]), 7);
+    assert_eq!(run(&fns, "f", &[]), 12);
+}
+
+#[test]
+fn null_pointer_checks() {
+    let fns = compile(
+        "int f() { int *p; p = 0; if (p) return 1; return 2; }
+         int g(int set) { int x; int *p = 0; if (set) p = &x; return (p == 0) * 10 + !p + (p != 0) * 100; }",
+    );
+    assert_eq!(run(&fns, "f", &[]), 2);
+    assert_eq!(run(&fns, "g", &[0]), 11);
+    assert_eq!(run(&fns, "g", &[1]), 100);
+}
+
+#[test]
+fn builtin_trap() {
+    let fns = compile("int main(int x) { if (x) __builtin_trap(); return 1; }");
+    assert!(fns[0].ir.iter().any(|ir| ir.op == IROp::Trap));
+    assert_eq!(run(&fns, "main", &[0]), 1);
+
+    let err = panic::catch_unwind(|| run(&fns, "main", &[1])).unwrap_err();
+    assert_eq!(err.downcast_ref::<String>().unwrap(), "main: trap");
+}
+
+#[test]
+fn elvis_evaluates_condition_once() {
+    let fns = compile(
+        "int bump(int *p) { *p = *p + 1; return *p; }
+         int main(int x) { int n = 0; int y = (bump(&n) * x) ?: 5; return y * 10 + n; }",
+    );
+    assert_eq!(run(&fns, "main", &[3]), 31);
+    assert_eq!(run(&fns, "main", &[0]), 51);
+}
+
+// There is no `long` keyword, so typeof(1L) stands in for it.
+#[test]
+fn long_arithmetic() {
+    let fns = compile(
+        "typeof(1L) mul(typeof(1L) x) { typeof(1L) y = x * 100000L; return y * 3; }
+         typeof(1L) div(typeof(1L) x) { return x * 100000L / 7; }
+         int narrow(typeof(1L) x) { int y = x; return y; }",
+    );
+    assert_eq!(run(&fns, "mul", &[100_000]), 30_000_000_000);
+    assert_eq!(run(&fns, "div", &[1_000_000]), 100_000_000_000 / 7);
+    assert_eq!(run(&fns, "div", &[-1_000_000]), -100_000_000_000 / 7);
+    assert_eq!(run(&fns, "narrow", &[0x1_0000_0005]), 5);
+}
@@ -0,0 +1,1016 @@
+#[macro_use]
+extern crate lazy_static;
+extern crate r9cc;
+
+mod common;
+
+use common::LOCK;
+
+use r9cc::gen_ir::{gen_ir, Function, IROp, IR};
+use r9cc::interp::run;
+use r9cc::irdump::{format_ir, format_ir_numbered};
+use r9cc::opt::optimize;
+use r9cc::parse::parse;
+use r9cc::preprocess::Preprocessor;
+use r9cc::regalloc::verify_kills;
+use r9cc::sema::sema;
+use r9cc::token::tokenize_str;
+
+use std::collections::HashSet;
+
+fn normalize(s: &str) -> Vec<&str> {
+    s.lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty())
+        .collect()
+}
+
+// Compiles `src` as `-dump-ir1` does and compares the dump with
+// `expected`. Indentation and blank lines do not matter.
+fn assert_ir(src: &str, expected: &str) {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let tokens = tokenize_str("test.c".into(), src.into(), &mut Preprocessor::new());
+    let nodes = parse(&tokens);
+    let (nodes, _) = sema(nodes);
+    let mut fns = gen_ir(nodes);
+    optimize(&mut fns);
+
+    let actual = format_ir(&fns);
+    assert_eq!(normalize(&actual), normalize(expected), "\n{}", actual);
+}
+
+#[test]
+fn assignment() {
+    assert_ir(
+        "int main() { int x; x = 3; return x; }",
+        "
+        main() [stack=4]:
+          MOV r0, 3
+          BPREL r1, 4
+          STORE4 r1, r0
+          KILL r1
+          MOV r2, r0
+          KILL r0
+          RET r2
+          KILL r2
+        ",
+    );
+}
+
+#[test]
+fn if_else() {
+    assert_ir(
+        "int main(int x) { if (x) return 2; else return 3; }",
+        "
+        main() [stack=4]:
+          STORE_ARG4 4, 0
+          BPREL r0, 4
+          LOAD4 r0, r0
+          UNLESS r0, .L0
+          KILL r0
+          MOV r1, 2
+          RET r1
+          KILL r1
+        .L0:
+          MOV r2, 3
+          RET r2
+          KILL r2
+        ",
+    );
+}
+
+#[test]
+fn call() {
+    assert_ir(
+        "int add(int a, int b) { return a + b; }
+         int main() { return add(1, 2); }",
+        "
+        add() [stack=8]:
+          STORE_ARG4 4, 0
+          STORE_ARG4 8, 1
+          BPREL r0, 4
+          LOAD4 r0, r0
+          BPREL r1, 8
+          LOAD4 r1, r1
+          ADD r0, r1
+          KILL r1
+          RET r0
+          KILL r0
+        main() [stack=8]:
+          MOV r0, 1
+          MOV r1, 2
+          BPREL r5, 4
+          STORE4 r5, r0
+          KILL r5
+          BPREL r6, 8
+          STORE4 r6, r1
+          KILL r6
+          BPREL r3, 4
+          LOAD4 r3, r3
+          MOV r4, r1
+          ADD r3, r4
+          KILL r4
+          MOV r2, r3
+          KILL r3
+          KILL r0
+          KILL r1
+          RET r2
+          KILL r2
+        ",
+    );
+}
+
+#[test]
+fn inlining() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let src = "int inc(int x) { return x + 1; }
+               int sign(int x) { if (x < 0) return 0 - 1; if (x) return 1; return 0; }
+               int main(int n) { return inc(n) * 10 + sign(n - 3); }";
+    let tokens = tokenize_str("test.c".into(), src.into(), &mut Preprocessor::new());
+    let (nodes, _) = sema(parse(&tokens));
+    let mut fns = gen_ir(nodes);
+    optimize(&mut fns);
+
+    let main = fns.iter().find(|f| f.name == "main").unwrap();
+    assert!(main.ir.iter().all(|ir| match ir.op {
+        IROp::Call(_, _, _) => false,
+        _ => true,
+    }));
+    assert_eq!(run(&fns, "main", &[41]), 421);
+    assert_eq!(run(&fns, "main", &[3]), 40);
+    assert_eq!(run(&fns, "main", &[1]), 19);
+}
+
+#[test]
+fn algebraic_identities() {
+    assert_ir(
+        "int main() { int x = 3; return x * 1 + 0; }",
+        "
+        main() [stack=4]:
+          MOV r0, 3
+          BPREL r1, 4
+          STORE4 r1, r0
+          KILL r1
+          MOV r2, r0
+          KILL r0
+          RET r2
+          KILL r2
+        ",
+    );
+}
+
+#[test]
+fn negation() {
+    assert_ir(
+        "int main() { int x = 3; return -x; }",
+        "
+        main() [stack=4]:
+          MOV r0, 3
+          BPREL r1, 4
+          STORE4 r1, r0
+          KILL r1
+          MOV r2, r0
+          KILL r0
+          NEG r2
+          RET r2
+          KILL r2
+        ",
+    );
+}
+
+#[test]
+fn negation_of_expression() {
+    assert_ir(
+        "int f(int x) { return -(x + 1); }",
+        "
+        f() [stack=4]:
+          STORE_ARG4 4, 0
+          BPREL r0, 4
+          LOAD4 r0, r0
+          MOV r1, 1
+          ADD r0, r1
+          KILL r1
+          NEG r0
+          RET r0
+          KILL r0
+        ",
+    );
+}
+
+#[test]
+fn sizeof_does_not_call() {
+    assert_ir(
+        "int f(); int main() { return sizeof(f()); }",
+        "
+        main() [stack=0]:
+          MOV r0, 4
+          RET r0
+          KILL r0
+        ",
+    );
+}
+
+#[test]
+#[should_panic(expected = "return with no value in function returning non-void")]
+fn return_without_value() {
+    assert_ir("int f() { return; }", "");
+}
+
+#[test]
+#[should_panic(expected = "return with a value in function returning void")]
+fn return_value_from_void() {
+    assert_ir("void g() { return 1; }", "");
+}
+
+#[test]
+fn return_from_void() {
+    assert_ir(
+        "void g() { return; }",
+        "
+        g() [stack=0]:
+          MOV r0, 0
+          RET r0
+          KILL r0
+        ",
+    );
+}
+
+#[test]
+fn cast_truncates() {
+    assert_ir(
+        "int main() { return (char)300; }",
+        "
+        main() [stack=0]:
+          MOV r0, 300
+          TRUNC r0, 1
+          RET r0
+          KILL r0
+        ",
+    );
+}
+
+#[test]
+fn unsigned_arithmetic() {
+    assert_ir(
+        "unsigned f(unsigned x) { return x / 2; } int g(int x) { return x >> 1; }",
+        "
+        f() [stack=4]:
+          STORE_ARG4 4, 0
+          BPREL r0, 4
+          LOAD4 r0, r0
+          ZEXT r0, 4
+          MOV r1, 2
+          ZEXT r1, 4
+          UDIV r0, r1
+          KILL r1
+          RET r0
+          KILL r0
+        g() [stack=4]:
+          STORE_ARG4 4, 0
+          BPREL r0, 4
+          LOAD4 r0, r0
+          MOV r1, 1
+          SAR r0, r1
+          KILL r1
+          RET r0
+          KILL r0
+        ",
+    );
+}
+
+#[test]
+fn sizeof_is_unsigned() {
+    assert_ir(
+        "int main() { int *p; return sizeof(p) - 10 > 0; }",
+        "
+        main() [stack=8]:
+          MOV r0, 0
+          ZEXT r0, 4
+          MOV r1, 8
+          MOV r2, 10
+          ZEXT r2, 4
+          SUB r1, r2
+          KILL r2
+          ZEXT r1, 4
+          ULT r0, r1
+          KILL r1
+          RET r0
+          KILL r0
+        ",
+    );
+}
+
+#[test]
+fn loop_invariant_code_motion() {
+    assert_ir(
+        "int main() { int a; int b; int y; int i; for (i=0; i<10; i++) y = a * b; return y; }",
+        "
+        main() [stack=16]:
+          MOV r0, 0
+          BPREL r1, 16
+          STORE4 r1, r0
+          KILL r1
+          KILL r0
+          BPREL r4, 4
+          LOAD4 r4, r4
+          BPREL r5, 8
+          LOAD4 r5, r5
+          MUL r4, r5
+        .L0:
+          BPREL r2, 16
+          LOAD4 r2, r2
+          MOV r3, 10
+          LT r2, r3
+          KILL r3
+          UNLESS r2, .L1
+          KILL r2
+          BPREL r6, 12
+          STORE4 r6, r4
+          KILL r6
+          BPREL r7, 16
+          LOAD4 r8, r7
+          ADD r8, 1
+          STORE4 r7, r8
+          KILL r7
+          SUB r8, 1
+          KILL r8
+          JMP .L0
+          KILL r4
+          KILL r5
+        .L1:
+          BPREL r9, 12
+          LOAD4 r9, r9
+          RET r9
+          KILL r9
+        ",
+    );
+}
+
+#[test]
+fn string_literal_argument() {
+    assert_ir(
+        "int puts(); int main() { puts(\"hi\"); return 0; }",
+        "
+        main() [stack=0]:
+          LABEL_ADDR r0, .L.str0
+          r1 = puts(r0)
+          KILL r0
+          KILL r1
+          MOV r2, 0
+          RET r2
+          KILL r2
+        ",
+    );
+}
+
+#[test]
+fn constant_branch() {
+    assert_ir(
+        "int main() { if (0) return 1; return 2; }",
+        "
+        main() [stack=0]:
+        .L0:
+          MOV r2, 2
+          RET r2
+          KILL r2
+        ",
+    );
+    assert_ir(
+        "int main() { if (1) return 1; else return 2; }",
+        "
+        main() [stack=0]:
+          MOV r1, 1
+          RET r1
+          KILL r1
+        ",
+    );
+}
+
+// The condition of an empty if statement is not even computed.
+#[test]
+fn empty_if_body() {
+    assert_ir(
+        "int f(int x) { if (x) ; return x; }",
+        "
+        f() [stack=4]:
+          STORE_ARG4 4, 0
+        .L0:
+          BPREL r1, 4
+          LOAD4 r1, r1
+          RET r1
+          KILL r1
+        ",
+    );
+}
+
+// Both ifs end at the same point, so they jump to the same label.
+#[test]
+fn nested_if_ends_share_label() {
+    assert_ir(
+        "int f(int a, int b) { int x; x = 0; if (a) if (b) x = 1; return x; }",
+        "
+        f() [stack=12]:
+          STORE_ARG4 4, 0
+          STORE_ARG4 8, 1
+          MOV r0, 0
+          BPREL r1, 12
+          STORE4 r1, r0
+          KILL r1
+          KILL r0
+          BPREL r2, 4
+          LOAD4 r2, r2
+          UNLESS r2, .L1
+          KILL r2
+          BPREL r3, 8
+          LOAD4 r3, r3
+          UNLESS r3, .L1
+          KILL r3
+          MOV r4, 1
+          BPREL r5, 12
+          STORE4 r5, r4
+          KILL r5
+          KILL r4
+        .L1:
+          BPREL r6, 12
+          LOAD4 r6, r6
+          RET r6
+          KILL r6
+        ",
+    );
+}
+
+// x is not read back from memory after the store.
+#[test]
+fn stored_value_is_forwarded() {
+    assert_ir(
+        "int f(int a, int b) { int x; x = a + b; return x + 1; }",
+        "
+        f() [stack=12]:
+          STORE_ARG4 4, 0
+          STORE_ARG4 8, 1
+          BPREL r0, 4
+          LOAD4 r0, r0
+          BPREL r1, 8
+          LOAD4 r1, r1
+          ADD r0, r1
+          KILL r1
+          BPREL r2, 12
+          STORE4 r2, r0
+          KILL r2
+          MOV r3, r0
+          TRUNC r3, 4
+          KILL r0
+          MOV r4, 1
+          ADD r3, r4
+          KILL r4
+          RET r3
+          KILL r3
+        ",
+    );
+}
+
+#[test]
+fn stack_size_in_header() {
+    assert_ir(
+        "int main() { int x; int y; return 0; }",
+        "
+        main() [stack=8]:
+          MOV r0, 0
+          RET r0
+          KILL r0
+        ",
+    );
+}
+
+#[test]
+fn numbered() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let src = "int main() { int x; for (x = 0; x < 3; x++); return x; }";
+    let tokens = tokenize_str("test.c".into(), src.into(), &mut Preprocessor::new());
+    let (nodes, _) = sema(parse(&tokens));
+    let fns = gen_ir(nodes);
+
+    let dump = format_ir_numbered(&fns);
+    let lines: Vec<&str> = dump.lines().collect();
+    assert_eq!(lines[0], "main() [stack=4]:");
+    assert_eq!(lines[1], "0000: MOV r0, 0");
+    for (i, line) in lines[1..].iter().enumerate() {
+        assert!(line.starts_with(&format!("{:04}: ", i)), "{}", line);
+    }
+    assert!(lines.len() > 11);
+    assert!(dump.contains(": .L1:\n"));
+}
+
+#[test]
+fn terminators() {
+    assert!(IROp::Jmp.is_terminator());
+    assert!(IROp::Return.is_terminator());
+    assert!(IROp::If.is_terminator());
+    assert!(IROp::Unless.is_terminator());
+    assert!(!IROp::Add.is_terminator());
+    assert!(!IROp::Label.is_terminator());
+    assert!(!IROp::Call("f".into(), vec![], vec![]).is_terminator());
+}
+
+fn regs_and_labels(f: &Function) -> (HashSet<usize>, HashSet<usize>) {
+    let mut regs = HashSet::new();
+    let mut labels = HashSet::new();
+    for ir in &f.ir {
+        match ir.op {
+            IROp::Label | IROp::Jmp => {
+                labels.insert(ir.lhs.unwrap());
+            }
+            IROp::If | IROp::Unless => {
+                labels.insert(ir.rhs.unwrap());
+            }
+            IROp::Kill => {
+                regs.insert(ir.lhs.unwrap());
+            }
+            _ => (),
+        }
+        regs.extend(ir.uses());
+        regs.extend(ir.def());
+    }
+    (regs, labels)
+}
+
+#[test]
+fn renumbered_regs() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let src = "int g(int a, int b) { while (a) a = a - b; if (a) return 1; return g(a, 2); }";
+    let tokens = tokenize_str("test.c".into(), src.into(), &mut Preprocessor::new());
+    let (nodes, _) = sema(parse(&tokens));
+    let g = gen_ir(nodes).remove(0);
+
+    // Merging a function with a copy of itself needs renumbering.
+    let copy = g.clone_with_renumbered_regs(g.reg_count(), g.label_count());
+    let (regs, labels) = regs_and_labels(&g);
+    let (copy_regs, copy_labels) = regs_and_labels(&copy);
+    assert!(labels.len() >= 3);
+    assert!(regs.is_disjoint(&copy_regs));
+    assert!(labels.is_disjoint(&copy_labels));
+    assert_eq!(copy_regs.len(), regs.len());
+    assert_eq!(copy_labels.len(), labels.len());
+
+    // Only the numbers change.
+    let renumbered = g.clone_with_renumbered_regs(100, 10);
+    for (a, b) in renumbered.ir.iter().zip(&g.ir) {
+        let shift = |r: Option<usize>, n: usize| r.map(|r| r + n);
+        match a.op {
+            IROp::Label | IROp::Jmp => assert_eq!(a.lhs, shift(b.lhs, 10)),
+            IROp::If | IROp::Unless => assert_eq!(a.rhs, shift(b.rhs, 10)),
+            IROp::StoreArg(_) => assert_eq!((a.lhs, a.rhs), (b.lhs, b.rhs)),
+            IROp::Imm | IROp::Bprel => assert_eq!((a.lhs, a.rhs), (shift(b.lhs, 100), b.rhs)),
+            _ => assert_eq!(a.lhs, shift(b.lhs, 100)),
+        }
+    }
+    assert_eq!(renumbered.ir.len(), g.ir.len());
+}
+
+#[test]
+fn conditional_move() {
+    assert_ir(
+        "int min(int x, int y) { return x < y ? x : y; }",
+        "
+        min() [stack=8]:
+          STORE_ARG4 4, 0
+          STORE_ARG4 8, 1
+          BPREL r0, 4
+          LOAD4 r0, r0
+          BPREL r1, 8
+          LOAD4 r1, r1
+          LT r0, r1
+          KILL r1
+          BPREL r2, 8
+          LOAD4 r2, r2
+          BPREL r3, 4
+          LOAD4 r3, r3
+          CMOV r2, r3, r0
+          KILL r3
+          KILL r0
+          RET r2
+          KILL r2
+        ",
+    );
+}
+
+#[test]
+fn conditional_with_side_effect_branches() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let src = "int main(int x) { int n; n = 0; return x ? n++ : n; }";
+    let tokens = tokenize_str("test.c".into(), src.into(), &mut Preprocessor::new());
+    let (nodes, _) = sema(parse(&tokens));
+    let fns = gen_ir(nodes);
+    assert!(fns[0].ir.iter().all(|ir| match ir.op {
+        IROp::CMov(_) => false,
+        _ => true,
+    }));
+    assert!(fns[0].ir.iter().any(|ir| ir.op == IROp::Unless));
+}
+
+#[test]
+fn variable_offsets() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let src = "int f(int x) { int a; char b; int c[2]; a = b = x; return a + b; }";
+    let tokens = tokenize_str("test.c".into(), src.into(), &mut Preprocessor::new());
+    let (nodes, _) = sema(parse(&tokens));
+    let fns = gen_ir(nodes);
+
+    let vars: Vec<(&str, usize)> = fns[0]
+        .vars
+        .iter()
+        .map(|&(ref name, offset)| (name.as_str(), offset))
+        .collect();
+    assert_eq!(vars, vec![("x", 4), ("a", 8), ("b", 9), ("c", 20)]);
+}
+
+// Stack slots are assigned by sema as declarations are seen, so the
+// order in which code uses the variables, or which branch declares
+// them, does not change their offsets.
+#[test]
+fn offsets_follow_declaration_order() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let src = "int f(int x) {
+                 int a; int b; int c;
+                 if (x) c = 1; else { int d; d = 2; b = d; }
+                 int e; e = 3; a = e;
+                 return a + b + c;
+               }";
+    let tokens = tokenize_str("test.c".into(), src.into(), &mut Preprocessor::new());
+    let (nodes, _) = sema(parse(&tokens));
+    let fns = gen_ir(nodes);
+
+    let vars: Vec<(&str, usize)> = fns[0]
+        .vars
+        .iter()
+        .map(|&(ref name, offset)| (name.as_str(), offset))
+        .collect();
+    assert_eq!(
+        vars,
+        vec![
+            ("x", 4),
+            ("a", 8),
+            ("b", 12),
+            ("c", 16),
+            ("d", 20),
+            ("e", 24)
+        ]
+    );
+    assert_eq!(fns[0].stacksize, 24);
+}
+
+#[test]
+fn continue_in_switch_targets_loop() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let src = "int f(int n) {
+                 int i; int s; s = 0;
+                 for (i = 0; i < n; i++) {
+                   switch (i) { case 1: continue; default: break; }
+                   s = s + i;
+                 }
+                 return s;
+               }";
+    let tokens = tokenize_str("test.c".into(), src.into(), &mut Preprocessor::new());
+    let (nodes, _) = sema(parse(&tokens));
+    let fns = gen_ir(nodes);
+
+    // `continue` skips `s = s + i` but not `i++`, and `break` only
+    // leaves the switch: 0 + 2 + 3.
+    assert_eq!(run(&fns, "f", &[4]), 5);
+}
+
+#[test]
+#[should_panic(expected = "stray 'continue' statement")]
+fn continue_in_switch_outside_loop() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let src = "int f(int n) { switch (n) { case 1: continue; } return 0; }";
+    let tokens = tokenize_str("test.c".into(), src.into(), &mut Preprocessor::new());
+    let (nodes, _) = sema(parse(&tokens));
+    gen_ir(nodes);
+}
+
+#[test]
+fn not_equal_branch_is_fused() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let src = "int f(int a, int b) { while (a != b) a = a + 1; return a; }";
+    let tokens = tokenize_str("test.c".into(), src.into(), &mut Preprocessor::new());
+    let (nodes, _) = sema(parse(&tokens));
+    let mut fns = gen_ir(nodes);
+    optimize(&mut fns);
+
+    let ops: Vec<&IROp> = fns[0].ir.iter().map(|ir| &ir.op).collect();
+    assert_eq!(
+        ops.iter().filter(|op| matches!(op, IROp::IfEq(_))).count(),
+        1
+    );
+    assert!(!ops
+        .iter()
+        .any(|op| **op == IROp::NE || **op == IROp::Unless));
+    assert_eq!(run(&fns, "f", &[3, 8]), 8);
+}
+
+#[test]
+fn min_max_builtins() {
+    assert_ir(
+        "int f() { return __builtin_max(3, 7); }",
+        "
+        f() [stack=0]:
+          MOV r0, 3
+          MOV r1, 7
+          MOV r2, r0
+          LT r2, r1
+          CMOV r0, r1, r2
+          KILL r2
+          KILL r1
+          RET r0
+          KILL r0
+        ",
+    );
+
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let src = "int max() { return __builtin_max(3, 7); }
+               int min(int a, int b) { return __builtin_min(a, b); }";
+    let tokens = tokenize_str("test.c".into(), src.into(), &mut Preprocessor::new());
+    let (nodes, _) = sema(parse(&tokens));
+    let fns = gen_ir(nodes);
+    assert_eq!(run(&fns, "max", &[]), 7);
+    assert_eq!(run(&fns, "min", &[-2, 5]), -2);
+    assert_eq!(run(&fns, "min", &[9, 5]), 5);
+}
+
+#[test]
+fn assign_comparison() {
+    assert_ir(
+        "int f() { int flag; flag = 3 < 5; return flag; }",
+        "
+        f() [stack=4]:
+          MOV r0, 3
+          MOV r1, 5
+          LT r0, r1
+          KILL r1
+          BPREL r2, 4
+          STORE4 r2, r0
+          KILL r2
+          MOV r3, r0
+          TRUNC r3, 4
+          KILL r0
+          RET r3
+          KILL r3
+        ",
+    );
+
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let src = "int f(int a, int b) { int flag; flag = a < b; return flag + (a == b); }";
+    let tokens = tokenize_str("test.c".into(), src.into(), &mut Preprocessor::new());
+    let (nodes, _) = sema(parse(&tokens));
+    let fns = gen_ir(nodes);
+    verify_kills(&fns[0]);
+    assert_eq!(run(&fns, "f", &[3, 5]), 1);
+    assert_eq!(run(&fns, "f", &[5, 3]), 0);
+    assert_eq!(run(&fns, "f", &[4, 4]), 1);
+}
+
+#[test]
+fn comma_in_for_increment() {
+    assert_ir(
+        "int f() { int sum=0; int i; for (i=0; i<3; i++, sum=sum+i) ; return sum; }",
+        "
+        f() [stack=8]:
+          MOV r0, 0
+          BPREL r1, 4
+          STORE4 r1, r0
+          KILL r1
+          KILL r0
+          MOV r2, 0
+          BPREL r3, 8
+          STORE4 r3, r2
+          KILL r3
+          KILL r2
+        .L0:
+          BPREL r4, 8
+          LOAD4 r4, r4
+          MOV r5, 3
+          LT r4, r5
+          KILL r5
+          UNLESS r4, .L1
+          KILL r4
+          BPREL r6, 8
+          LOAD4 r7, r6
+          ADD r7, 1
+          STORE4 r6, r7
+          KILL r6
+          SUB r7, 1
+          KILL r7
+          BPREL r8, 4
+          LOAD4 r8, r8
+          BPREL r9, 8
+          LOAD4 r9, r9
+          ADD r8, r9
+          KILL r9
+          BPREL r10, 4
+          STORE4 r10, r8
+          KILL r10
+          KILL r8
+          JMP .L0
+        .L1:
+          BPREL r11, 4
+          LOAD4 r11, r11
+          RET r11
+          KILL r11
+        ",
+    );
+}
+
+// A while loop has no increment, so `continue` jumps back to the
+// condition at .L1 rather than to a label at the end of the body.
+#[test]
+fn continue_in_while_rechecks_condition() {
+    assert_ir(
+        "int f(int x) { int n=0; while (x) { x=x-1; if (x & 1) continue; n=n+1; } return n; }",
+        "
+        f() [stack=8]:
+          STORE_ARG4 4, 0
+          MOV r0, 0
+          BPREL r1, 8
+          STORE4 r1, r0
+          KILL r1
+          KILL r0
+        .L0:
+          BPREL r2, 4
+          LOAD4 r2, r2
+          UNLESS r2, .L1
+          KILL r2
+          BPREL r3, 4
+          LOAD4 r3, r3
+          MOV r4, 1
+          SUB r3, r4
+          KILL r4
+          BPREL r5, 4
+          STORE4 r5, r3
+          KILL r5
+          MOV r6, r3
+          TRUNC r6, 4
+          KILL r3
+          MOV r7, 1
+          AND r6, r7
+          KILL r7
+          UNLESS r6, .L3
+          KILL r6
+          JMP .L0
+        .L3:
+          BPREL r8, 8
+          LOAD4 r8, r8
+          MOV r9, 1
+          ADD r8, r9
+          KILL r9
+          BPREL r10, 8
+          STORE4 r10, r8
+          KILL r10
+          KILL r8
+          JMP .L0
+        .L1:
+          BPREL r11, 8
+          LOAD4 r11, r11
+          RET r11
+          KILL r11
+        ",
+    );
+}
+
+#[test]
+fn volatile_loads_are_not_merged() {
+    assert_ir(
+        "int f(int a) { return a * a; }
+         int g(int a) { volatile int v = a; return v * v; }",
+        "
+        f() [stack=4]:
+          STORE_ARG4 4, 0
+          BPREL r0, 4
+          LOAD4 r0, r0
+          MOV r1, r0
+          MUL r0, r1
+          KILL r1
+          RET r0
+          KILL r0
+        g() [stack=8]:
+          STORE_ARG4 4, 0
+          BPREL r0, 4
+          LOAD4 r0, r0
+          BPREL r1, 8
+          VSTORE4 r1, r0
+          KILL r1
+          KILL r0
+          BPREL r2, 8
+          VLOAD4 r2, r2
+          BPREL r3, 8
+          VLOAD4 r3, r3
+          MUL r2, r3
+          KILL r3
+          RET r2
+          KILL r2
+        ",
+    );
+}
+
+#[test]
+fn char_arithmetic_is_int() {
+    assert_ir(
+        "int main() { char c1; char c2; return sizeof(c1 + c2) + sizeof(c1); }",
+        "
+        main() [stack=2]:
+          MOV r0, 4
+          MOV r1, 1
+          ADD r0, r1
+          KILL r1
+          ZEXT r0, 4
+          RET r0
+          KILL r0
+        ",
+    );
+}
+
+#[test]
+fn canonical_regs_ignore_gaps() {
+    let ir = |op, lhs, rhs| IR { op, lhs, rhs };
+    // r = 1; s = 2; r += s; return r; with different register numbers.
+    let code = |r, s| {
+        vec![
+            ir(IROp::Imm, Some(r), Some(1)),
+            ir(IROp::Imm, Some(s), Some(2)),
+            ir(IROp::Add, Some(r), Some(s)),
+            ir(IROp::Kill, Some(s), None),
+            ir(IROp::Return, Some(r), None),
+            ir(IROp::Kill, Some(r), None),
+        ]
+    };
+    let function = |ir| Function {
+        name: "f".into(),
+        ir,
+        stacksize: 0,
+        vars: vec![],
+    };
+    let mut a = function(code(0, 1));
+    let mut b = function(code(4, 9));
+    assert_ne!(format_ir(&[a.clone()]), format_ir(&[b.clone()]));
+
+    a.canonicalize_regs();
+    b.canonicalize_regs();
+    assert_eq!(format_ir(&[a.clone()]), format_ir(&[b]));
+    assert_eq!(a.ir[2].to_string().trim(), "ADD r1, r2");
+}
+
+// `do { ... } while (0)` is not a loop: the body is emitted once, and
+// `break` jumps past it.
+#[test]
+fn do_while_zero_is_flattened() {
+    assert_ir(
+        "int f(int x) { do { x = 1; } while (0); return x; }",
+        "
+        f() [stack=4]:
+          STORE_ARG4 4, 0
+          MOV r0, 1
+          BPREL r1, 4
+          STORE4 r1, r0
+          KILL r1
+          KILL r0
+        .L0:
+          BPREL r2, 4
+          LOAD4 r2, r2
+          RET r2
+          KILL r2
+        ",
+    );
+}
+
+// A volatile read is a side effect, so a ternary that may skip it is not
+// turned into a conditional move.
+#[test]
+fn volatile_read_is_not_speculated() {
+    assert_ir(
+        "int f(int x) { volatile int v = 1; return x ? v : 3; }",
+        "
+        f() [stack=8]:
+          STORE_ARG4 4, 0
+          MOV r0, 1
+          BPREL r1, 8
+          VSTORE4 r1, r0
+          KILL r1
+          KILL r0
+          BPREL r2, 4
+          LOAD4 r2, r2
+          UNLESS r2, .L0
+          BPREL r3, 8
+          VLOAD4 r3, r3
+          MOV r2, r3
+          KILL r3
+          JMP .L1
+        .L0:
+          MOV r4, 3
+          MOV r2, r4
+          KILL r4
+        .L1:
+          RET r2
+          KILL r2
+        ",
+    );
+}
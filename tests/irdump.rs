@@ -0,0 +1,90 @@
+extern crate r9cc;
+
+use r9cc::gen_ir::{IROp, IRType, IR};
+use r9cc::irdump::IRInfo;
+
+fn call(args: Vec<usize>, slots: Vec<usize>) -> String {
+    let ir = IR {
+        op: IROp::Call("f".into(), args, slots),
+        lhs: Some(3),
+        rhs: None,
+    };
+    ir.to_string()
+}
+
+#[test]
+fn call_without_args() {
+    assert_eq!(call(vec![], vec![]), "  r3 = f()");
+}
+
+#[test]
+fn call_with_args() {
+    assert_eq!(call(vec![1], vec![]), "  r3 = f(r1)");
+    assert_eq!(call(vec![1, 2], vec![]), "  r3 = f(r1, r2)");
+}
+
+#[test]
+fn call_with_stack_args() {
+    assert_eq!(call(vec![1], vec![8, 16]), "  r3 = f(r1, [bp-8], [bp-16])");
+}
+
+#[test]
+fn info_for_every_op() {
+    use IROp::*;
+    // IROp also has Imm, Call, Label and so on, so the IRType variants
+    // that clash with them are spelled out below.
+    use IRType::{Mem, Noarg, Reg, RegImm, RegLabel, RegReg, RegRegLabel};
+    let cases = vec![
+        (Add, "ADD", RegReg),
+        (AddImm, "ADD", RegImm),
+        (Sub, "SUB", RegReg),
+        (SubImm, "SUB", RegImm),
+        (Mul, "MUL", RegReg),
+        (MulImm, "MUL", RegImm),
+        (Div, "DIV", RegReg),
+        (UDiv, "UDIV", RegReg),
+        (IROp::Imm, "MOV", RegImm),
+        (Bprel, "BPREL", RegImm),
+        (Mov, "MOV", RegReg),
+        (Return, "RET", Reg),
+        (Call("f".into(), vec![], vec![]), "CALL", IRType::Call),
+        (CallReg(0, vec![], vec![]), "CALL", IRType::Call),
+        (IROp::Label, "", IRType::Label),
+        (IROp::LabelAddr("x".into()), "LABEL_ADDR", IRType::LabelAddr),
+        (EQ, "EQ", RegReg),
+        (NE, "NE", RegReg),
+        (LE, "LE", RegReg),
+        (LT, "LT", RegReg),
+        (ULE, "ULE", RegReg),
+        (ULT, "ULT", RegReg),
+        (AND, "AND", RegReg),
+        (OR, "OR", RegReg),
+        (XOR, "XOR", RegReg),
+        (SHL, "SHL", RegReg),
+        (SHR, "SHR", RegReg),
+        (SAR, "SAR", RegReg),
+        (Mod, "MOD", RegReg),
+        (UMod, "UMOD", RegReg),
+        (Neg, "NEG", Reg),
+        (Trunc, "TRUNC", RegImm),
+        (Zext, "ZEXT", RegImm),
+        (IROp::CMov(0), "CMOV", IRType::CMov),
+        (IROp::Jmp, "JMP", IRType::Jmp),
+        (If, "IF", RegLabel),
+        (Unless, "UNLESS", RegLabel),
+        (IfEq(0), "IF_EQ", RegRegLabel),
+        (Load(4), "LOAD", Mem),
+        (Store(4), "STORE", Mem),
+        (VolatileLoad(4), "VLOAD", Mem),
+        (VolatileStore(4), "VSTORE", Mem),
+        (IROp::StoreArg(4), "STORE_ARG", IRType::StoreArg),
+        (Kill, "KILL", Reg),
+        (Nop, "NOP", Noarg),
+        (IROp::Asm("nop".into()), "ASM", IRType::Asm),
+        (IROp::Trap, "TRAP", Noarg),
+    ];
+    for (op, name, ty) in cases {
+        let info = IRInfo::from(&op);
+        assert_eq!((info.name, info.ty), (name, ty), "{:?}", op);
+    }
+}
@@ -0,0 +1,81 @@
+// Dumping the IR must not allocate for each instruction. This needs its
+// own test binary, since it replaces the global allocator.
+
+#[macro_use]
+extern crate lazy_static;
+extern crate r9cc;
+
+mod common;
+
+use common::ir;
+
+use r9cc::gen_ir::{Function, IROp};
+use r9cc::irdump::write_ir;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+// Counts the allocations made by the current thread while enabled.
+struct Counter;
+
+thread_local! {
+    static COUNTING: Cell<bool> = Cell::new(false);
+    static ALLOCS: Cell<usize> = Cell::new(0);
+}
+
+unsafe impl GlobalAlloc for Counter {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if COUNTING.with(|c| c.get()) {
+            ALLOCS.with(|n| n.set(n.get() + 1));
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static A: Counter = Counter;
+
+fn allocs_while<F: FnOnce()>(f: F) -> usize {
+    ALLOCS.with(|n| n.set(0));
+    COUNTING.with(|c| c.set(true));
+    f();
+    COUNTING.with(|c| c.set(false));
+    ALLOCS.with(|n| n.get())
+}
+
+#[test]
+fn dump_does_not_allocate_per_instruction() {
+    let mut code = vec![];
+    for i in 0..1000 {
+        code.push(ir(IROp::Label, i, None));
+        code.push(ir(IROp::Imm, 1, Some(i)));
+        code.push(ir(IROp::LabelAddr("g".into()), 2, None));
+        code.push(ir(IROp::Load(4), 3, Some(2)));
+        code.push(ir(IROp::Call("f".into(), vec![1, 3], vec![8, 16]), 4, None));
+        code.push(ir(IROp::CallReg(2, vec![], vec![8]), 5, None));
+        code.push(ir(IROp::Phi(vec![(0, 4), (1, 5)]), 6, None));
+        code.push(ir(IROp::Asm("nop".into()), 0, None));
+        code.push(ir(IROp::Unless, 6, Some(i)));
+    }
+    let fns = vec![Function {
+        name: "main".into(),
+        ir: code,
+        stacksize: 16,
+        vars: vec![],
+    }];
+
+    for &(numbered, line) in &[
+        (false, "  r4 = f(r1, r3, [bp-8], [bp-16])\n"),
+        (true, "0004: r4 = f(r1, r3, [bp-8], [bp-16])\n"),
+    ] {
+        let mut out = String::with_capacity(1 << 20);
+        let n = allocs_while(|| write_ir(&mut out, &fns, numbered).unwrap());
+        assert_eq!(n, 0);
+        assert!(out.contains(line));
+        assert!(out.len() < 1 << 20);
+    }
+}
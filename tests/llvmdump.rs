@@ -0,0 +1,49 @@
+#[macro_use]
+extern crate lazy_static;
+extern crate r9cc;
+
+mod common;
+
+use common::LOCK;
+
+use r9cc::gen_ir::gen_ir;
+use r9cc::llvmdump::format_llvm;
+use r9cc::opt::optimize;
+use r9cc::parse::parse;
+use r9cc::preprocess::Preprocessor;
+use r9cc::sema::sema;
+use r9cc::token::tokenize_str;
+
+fn llvm(src: &str) -> String {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let tokens = tokenize_str("test.c".into(), src.into(), &mut Preprocessor::new());
+    let (nodes, _) = sema(parse(&tokens));
+    let mut fns = gen_ir(nodes);
+    optimize(&mut fns);
+    format_llvm(&fns)
+}
+
+#[test]
+fn add() {
+    let out = llvm("int f(int a, int b) { return a + b; }");
+    assert!(out.starts_with("define i64 @f(i64 %a0, i64 %a1) {\nb0:\n"));
+    assert!(out.contains("  %r0.1 = load i32, ptr %r0\n"));
+    assert!(out.contains("  %r0.2 = add i64 %r0.1, %r1.1\n"));
+    assert!(out.contains("  ret i64 %r0.2\n"));
+    assert!(out.ends_with("}\n"));
+}
+
+// The two values of the conditional meet in a PHI that names the block
+// each of them comes from.
+#[test]
+fn phi() {
+    let out = llvm(
+        "int g(int x) { return x; }
+         int f(int x) { return x ? g(1) : g(2); }",
+    );
+    assert!(out.contains("\n\ndefine i64 @f(i64 %a0) {\n"));
+    assert!(out.contains(", label %b1, label %b2\n"));
+    let phi = out.lines().find(|l| l.contains(" = phi i64 ")).unwrap();
+    assert!(phi.contains(", %b1 ], [ %r"), "{}", phi);
+    assert!(phi.ends_with(", %b2 ]"), "{}", phi);
+}
@@ -0,0 +1,116 @@
+#[macro_use]
+extern crate lazy_static;
+extern crate r9cc;
+
+mod common;
+
+use common::{function, ir};
+
+use r9cc::gen_ir::{Function, IROp};
+use r9cc::opt::{optimize, schedule};
+use r9cc::regalloc::verify_kills;
+
+fn ops(f: &Function) -> Vec<String> {
+    f.ir.iter()
+        .map(|ir| ir.to_string().trim().to_string())
+        .collect()
+}
+
+#[test]
+fn independent_chains_are_interleaved() {
+    let mut fns = vec![function(vec![
+        ir(IROp::Imm, 0, Some(1)),
+        ir(IROp::AddImm, 0, Some(1)),
+        ir(IROp::Imm, 1, Some(2)),
+        ir(IROp::AddImm, 1, Some(2)),
+        ir(IROp::Add, 0, Some(1)),
+        ir(IROp::Kill, 1, None),
+        ir(IROp::Return, 0, None),
+        ir(IROp::Kill, 0, None),
+    ])];
+    schedule(&mut fns);
+    verify_kills(&fns[0]);
+
+    assert_eq!(
+        ops(&fns[0]),
+        vec![
+            "MOV r0, 1",
+            "MOV r1, 2",
+            "ADD r0, 1",
+            "ADD r1, 2",
+            "ADD r0, r1",
+            "KILL r1",
+            "RET r0",
+            "KILL r0",
+        ]
+    );
+}
+
+#[test]
+fn memory_order_is_kept() {
+    let mut fns = vec![function(vec![
+        ir(IROp::Bprel, 0, Some(4)),
+        ir(IROp::Imm, 1, Some(3)),
+        ir(IROp::Store(4), 0, Some(1)),
+        ir(IROp::Kill, 1, None),
+        ir(IROp::Load(4), 2, Some(0)),
+        ir(IROp::Kill, 0, None),
+        ir(IROp::Return, 2, None),
+        ir(IROp::Kill, 2, None),
+    ])];
+    schedule(&mut fns);
+    verify_kills(&fns[0]);
+
+    let ops = ops(&fns[0]);
+    let store = ops.iter().position(|s| s.starts_with("STORE4")).unwrap();
+    let load = ops.iter().position(|s| s.starts_with("LOAD4")).unwrap();
+    assert!(store < load);
+}
+
+#[test]
+fn nothing_moves_across_a_call() {
+    let code = vec![
+        ir(IROp::Imm, 0, Some(1)),
+        ir(IROp::Call("g".into(), vec![0], vec![]), 1, None),
+        ir(IROp::Kill, 0, None),
+        ir(IROp::Imm, 2, Some(3)),
+        ir(IROp::Add, 1, Some(2)),
+        ir(IROp::Kill, 2, None),
+        ir(IROp::Return, 1, None),
+        ir(IROp::Kill, 1, None),
+    ];
+    let mut fns = vec![function(code.clone())];
+    schedule(&mut fns);
+    verify_kills(&fns[0]);
+
+    // Hoisting `MOV r2, 3` above the call would keep r2 live across it.
+    assert_eq!(ops(&fns[0]), ops(&function(code)));
+}
+
+#[test]
+fn self_moves_are_removed() {
+    let mut fns = vec![function(vec![
+        ir(IROp::Imm, 0, Some(3)),
+        ir(IROp::Mov, 0, Some(0)),
+        ir(IROp::Imm, 1, Some(0)),
+        ir(IROp::Add, 1, Some(0)),
+        ir(IROp::Mov, 1, Some(1)),
+        ir(IROp::Kill, 0, None),
+        ir(IROp::Return, 1, None),
+        ir(IROp::Kill, 1, None),
+    ])];
+    optimize(&mut fns);
+    verify_kills(&fns[0]);
+
+    assert_eq!(
+        ops(&fns[0]),
+        vec![
+            "MOV r0, 3",
+            "MOV r1, 0",
+            "MOV r1, r0",
+            "KILL r0",
+            "RET r1",
+            "KILL r1"
+        ]
+    );
+}
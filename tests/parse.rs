@@ -0,0 +1,279 @@
+#[macro_use]
+extern crate lazy_static;
+extern crate r9cc;
+
+use r9cc::parse::{parse, Node, NodeType};
+use r9cc::preprocess::Preprocessor;
+use r9cc::token::{catch_error, take_warnings, tokenize_str};
+use r9cc::{Ctype, TokenType};
+
+use std::sync::Mutex;
+
+// Warnings are collected in a global, so tests must not run
+// concurrently.
+lazy_static! {
+    static ref LOCK: Mutex<()> = Mutex::new(());
+}
+
+// Parses `int f(int a, int b, int c) { return <expr>; }` and returns the
+// returned expression along with the warnings.
+fn parse_expr(expr: &str) -> (Node, Vec<String>) {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    take_warnings();
+    let src = format!("int f(int a, int b, int c) {{ return {}; }}", expr);
+    let tokens = tokenize_str("test.c".into(), src, &mut Preprocessor::new());
+    let nodes = parse(&tokens);
+
+    let body = match nodes[0].op {
+        NodeType::Func(_, _, ref body, _) => body,
+        ref op => panic!("{:?}", op),
+    };
+    let stmt = match body.op {
+        NodeType::CompStmt(ref stmts) => &stmts[0],
+        ref op => panic!("{:?}", op),
+    };
+    match stmt.op {
+        NodeType::Return(ref expr) => ((**expr).clone(), take_warnings()),
+        ref op => panic!("{:?}", op),
+    }
+}
+
+fn is_ident(node: &Node, name: &str) -> bool {
+    match node.op {
+        NodeType::Ident(ref s) => s == name,
+        _ => false,
+    }
+}
+
+// Renders the binary operators of an expression with explicit
+// parentheses, e.g. "((a Minus b) Minus c)".
+fn shape(node: &Node) -> String {
+    match node.op {
+        NodeType::BinOp(ref op, ref lhs, ref rhs) => {
+            format!("({} {:?} {})", shape(lhs), op, shape(rhs))
+        }
+        NodeType::Ident(ref name) => name.clone(),
+        ref op => panic!("{:?}", op),
+    }
+}
+
+#[test]
+fn chained_comparison() {
+    let (node, warnings) = parse_expr("a < b < c");
+
+    // (a < b) < c
+    match node.op {
+        NodeType::BinOp(TokenType::LeftAngleBracket, ref lhs, ref rhs) => {
+            match lhs.op {
+                NodeType::BinOp(TokenType::LeftAngleBracket, ref a, ref b) => {
+                    assert!(is_ident(a, "a"));
+                    assert!(is_ident(b, "b"));
+                }
+                ref op => panic!("{:?}", op),
+            }
+            assert!(is_ident(rhs, "c"));
+        }
+        ref op => panic!("{:?}", op),
+    }
+
+    assert_eq!(warnings.len(), 1);
+    assert!(
+        warnings[0].starts_with("test.c:1: warning: comparisons like"),
+        "{}",
+        warnings[0]
+    );
+}
+
+#[test]
+fn chained_comparison_suggests_fix() {
+    let (_, warnings) = parse_expr("a < b < c");
+    assert_eq!(warnings.len(), 1);
+    assert!(
+        warnings[0].ends_with("; did you mean (a < b) && (b < c)?"),
+        "{}",
+        warnings[0]
+    );
+
+    // The operands are quoted as written.
+    let (_, warnings) = parse_expr("a >= b+1 > c*2");
+    assert!(
+        warnings[0].ends_with("; did you mean (a >= b+1) && (b+1 > c*2)?"),
+        "{}",
+        warnings[0]
+    );
+}
+
+#[test]
+fn parenthesized_comparison() {
+    let (_, warnings) = parse_expr("(a < b) < c");
+    assert!(warnings.is_empty());
+    let (_, warnings) = parse_expr("a < b && b < c");
+    assert!(warnings.is_empty());
+}
+
+#[test]
+fn pointer_binds_to_declarator() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let tokens = tokenize_str(
+        "test.c".into(),
+        "int f() { int *p, q; return 0; }".into(),
+        &mut Preprocessor::new(),
+    );
+    let nodes = parse(&tokens);
+
+    let stmts = match nodes[0].op {
+        NodeType::Func(_, _, ref body, _) => match body.op {
+            NodeType::CompStmt(ref stmts) => stmts.clone(),
+            ref op => panic!("{:?}", op),
+        },
+        ref op => panic!("{:?}", op),
+    };
+    let vars = match stmts[0].op {
+        NodeType::VecStmt(ref vars) => vars.clone(),
+        ref op => panic!("{:?}", op),
+    };
+    assert_eq!(vars.len(), 2);
+
+    match (&vars[0].op, &vars[0].ty.ty) {
+        (NodeType::Vardef(name, _, _), Ctype::Ptr(to)) => {
+            assert_eq!(name, "p");
+            match to.ty {
+                Ctype::Int => (),
+                ref ty => panic!("{:?}", ty),
+            }
+        }
+        other => panic!("{:?}", other),
+    }
+    assert_eq!(vars[0].ty.size, 8);
+
+    match (&vars[1].op, &vars[1].ty.ty) {
+        (NodeType::Vardef(name, _, _), Ctype::Int) => assert_eq!(name, "q"),
+        other => panic!("{:?}", other),
+    }
+    assert_eq!(vars[1].ty.size, 4);
+}
+
+#[test]
+fn binary_precedence_and_associativity() {
+    let cases = [
+        ("a - b - c", "((a Minus b) Minus c)"),
+        ("a / b * c", "((a Div b) Mul c)"),
+        ("a + b * c << a", "((a Plus (b Mul c)) SHL a)"),
+        ("a << b < c", "((a SHL b) LeftAngleBracket c)"),
+        ("a == b & c", "((a EQ b) And c)"),
+        ("a & b ^ c | a", "(((a And b) Hat c) VerticalBar a)"),
+        ("a | b && c || a", "(((a VerticalBar b) Logand c) Logor a)"),
+        ("a || b && c", "(a Logor (b Logand c))"),
+        ("a != b == c", "((a NE b) EQ c)"),
+        ("a + b > c", "(c LeftAngleBracket (a Plus b))"),
+    ];
+    for &(expr, expected) in &cases {
+        let (node, _) = parse_expr(expr);
+        assert_eq!(shape(&node), expected, "{}", expr);
+    }
+}
+
+#[test]
+fn nested_conditional_is_right_associative() {
+    let (node, _) = parse_expr("a ? b : c ? a : b");
+
+    // a ? b : (c ? a : b)
+    match node.op {
+        NodeType::Ternary(ref cond, ref then, ref els) => {
+            assert!(is_ident(cond, "a"));
+            assert!(is_ident(then, "b"));
+            match els.op {
+                NodeType::Ternary(ref cond, ref then, ref els) => {
+                    assert!(is_ident(cond, "c"));
+                    assert!(is_ident(then, "a"));
+                    assert!(is_ident(els, "b"));
+                }
+                ref op => panic!("{:?}", op),
+            }
+        }
+        ref op => panic!("{:?}", op),
+    }
+}
+
+#[test]
+fn conditional_binds_tighter_than_assignment() {
+    let (node, _) = parse_expr("a = b ? c : a");
+
+    // a = (b ? c : a)
+    match node.op {
+        NodeType::BinOp(TokenType::Equal, ref lhs, ref rhs) => {
+            assert!(is_ident(lhs, "a"));
+            match rhs.op {
+                NodeType::Ternary(ref cond, ref then, ref els) => {
+                    assert!(is_ident(cond, "b"));
+                    assert!(is_ident(then, "c"));
+                    assert!(is_ident(els, "a"));
+                }
+                ref op => panic!("{:?}", op),
+            }
+        }
+        ref op => panic!("{:?}", op),
+    }
+}
+
+#[test]
+fn restrict_pointer() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let tokens = tokenize_str(
+        "test.c".into(),
+        "int f(int * restrict p) { int * restrict q; int *r; return 0; }".into(),
+        &mut Preprocessor::new(),
+    );
+    let nodes = parse(&tokens);
+
+    let (params, stmts) = match nodes[0].op {
+        NodeType::Func(_, ref params, ref body, _) => match body.op {
+            NodeType::CompStmt(ref stmts) => (params.clone(), stmts.clone()),
+            ref op => panic!("{:?}", op),
+        },
+        ref op => panic!("{:?}", op),
+    };
+    assert!(params[0].ty.restrict);
+    assert!(stmts[0].ty.restrict);
+    assert!(!stmts[1].ty.restrict);
+    match stmts[0].ty.ty {
+        Ctype::Ptr(ref to) => assert!(!to.restrict),
+        ref ty => panic!("{:?}", ty),
+    }
+}
+
+#[test]
+fn implicit_int_return() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let tokens = tokenize_str(
+        "test.c".into(),
+        "main() { return 0; }".into(),
+        &mut Preprocessor::new(),
+    );
+    let nodes = parse(&tokens);
+
+    match (&nodes[0].op, &nodes[0].ty.ty) {
+        (NodeType::Func(name, _, _, _), Ctype::Func(ret)) => {
+            assert_eq!(name, "main");
+            match ret.ty {
+                Ctype::Int => (),
+                ref ty => panic!("{:?}", ty),
+            }
+        }
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn excess_array_initializers() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let parse_src = |src: &str| {
+        let tokens = tokenize_str("test.c".into(), src.into(), &mut Preprocessor::new());
+        catch_error(|| parse(&tokens))
+    };
+
+    assert!(parse_src("int f() { int x[3] = {1, 2, 3}; return 0; }").is_ok());
+
+    let err = parse_src("int f() { int x[2] = {1, 2, 3}; return 0; }").unwrap_err();
+    assert_eq!(err.message, "excess elements in array initializer");
+}
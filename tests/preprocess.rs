@@ -0,0 +1,77 @@
+extern crate r9cc;
+
+use r9cc::preprocess::Preprocessor;
+use r9cc::token::tokenize_str;
+use r9cc::TokenType;
+
+fn token_types(src: &str) -> Vec<TokenType> {
+    tokenize_str("test.c".into(), src.into(), &mut Preprocessor::new())
+        .into_iter()
+        .map(|t| t.ty)
+        .collect()
+}
+
+#[test]
+fn if_0_is_skipped() {
+    let src = "int\n#if 0\nthis is not C\n#if 1\nnor this\n#endif\n#endif\nx;\n";
+    assert_eq!(
+        token_types(src),
+        vec![
+            TokenType::Int,
+            TokenType::Ident("x".into()),
+            TokenType::Semicolon,
+        ]
+    );
+}
+
+#[test]
+fn if_1_is_kept() {
+    let src = "#define ON 1\n#if ON\nint\n#if 0\nchar\n#endif\n#endif\nx;\n";
+    assert_eq!(
+        token_types(src),
+        vec![
+            TokenType::Int,
+            TokenType::Ident("x".into()),
+            TokenType::Semicolon,
+        ]
+    );
+}
+
+#[test]
+#[should_panic(expected = "unterminated #if")]
+fn unterminated_if() {
+    token_types("#if 0\nint x;\n");
+}
+
+#[test]
+fn funclike_macro_is_textual() {
+    // No parentheses are added around the expansion or the arguments.
+    let src = "#define ADD(a,b) a+b\nADD(1,2)*3\n";
+    assert_eq!(
+        token_types(src),
+        vec![
+            TokenType::Num(1),
+            TokenType::Plus,
+            TokenType::Num(2),
+            TokenType::Mul,
+            TokenType::Num(3),
+        ]
+    );
+
+    let src = "#define SQ(x) ((x)*(x))\nSQ(1+2)\n";
+    assert_eq!(token_types(src), token_types("((1+2)*(1+2))"));
+}
+
+#[test]
+#[should_panic(expected = "ADD: 2 arguments expected, but got 3")]
+fn funclike_macro_argument_count() {
+    token_types("#define ADD(a,b) a+b\nADD(1,2,3)\n");
+}
+
+#[test]
+fn stringized_argument_has_nul() {
+    assert_eq!(
+        token_types("#define S(x) #x\nS(abc)\n"),
+        vec![TokenType::Str("abc".into(), 4)]
+    );
+}
@@ -0,0 +1,54 @@
+#[macro_use]
+extern crate lazy_static;
+extern crate r9cc;
+
+mod common;
+
+use common::{function, ir};
+
+use r9cc::gen_ir::IROp;
+use r9cc::regalloc::verify_kills;
+
+#[test]
+fn well_formed_kills() {
+    verify_kills(&function(vec![
+        ir(IROp::Imm, 0, Some(1)),
+        ir(IROp::Imm, 1, Some(2)),
+        ir(IROp::Add, 0, Some(1)),
+        ir(IROp::Kill, 1, None),
+        ir(IROp::Return, 0, None),
+        ir(IROp::Kill, 0, None),
+    ]));
+}
+
+#[test]
+#[should_panic(expected = "f(): r1 is used after it is killed")]
+fn use_after_kill() {
+    verify_kills(&function(vec![
+        ir(IROp::Imm, 0, Some(1)),
+        ir(IROp::Imm, 1, Some(2)),
+        ir(IROp::Kill, 1, None),
+        ir(IROp::Add, 0, Some(1)),
+        ir(IROp::Return, 0, None),
+        ir(IROp::Kill, 0, None),
+    ]));
+}
+
+#[test]
+#[should_panic(expected = "f(): r0 is killed twice")]
+fn double_kill() {
+    verify_kills(&function(vec![
+        ir(IROp::Imm, 0, Some(1)),
+        ir(IROp::Kill, 0, None),
+        ir(IROp::Kill, 0, None),
+    ]));
+}
+
+#[test]
+#[should_panic(expected = "f(): r0 is never killed")]
+fn missing_kill() {
+    verify_kills(&function(vec![
+        ir(IROp::Imm, 0, Some(1)),
+        ir(IROp::Return, 0, None),
+    ]));
+}
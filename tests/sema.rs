@@ -0,0 +1,50 @@
+#[macro_use]
+extern crate lazy_static;
+extern crate r9cc;
+
+mod common;
+
+use common::LOCK;
+
+use r9cc::parse::{parse, Node, NodeType};
+use r9cc::preprocess::Preprocessor;
+use r9cc::sema::{check_lval, sema};
+use r9cc::token::{catch_error, tokenize_str};
+use r9cc::{CompileError, TokenType};
+
+fn num(val: i32) -> Box<Node> {
+    Box::new(Node::new_int(val))
+}
+
+#[test]
+fn not_an_lvalue() {
+    let cond = Node::new(NodeType::Ternary(num(1), num(2), num(3)));
+    assert_eq!(
+        check_lval(&cond),
+        Err(CompileError::NotAnLvalue("conditional expression".into()))
+    );
+
+    let call = Node::new(NodeType::Call("f".into(), vec![]));
+    assert_eq!(
+        check_lval(&call),
+        Err(CompileError::NotAnLvalue("call to f()".into()))
+    );
+
+    let sum = Node::new(NodeType::BinOp(TokenType::Plus, num(1), num(2)));
+    assert_eq!(
+        check_lval(&sum),
+        Err(CompileError::NotAnLvalue("binary Plus expression".into()))
+    );
+
+    let deref = Node::new(NodeType::Deref(num(0)));
+    assert_eq!(check_lval(&deref), Ok(()));
+}
+
+#[test]
+fn assignment_to_conditional() {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let src = "int main() { int x; int y; (1 ? x : y) = 3; return x; }";
+    let tokens = tokenize_str("test.c".into(), src.into(), &mut Preprocessor::new());
+    let err = catch_error(|| sema(parse(&tokens))).unwrap_err();
+    assert_eq!(err.message, "not an lvalue: conditional expression");
+}
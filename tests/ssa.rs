@@ -0,0 +1,233 @@
+#[macro_use]
+extern crate lazy_static;
+extern crate r9cc;
+
+mod common;
+
+use common::{function, ir, LOCK};
+
+use r9cc::gen_ir::{gen_ir, Function, IROp, IR};
+use r9cc::interp::run;
+use r9cc::opt::optimize;
+use r9cc::parse::parse;
+use r9cc::preprocess::Preprocessor;
+use r9cc::regalloc::{alloc_regs, verify_kills};
+use r9cc::sema::sema;
+use r9cc::ssa::{from_ssa, to_ssa};
+use r9cc::token::tokenize_str;
+
+fn ops(f: &Function) -> Vec<String> {
+    f.ir.iter()
+        .map(|ir| ir.to_string().trim().to_string())
+        .collect()
+}
+
+fn compile(src: &str) -> Vec<Function> {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let tokens = tokenize_str("test.c".into(), src.into(), &mut Preprocessor::new());
+    let (nodes, _) = sema(parse(&tokens));
+    let mut fns = gen_ir(nodes);
+    optimize(&mut fns);
+    fns
+}
+
+#[test]
+fn phi_at_merge_of_if_else() {
+    // if (r0) r1 = 1; else r1 = 2; return r1;
+    let mut f = function(vec![
+        ir(IROp::Imm, 0, Some(1)),
+        ir(IROp::Unless, 0, Some(1)),
+        ir(IROp::Kill, 0, None),
+        ir(IROp::Imm, 1, Some(1)),
+        ir(IROp::Jmp, 2, None),
+        ir(IROp::Label, 1, None),
+        ir(IROp::Imm, 1, Some(2)),
+        ir(IROp::Label, 2, None),
+        ir(IROp::Return, 1, None),
+        ir(IROp::Kill, 1, None),
+    ]);
+    to_ssa(&mut f);
+    assert_eq!(
+        ops(&f),
+        vec![
+            "MOV r0, 1",
+            "UNLESS r0, .L1",
+            "KILL r0",
+            "MOV r2, 1",
+            "JMP .L2",
+            ".L1:",
+            "MOV r3, 2",
+            ".L2:",
+            "PHI r4, [r2, B1], [r3, B2]",
+            "RET r4",
+        ]
+    );
+}
+
+#[test]
+fn two_address_update_copies_incoming_value() {
+    // r1 = 1; if (r0) r1 += 1; return r1;
+    let mut f = function(vec![
+        ir(IROp::Imm, 0, Some(1)),
+        ir(IROp::Imm, 1, Some(1)),
+        ir(IROp::Unless, 0, Some(1)),
+        ir(IROp::AddImm, 1, Some(1)),
+        ir(IROp::Label, 1, None),
+        ir(IROp::Kill, 0, None),
+        ir(IROp::Return, 1, None),
+        ir(IROp::Kill, 1, None),
+    ]);
+    to_ssa(&mut f);
+    assert_eq!(
+        ops(&f),
+        vec![
+            "MOV r0, 1",
+            "MOV r2, 1",
+            "UNLESS r0, .L1",
+            "MOV r3, r2",
+            "ADD r3, 1",
+            ".L1:",
+            "PHI r4, [r2, B0], [r3, B1]",
+            "KILL r0",
+            "RET r4",
+        ]
+    );
+}
+
+#[test]
+fn conditional_gets_phi_and_keeps_result() {
+    let mut fns = compile(
+        "int g(int x) { return x + 1; }
+         int f(int c, int a) { return c ? g(a) : a * 2; }",
+    );
+    let before: Vec<i64> = vec![run(&fns, "f", &[1, 5]), run(&fns, "f", &[0, 5])];
+    assert_eq!(before, vec![6, 10]);
+
+    for f in &mut fns {
+        to_ssa(f);
+    }
+    let f = fns.iter().find(|f| f.name == "f").unwrap();
+    let phis: Vec<&IR> =
+        f.ir.iter()
+            .filter(|ir| match ir.op {
+                IROp::Phi(ref args) => args.len() == 2,
+                _ => false,
+            })
+            .collect();
+    assert_eq!(phis.len(), 1, "{:?}", ops(f));
+    assert_eq!(run(&fns, "f", &[1, 5]), 6);
+    assert_eq!(run(&fns, "f", &[0, 5]), 10);
+}
+
+#[test]
+fn phi_becomes_copies_in_predecessors() {
+    let mut f = function(vec![
+        ir(IROp::Imm, 0, Some(1)),
+        ir(IROp::Unless, 0, Some(1)),
+        ir(IROp::Kill, 0, None),
+        ir(IROp::Imm, 1, Some(1)),
+        ir(IROp::Jmp, 2, None),
+        ir(IROp::Label, 1, None),
+        ir(IROp::Imm, 1, Some(2)),
+        ir(IROp::Label, 2, None),
+        ir(IROp::Return, 1, None),
+        ir(IROp::Kill, 1, None),
+    ]);
+    to_ssa(&mut f);
+    from_ssa(&mut f);
+    assert_eq!(
+        ops(&f),
+        vec![
+            "MOV r0, 1",
+            "UNLESS r0, .L1",
+            "KILL r0",
+            "MOV r2, 1",
+            "MOV r4, r2",
+            "KILL r2",
+            "JMP .L2",
+            ".L1:",
+            "MOV r3, 2",
+            "MOV r4, r3",
+            "KILL r3",
+            ".L2:",
+            "RET r4",
+            "KILL r4",
+        ]
+    );
+    verify_kills(&f);
+}
+
+#[test]
+fn swapping_phis_go_through_scratch_register() {
+    // a = 1; b = 2; for (n = 3; n; n--) { a, b = b, a; } return a * 10 + b;
+    let phi = |dst, args| ir(IROp::Phi(args), dst, None);
+    let f = function(vec![
+        ir(IROp::Imm, 0, Some(1)),
+        ir(IROp::Imm, 1, Some(2)),
+        ir(IROp::Imm, 2, Some(3)),
+        ir(IROp::Label, 1, None),
+        phi(3, vec![(0, 0), (2, 4)]),
+        phi(4, vec![(0, 1), (2, 3)]),
+        phi(5, vec![(0, 2), (2, 6)]),
+        ir(IROp::Unless, 5, Some(2)),
+        ir(IROp::Mov, 6, Some(5)),
+        ir(IROp::SubImm, 6, Some(1)),
+        ir(IROp::Jmp, 1, None),
+        ir(IROp::Label, 2, None),
+        ir(IROp::Mov, 7, Some(3)),
+        ir(IROp::MulImm, 7, Some(10)),
+        ir(IROp::Add, 7, Some(4)),
+        ir(IROp::Return, 7, None),
+    ]);
+    let mut fns = vec![f];
+    assert_eq!(run(&fns, "f", &[]), 21);
+
+    from_ssa(&mut fns[0]);
+    let code: Vec<String> = ops(&fns[0])
+        .into_iter()
+        .filter(|s| !s.starts_with("KILL"))
+        .collect();
+    let back_edge = code.iter().position(|s| s == "SUB r6, 1").unwrap();
+    assert_eq!(
+        code[back_edge + 1..back_edge + 5].to_vec(),
+        vec!["MOV r5, r6", "MOV r8, r3", "MOV r3, r4", "MOV r4, r8"],
+        "{:?}",
+        code
+    );
+    assert_eq!(run(&fns, "f", &[]), 21);
+
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    alloc_regs(&mut fns);
+    assert_eq!(run(&fns, "f", &[]), 21);
+}
+
+#[test]
+fn round_trip_keeps_result() {
+    let src = "int g(int x) { return x + 1; }
+               int f(int c, int n) {
+                 int s = 0;
+                 int i;
+                 for (i = 0; i < n; i++)
+                   s = s + (c ? g(i) : i * 2) + (i && c || n < 2);
+                 return s;
+               }";
+    let args = [[0, 0], [0, 4], [1, 1], [1, 5], [2, 3]];
+    let mut fns = compile(src);
+    let before: Vec<i64> = args.iter().map(|a| run(&fns, "f", a)).collect();
+
+    for f in &mut fns {
+        to_ssa(f);
+        from_ssa(f);
+        assert!(f.ir.iter().all(|ir| match ir.op {
+            IROp::Phi(_) => false,
+            _ => true,
+        }));
+    }
+    let after: Vec<i64> = args.iter().map(|a| run(&fns, "f", a)).collect();
+    assert_eq!(after, before);
+
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    alloc_regs(&mut fns);
+    let allocated: Vec<i64> = args.iter().map(|a| run(&fns, "f", a)).collect();
+    assert_eq!(allocated, before);
+}
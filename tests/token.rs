@@ -0,0 +1,134 @@
+extern crate r9cc;
+
+use r9cc::preprocess::Preprocessor;
+use r9cc::token::Token;
+use r9cc::token::{catch_error, tokenize_str, TokenStream};
+use r9cc::TokenType;
+
+fn tokens(src: &str) -> Vec<Token> {
+    tokenize_str("test.c".into(), src.into(), &mut Preprocessor::new())
+}
+
+fn token_types(src: &str) -> Vec<TokenType> {
+    tokens(src).into_iter().map(|t| t.ty).collect()
+}
+
+#[test]
+fn int_max() {
+    assert_eq!(
+        token_types("return 2147483647;"),
+        vec![
+            TokenType::Return,
+            TokenType::Num(2147483647),
+            TokenType::Semicolon,
+        ]
+    );
+}
+
+#[test]
+#[should_panic(expected = "integer constant is too large")]
+fn int_max_plus_one() {
+    token_types("return 2147483648;");
+}
+
+#[test]
+#[should_panic(expected = "integer constant is too large")]
+fn long_decimal() {
+    token_types("return 99999999999999999999999;");
+}
+
+#[test]
+fn hexadecimal_uses_all_bits() {
+    assert_eq!(token_types("0xffffffff"), vec![TokenType::Num(-1)]);
+    assert_eq!(token_types("037777777777"), vec![TokenType::Num(-1)]);
+    assert!(tokens("0x80000000")[0].unsigned);
+    assert!(!tokens("0x7fffffff")[0].unsigned);
+}
+
+#[test]
+#[should_panic(expected = "integer constant is too large")]
+fn hexadecimal_too_large() {
+    token_types("0x100000000");
+}
+
+#[test]
+fn integer_suffixes() {
+    let ts = tokens("10L 5U 3UL 7lu 1ll 4294967295u");
+    let flags: Vec<_> = ts.iter().map(|t| (t.unsigned, t.long)).collect();
+    assert_eq!(
+        flags,
+        vec![
+            (false, true),
+            (true, false),
+            (true, true),
+            (true, true),
+            (false, true),
+            (true, false),
+        ]
+    );
+    assert_eq!(ts[2].tokstr(), "3UL");
+    assert_eq!(ts[5].ty, TokenType::Num(-1));
+}
+
+#[test]
+fn stream() {
+    let tokens = tokens("f(1)");
+    let mut ts = TokenStream::new(&tokens);
+    assert_eq!(ts.peek().ty, TokenType::Ident("f".into()));
+    assert_eq!(ts.peek_nth(1).ty, TokenType::LeftParen);
+    assert!(!ts.consume_if(TokenType::LeftParen));
+    ts.advance();
+    assert!(ts.consume_if(TokenType::LeftParen));
+    assert_eq!(ts.expect(TokenType::Num(1)).tokstr(), "1");
+    assert_eq!(ts.next().map(|t| &t.ty), Some(&TokenType::RightParen));
+    assert!(ts.at_end());
+    assert!(ts.next().is_none());
+}
+
+#[test]
+fn expect_mismatch() {
+    let tokens = tokens("int main() {\n  return f(1;\n}");
+    let mut ts = TokenStream::new(&tokens);
+    for _ in 0..9 {
+        ts.advance();
+    }
+    let err = catch_error(|| ts.expect(TokenType::RightParen)).unwrap_err();
+    assert_eq!(err.message, "expected ')', found ';'");
+    let span = err.span.unwrap();
+    assert_eq!(span.line, 2);
+    assert_eq!((span.start, span.end), (25, 26));
+}
+
+#[test]
+fn expect_at_end_of_input() {
+    let tokens = tokens("return 0");
+    let mut ts = TokenStream::new(&tokens);
+    ts.advance();
+    ts.advance();
+    let err = catch_error(|| ts.expect(TokenType::Semicolon)).unwrap_err();
+    assert_eq!(err.message, "unexpected end of input");
+    assert_eq!(err.span.map(|s| s.line), Some(1));
+}
+
+#[test]
+fn line_continuation() {
+    assert_eq!(token_types("1 + \\\n2"), token_types("1 + 2"));
+    assert_eq!(token_types("1 +\\\n\\\n 2"), token_types("1 + 2"));
+    assert_eq!(
+        token_types("\"ab\\\ncd\""),
+        vec![TokenType::Str("abcd".into(), 5)]
+    );
+
+    // The spliced lines still count, so later tokens keep their line.
+    let t = tokens("1 + \\\n2\n;");
+    assert_eq!(t[3].ty, TokenType::Semicolon);
+    assert_eq!(t[3].span().line, 3);
+}
+
+#[test]
+fn string_length_counts_escapes_once() {
+    assert_eq!(
+        token_types("\"a\\tb\\n\""),
+        vec![TokenType::Str("a\tb\n".into(), 5)]
+    );
+}
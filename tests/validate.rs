@@ -0,0 +1,66 @@
+#[macro_use]
+extern crate lazy_static;
+extern crate r9cc;
+
+mod common;
+
+use common::{function, ir};
+
+use r9cc::gen_ir::IROp;
+use r9cc::validate::validate;
+
+#[test]
+fn well_formed() {
+    let f = function(vec![
+        ir(IROp::Imm, 0, Some(1)),
+        ir(IROp::Unless, 0, Some(1)),
+        ir(IROp::Jmp, 2, None),
+        ir(IROp::Label, 1, None),
+        ir(IROp::Label, 2, None),
+        ir(IROp::Return, 0, None),
+        ir(IROp::Kill, 0, None),
+    ]);
+    assert_eq!(validate(&f), Ok(()));
+}
+
+#[test]
+fn dangling_label() {
+    let f = function(vec![
+        ir(IROp::Imm, 0, Some(1)),
+        ir(IROp::Unless, 0, Some(3)),
+        ir(IROp::Return, 0, None),
+        ir(IROp::Kill, 0, None),
+    ]);
+    let err = validate(&f).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "f(): jump to undefined label .L3:   UNLESS r0, .L3"
+    );
+}
+
+#[test]
+fn use_before_def() {
+    let f = function(vec![
+        ir(IROp::Imm, 0, Some(1)),
+        ir(IROp::Add, 0, Some(1)),
+        ir(IROp::Return, 0, None),
+    ]);
+    let err = validate(&f).unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("f(): r1 is used before it is defined"));
+}
+
+#[test]
+fn killed_call_argument() {
+    let f = function(vec![
+        ir(IROp::Imm, 0, Some(1)),
+        ir(IROp::Kill, 0, None),
+        ir(IROp::Call("g".into(), vec![0], vec![]), 1, None),
+        ir(IROp::Return, 1, None),
+    ]);
+    let err = validate(&f).unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("f(): call argument r0 is not live"));
+}